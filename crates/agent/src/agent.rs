@@ -627,7 +627,8 @@ impl NativeAgent {
         id: acp::SessionId,
         cx: &mut Context<Self>,
     ) -> Task<Result<Entity<Thread>>> {
-        let database_future = ThreadsDatabase::connect(cx);
+        let database_future =
+            ThreadsDatabase::connect_scoped(self.history.read(cx).database_scope().clone(), cx);
         cx.spawn(async move |this, cx| {
             let database = database_future.await.map_err(|err| anyhow!(err))?;
             let db_thread = database
@@ -704,7 +705,8 @@ impl NativeAgent {
             return;
         }
 
-        let database_future = ThreadsDatabase::connect(cx);
+        let database_future =
+            ThreadsDatabase::connect_scoped(self.history.read(cx).database_scope().clone(), cx);
         let (id, db_thread) =
             thread.update(cx, |thread, cx| (thread.id().clone(), thread.to_db(cx)));
         let Some(session) = self.sessions.get_mut(&id) else {
@@ -801,10 +803,14 @@ impl NativeAgentConnection {
                                 tool_call,
                                 options,
                                 response,
+                                respect_always_allow_setting,
                             }) => {
                                 let outcome_task = acp_thread.update(cx, |thread, cx| {
                                     thread.request_tool_call_authorization(
-                                        tool_call, options, true, cx,
+                                        tool_call,
+                                        options,
+                                        respect_always_allow_setting,
+                                        cx,
                                     )
                                 })??;
                                 cx.background_spawn(async move {
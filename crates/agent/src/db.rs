@@ -1,22 +1,34 @@
 use crate::{AgentMessage, AgentMessageContent, UserMessage, UserMessageContent};
 use acp_thread::UserMessageId;
 use agent_client_protocol as acp;
-use agent_settings::{AgentProfileId, CompletionMode};
-use anyhow::{Result, anyhow};
-use chrono::{DateTime, Utc};
-use collections::{HashMap, IndexMap};
+use agent_settings::{AgentProfileId, AgentSettings, CompletionMode};
+use anyhow::{Context as _, Result, anyhow};
+use chrono::{DateTime, NaiveDate, Utc};
+use collections::{HashMap, HashSet, IndexMap};
 use futures::{FutureExt, future::Shared};
-use gpui::{BackgroundExecutor, Global, Task};
+use gpui::{BackgroundExecutor, Entity, Global, Task};
 use indoc::indoc;
-use parking_lot::Mutex;
+use parking_lot::{Mutex, MutexGuard};
+use project::Project;
 use serde::{Deserialize, Serialize};
+use settings::{Settings as _, ThreadStorage};
+use sha2::{Digest as _, Sha256};
 use sqlez::{
     bindable::{Bind, Column},
     connection::Connection,
     statement::Statement,
 };
-use std::sync::Arc;
+use std::{
+    fs::{File, TryLockError},
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{Read, Write},
+    mem,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 use ui::{App, SharedString};
+use util::ResultExt as _;
 use zed_env_vars::ZED_STATELESS;
 
 pub type DbMessage = crate::Message;
@@ -29,6 +41,9 @@ pub struct DbThreadMetadata {
     #[serde(alias = "summary")]
     pub title: SharedString,
     pub updated_at: DateTime<Utc>,
+    /// Pinned threads are listed before all other threads.
+    #[serde(default)]
+    pub pinned: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -50,6 +65,12 @@ pub struct DbThread {
     pub completion_mode: Option<CompletionMode>,
     #[serde(default)]
     pub profile: Option<AgentProfileId>,
+    /// The thread's tags, stored in the `thread_tags` table rather than the
+    /// thread blob. Saving a thread replaces its tags with these, unless they're
+    /// `None`, so that saving a thread loaded without its tags keeps the tags
+    /// added since.
+    #[serde(skip)]
+    pub tags: Option<Vec<SharedString>>,
 }
 
 impl DbThread {
@@ -70,6 +91,17 @@ impl DbThread {
         }
     }
 
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = format!("# {}\n\n", self.title);
+        for (ix, message) in self.messages.iter().enumerate() {
+            if ix > 0 {
+                markdown.push('\n');
+            }
+            markdown.push_str(&message.to_markdown());
+        }
+        markdown
+    }
+
     fn upgrade_from_agent_1(thread: crate::legacy_thread::SerializedThread) -> Result<Self> {
         let mut messages = Vec::new();
         let mut request_token_usage = HashMap::default();
@@ -209,16 +241,148 @@ impl DbThread {
             model: thread.model,
             completion_mode: thread.completion_mode,
             profile: thread.profile,
+            tags: None,
+        })
+    }
+}
+
+/// A [`DbThread`] in the versioned JSON format stored in threads.db.
+#[derive(Serialize)]
+struct SerializedThread {
+    #[serde(flatten)]
+    thread: DbThread,
+    version: &'static str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ThreadExportFormat {
+    /// A human-readable transcript of the conversation.
+    Markdown,
+    /// A [`ThreadsBundle`] that can be imported again.
+    Json,
+}
+
+/// A portable collection of threads, as written by [`ThreadsDatabase::export_all`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ThreadsBundle {
+    pub version: String,
+    pub exported_at: DateTime<Utc>,
+    pub threads: Vec<BundledThread>,
+}
+
+impl ThreadsBundle {
+    pub const VERSION: &'static str = "1";
+
+    fn new(threads: Vec<BundledThread>) -> Self {
+        Self {
+            version: Self::VERSION.to_string(),
+            exported_at: Utc::now(),
+            threads,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BundledThread {
+    pub id: acp::SessionId,
+    #[serde(default)]
+    pub tags: Vec<SharedString>,
+    /// The thread in the same versioned JSON format used by threads.db, so that
+    /// bundles from older versions go through the regular upgrade path on import.
+    pub thread: serde_json::Value,
+}
+
+impl BundledThread {
+    fn new(id: acp::SessionId, mut thread: DbThread) -> Result<Self> {
+        let tags = thread.tags.take().unwrap_or_default();
+        Ok(Self {
+            id,
+            tags,
+            thread: serde_json::to_value(SerializedThread {
+                thread,
+                version: DbThread::VERSION,
+            })?,
         })
     }
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadImportSummary {
+    pub imported: usize,
+    /// Threads that were not imported because their ID already exists.
+    pub skipped: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ThreadStorageUsage {
+    pub id: acp::SessionId,
+    pub title: SharedString,
+    /// Bytes stored for the thread: its blob, header, and appended messages.
+    pub stored_bytes: u64,
+    /// Bytes of thread JSON once the blob is decompressed.
+    pub uncompressed_bytes: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct StorageUsage {
+    /// Per-thread usage, largest first.
+    pub threads: Vec<ThreadStorageUsage>,
+    /// Size of the whole database file, including free pages and indices.
+    pub database_bytes: u64,
+}
+
+/// Cumulative token usage of the threads sharing `key`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenUsageSummary<K> {
+    pub key: K,
+    pub thread_count: usize,
+    pub token_usage: language_model::TokenUsage,
+}
+
+/// The fields of a thread needed for usage analytics, so that the messages of
+/// current threads are skipped instead of deserialized.
+#[derive(Deserialize)]
+struct ThreadTokenUsage {
+    #[serde(default)]
+    version: Option<String>,
+    updated_at: DateTime<Utc>,
+    #[serde(default)]
+    cumulative_token_usage: language_model::TokenUsage,
+    #[serde(default)]
+    model: Option<DbLanguageModel>,
+    #[serde(default)]
+    profile: Option<AgentProfileId>,
+}
+
+/// Outcome of [`ThreadsDatabase::train_compression_dictionary`], measured on the
+/// sampled threads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionDictionaryReport {
+    pub sample_count: usize,
+    pub dictionary_bytes: usize,
+    /// Compressed size of the samples without a dictionary.
+    pub baseline_bytes: u64,
+    /// Compressed size of the samples with the new dictionary.
+    pub dictionary_compressed_bytes: u64,
+}
+
+impl CompressionDictionaryReport {
+    pub fn saved_bytes(&self) -> u64 {
+        self.baseline_bytes
+            .saturating_sub(self.dictionary_compressed_bytes)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum DataType {
     #[serde(rename = "json")]
     Json,
     #[serde(rename = "zstd")]
     Zstd,
+    /// Zstd compressed with the dictionary referenced by the row's `dictionary_id`.
+    #[serde(rename = "zstd_dict")]
+    ZstdDict,
 }
 
 impl Bind for DataType {
@@ -226,6 +390,7 @@ impl Bind for DataType {
         let value = match self {
             DataType::Json => "json",
             DataType::Zstd => "zstd",
+            DataType::ZstdDict => "zstd_dict",
         };
         value.bind(statement, start_index)
     }
@@ -237,46 +402,326 @@ impl Column for DataType {
         let data_type = match value.as_str() {
             "json" => DataType::Json,
             "zstd" => DataType::Zstd,
+            "zstd_dict" => DataType::ZstdDict,
             _ => anyhow::bail!("Unknown data type: {}", value),
         };
         Ok((data_type, next_index))
     }
 }
 
-pub(crate) struct ThreadsDatabase {
+pub struct ThreadsDatabase {
     executor: BackgroundExecutor,
-    connection: Arc<Mutex<Connection>>,
+    /// Where scheduled backups are written. Only set for databases on disk.
+    backups_dir: Option<PathBuf>,
+    connection: Arc<WriteConnection>,
+    read_connections: Arc<ReadConnections>,
+    saved_messages: Arc<Mutex<HashMap<Arc<str>, SavedMessages>>>,
+}
+
+/// Returned for writes while another process owns writes to threads.db.
+#[derive(Debug, thiserror::Error)]
+#[error("threads are being saved by another Zed window, so they are read-only in this one")]
+pub struct ThreadsDatabaseReadOnly;
+
+/// Returned when opening threads.db read-only while another process, running an
+/// older version, owns writes and hasn't migrated it to this version's schema.
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "threads are being saved by an older version of Zed, so they can't be shown until it exits"
+)]
+pub struct ThreadsDatabaseOutdated;
+
+/// The connection used for writes. When threads.db lives on disk, only the process
+/// holding a lock on `threads.db.lock` writes to it, so concurrent windows don't fail
+/// with `database is locked` or overwrite each other's saves. Other processes open the
+/// database read-only and take over writing as soon as the lock is released.
+struct WriteConnection {
+    connection: Mutex<Connection>,
+    lock_path: Option<PathBuf>,
+    lock_file: Mutex<Option<File>>,
+    /// The most recently trained dictionary, used when compacting threads.
+    compression_dictionary: Mutex<Option<CompressionDictionary>>,
+}
+
+impl WriteConnection {
+    /// Locks the connection for writing, failing with [`ThreadsDatabaseReadOnly`]
+    /// if another process owns writes.
+    fn lock(&self) -> Result<MutexGuard<'_, Connection>> {
+        let connection = self.connection.lock();
+        if let Some(lock_path) = &self.lock_path {
+            let mut lock_file = self.lock_file.lock();
+            if lock_file.is_none() {
+                let file = try_lock_file(lock_path)?.ok_or(ThreadsDatabaseReadOnly)?;
+                // The previous owner may have been an older version, or trained
+                // a dictionary since this process started.
+                connection.exec(WRITE_CONNECTION_PRAGMAS)?()
+                    .map_err(|e| anyhow!("Failed to configure threads database: {}", e))?;
+                ThreadsDatabase::migrate(&connection)?;
+                *self.compression_dictionary.lock() =
+                    ThreadsDatabase::latest_dictionary_sync(&connection)?;
+                *lock_file = Some(file);
+            }
+        }
+        Ok(connection)
+    }
+
+    fn lock_for_read(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.lock_path.is_some() && self.lock_file.lock().is_none()
+    }
+}
+
+/// Returns `None` if another process holds the lock.
+fn try_lock_file(path: &Path) -> Result<Option<File>> {
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("opening {path:?}"))?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(file)),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(error)) => Err(error).with_context(|| format!("locking {path:?}")),
+    }
+}
+
+struct CompressionDictionary {
+    id: i64,
+    data: Arc<[u8]>,
+}
+
+const THREAD_COMPRESSION_LEVEL: i32 = 3;
+/// Training needs enough threads for shared structure to stand out.
+const MIN_DICTIONARY_SAMPLES: usize = 16;
+const MAX_DICTIONARY_SAMPLES: usize = 256;
+/// Only the start of each thread is sampled, which is where the JSON structure
+/// shared between threads is densest.
+const MAX_DICTIONARY_SAMPLE_BYTES: u64 = 64 * 1024;
+const MAX_DICTIONARY_BYTES: usize = 110 * 1024;
+
+const BACKUP_INTERVAL: Duration = Duration::from_secs(60 * 60 * 24);
+const MAX_BACKUPS: usize = 7;
+const BACKUP_FILE_PREFIX: &str = "threads-";
+const BACKUP_FILE_EXTENSION: &str = "db";
+
+/// How many read-only connections to open alongside the write connection
+/// when threads.db lives on disk.
+const READ_CONNECTION_COUNT: usize = 2;
+
+const WRITE_CONNECTION_PRAGMAS: &str = indoc! {"
+    PRAGMA journal_mode=WAL;
+    PRAGMA synchronous=NORMAL;
+    PRAGMA busy_timeout=5000;
+    PRAGMA query_only=FALSE;
+"};
+
+const READ_CONNECTION_PRAGMAS: &str = indoc! {"
+    PRAGMA busy_timeout=5000;
+    PRAGMA query_only=TRUE;
+"};
+
+/// Connections for queries that don't write. In WAL mode these can read
+/// while the write connection is saving, so listing and loading threads never
+/// waits behind a save. In-memory databases read through the write connection.
+struct ReadConnections {
+    connections: Vec<Mutex<Connection>>,
+    write_connection: Arc<WriteConnection>,
+}
+
+impl ReadConnections {
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        if let Some(connection) = self
+            .connections
+            .iter()
+            .find_map(|connection| connection.try_lock())
+        {
+            connection
+        } else if let Some(connection) = self.connections.first() {
+            connection.lock()
+        } else {
+            self.write_connection.lock_for_read()
+        }
+    }
+}
+
+const MIGRATION_DOMAIN: &str = "threads";
+
+/// Schema migrations for threads.db, applied in order by sqlez and recorded in its
+/// `migrations` table. Databases created before migrations were introduced already
+/// have the `threads` table, so the first step must not fail when it exists.
+const MIGRATIONS: &[&str] = &[
+    indoc! {"
+        CREATE TABLE IF NOT EXISTS threads (
+            id TEXT PRIMARY KEY,
+            summary TEXT NOT NULL,
+            updated_at TEXT NOT NULL,
+            data_type TEXT NOT NULL,
+            data BLOB NOT NULL
+        );
+    "},
+    // When set, `header` holds the latest thread JSON without its messages and takes
+    // precedence over everything but the messages stored in `data`.
+    indoc! {"
+        ALTER TABLE threads ADD COLUMN header TEXT;
+
+        CREATE TABLE thread_tags (
+            thread_id TEXT NOT NULL,
+            tag TEXT NOT NULL,
+            PRIMARY KEY (thread_id, tag)
+        );
+
+        CREATE TABLE thread_messages (
+            thread_id TEXT NOT NULL,
+            message_index INTEGER NOT NULL,
+            data TEXT NOT NULL,
+            PRIMARY KEY (thread_id, message_index)
+        );
+    "},
+    // Dictionaries are never deleted, since threads compressed with an older
+    // dictionary keep referencing it until they're compacted again.
+    indoc! {"
+        CREATE TABLE compression_dictionaries (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            data BLOB NOT NULL,
+            created_at TEXT NOT NULL
+        );
+        ALTER TABLE threads ADD COLUMN dictionary_id INTEGER;
+    "},
+    indoc! {"
+        ALTER TABLE threads ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;
+    "},
+];
+
+/// How many messages may accumulate in `thread_messages` before a save folds
+/// them back into the thread blob.
+/// How many of [`MIGRATIONS`] create tables and columns that versions from before
+/// migrations were introduced created directly, without recording a migration.
+const UNVERSIONED_MIGRATION_STEPS: usize = 3;
+
+const MESSAGE_COMPACTION_THRESHOLD: usize = 32;
+
+/// What this process last wrote for a thread, used to append only the
+/// messages that changed instead of rewriting the whole thread blob.
+struct SavedMessages {
+    /// Number of messages stored in the thread blob itself.
+    compacted_len: usize,
+    /// Hashes of the serialized messages, for both the blob and `thread_messages`.
+    hashes: Vec<u64>,
+}
+
+impl SavedMessages {
+    fn can_append(&self, hashes: &[u64]) -> bool {
+        let unchanged_prefix =
+            hashes.get(..self.compacted_len) == self.hashes.get(..self.compacted_len);
+        let pending = hashes.len().saturating_sub(self.compacted_len);
+        unchanged_prefix && pending <= MESSAGE_COMPACTION_THRESHOLD
+    }
+}
+
+/// Which threads a [`ThreadsDatabase`] holds.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub enum ThreadsDatabaseScope {
+    /// Threads from every project.
+    #[default]
+    Global,
+    /// Threads of the project rooted at this directory.
+    Project(Arc<Path>),
+}
+
+impl ThreadsDatabaseScope {
+    /// The scope that `project`'s threads are stored in, according to the
+    /// `agent.thread_storage` setting.
+    pub fn for_project(project: &Entity<Project>, cx: &App) -> Self {
+        match AgentSettings::get_global(cx).thread_storage {
+            ThreadStorage::Global => Self::Global,
+            ThreadStorage::PerProject => project
+                .read(cx)
+                .visible_worktrees(cx)
+                .next()
+                .map_or(Self::Global, |worktree| {
+                    Self::Project(worktree.read(cx).abs_path())
+                }),
+        }
+    }
+
+    /// Names the project's database after a digest of its root, which stays
+    /// stable across releases, unlike `std`'s hashers.
+    fn project_key(&self) -> Option<String> {
+        match self {
+            Self::Global => None,
+            Self::Project(root) => {
+                let digest = Sha256::digest(root.to_string_lossy().as_bytes());
+                Some(digest.iter().map(|byte| format!("{byte:02x}")).collect())
+            }
+        }
+    }
 }
 
-struct GlobalThreadsDatabase(Shared<Task<Result<Arc<ThreadsDatabase>, Arc<anyhow::Error>>>>);
+#[derive(Default)]
+struct GlobalThreadsDatabases(
+    HashMap<ThreadsDatabaseScope, Shared<Task<Result<Arc<ThreadsDatabase>, Arc<anyhow::Error>>>>>,
+);
 
-impl Global for GlobalThreadsDatabase {}
+impl Global for GlobalThreadsDatabases {}
 
 impl ThreadsDatabase {
     pub fn connect(cx: &mut App) -> Shared<Task<Result<Arc<ThreadsDatabase>, Arc<anyhow::Error>>>> {
-        if cx.has_global::<GlobalThreadsDatabase>() {
-            return cx.global::<GlobalThreadsDatabase>().0.clone();
+        Self::connect_scoped(ThreadsDatabaseScope::Global, cx)
+    }
+
+    pub fn connect_scoped(
+        scope: ThreadsDatabaseScope,
+        cx: &mut App,
+    ) -> Shared<Task<Result<Arc<ThreadsDatabase>, Arc<anyhow::Error>>>> {
+        if let Some(task) = cx
+            .try_global::<GlobalThreadsDatabases>()
+            .and_then(|databases| databases.0.get(&scope))
+        {
+            return task.clone();
         }
         let executor = cx.background_executor().clone();
         let task = executor
             .spawn({
                 let executor = executor.clone();
+                let scope = scope.clone();
                 async move {
-                    match ThreadsDatabase::new(executor) {
-                        Ok(db) => Ok(Arc::new(db)),
+                    match ThreadsDatabase::new_scoped(executor, &scope) {
+                        Ok(db) => {
+                            let db = Arc::new(db);
+                            db.schedule_backups();
+                            Ok(db)
+                        }
                         Err(err) => Err(Arc::new(err)),
                     }
                 }
             })
             .shared();
 
-        cx.set_global(GlobalThreadsDatabase(task.clone()));
+        cx.default_global::<GlobalThreadsDatabases>()
+            .0
+            .insert(scope, task.clone());
         task
     }
 
     pub fn new(executor: BackgroundExecutor) -> Result<Self> {
+        Self::new_scoped(executor, &ThreadsDatabaseScope::Global)
+    }
+
+    pub fn new_scoped(executor: BackgroundExecutor, scope: &ThreadsDatabaseScope) -> Result<Self> {
+        let mut sqlite_path = None;
+        let mut backups_dir = None;
+        let mut lock_path = None;
+        let project_key = scope.project_key();
+        let memory_name_suffix = project_key
+            .as_ref()
+            .map(|key| format!("_{key}"))
+            .unwrap_or_default();
         let connection = if *ZED_STATELESS {
-            Connection::open_memory(Some("THREAD_FALLBACK_DB"))
+            Connection::open_memory(Some(&format!("THREAD_FALLBACK_DB{memory_name_suffix}")))
         } else if cfg!(any(feature = "test-support", test)) {
             // rust stores the name of the test on the current thread.
             // We use this to automatically create a database that will
@@ -285,90 +730,389 @@ impl ThreadsDatabase {
             let thread = std::thread::current();
             let test_name = thread.name();
             Connection::open_memory(Some(&format!(
-                "THREAD_FALLBACK_{}",
+                "THREAD_FALLBACK_{}{memory_name_suffix}",
                 test_name.unwrap_or_default()
             )))
         } else {
-            let threads_dir = paths::data_dir().join("threads");
+            let mut threads_dir = paths::data_dir().join("threads");
+            if let Some(project_key) = project_key {
+                threads_dir = threads_dir.join("projects").join(project_key);
+            }
             std::fs::create_dir_all(&threads_dir)?;
-            let sqlite_path = threads_dir.join("threads.db");
-            Connection::open_file(&sqlite_path.to_string_lossy())
+            let path = threads_dir
+                .join("threads.db")
+                .to_string_lossy()
+                .into_owned();
+            let connection = Connection::open_file(&path);
+            sqlite_path = Some(path);
+            backups_dir = Some(threads_dir.join("backups"));
+            lock_path = Some(threads_dir.join("threads.db.lock"));
+            connection
         };
 
-        connection.exec(indoc! {"
-            CREATE TABLE IF NOT EXISTS threads (
-                id TEXT PRIMARY KEY,
-                summary TEXT NOT NULL,
-                updated_at TEXT NOT NULL,
-                data_type TEXT NOT NULL,
-                data BLOB NOT NULL
-            )
-        "})?()
-        .map_err(|e| anyhow!("Failed to create threads table: {}", e))?;
+        let lock_path = lock_path.filter(|_| connection.persistent());
+        let lock_file = lock_path
+            .as_deref()
+            .map(try_lock_file)
+            .transpose()?
+            .flatten();
+        let compression_dictionary = if lock_path.is_some() && lock_file.is_none() {
+            log::warn!(
+                "threads database is being written by another process, opening it read-only"
+            );
+            connection.exec(READ_CONNECTION_PRAGMAS)?()
+                .map_err(|e| anyhow!("Failed to configure threads database: {}", e))?;
+            // The schema is migrated by the process that owns writes, which may
+            // be running an older version.
+            Self::ensure_migrated(&connection)?;
+            None
+        } else {
+            if connection.persistent() {
+                connection.exec(WRITE_CONNECTION_PRAGMAS)?()
+                    .map_err(|e| anyhow!("Failed to configure threads database: {}", e))?;
+            }
+            Self::migrate(&connection)?;
+            Self::latest_dictionary_sync(&connection)?
+        };
 
+        let mut read_connections = Vec::new();
+        if let Some(sqlite_path) = sqlite_path.filter(|_| connection.persistent()) {
+            for _ in 0..READ_CONNECTION_COUNT {
+                let read_connection = Connection::open_file(&sqlite_path);
+                read_connection.exec(READ_CONNECTION_PRAGMAS)?()
+                    .map_err(|e| anyhow!("Failed to configure threads database: {}", e))?;
+                read_connections.push(Mutex::new(read_connection));
+            }
+        }
+
+        let backups_dir = backups_dir.filter(|_| connection.persistent());
+        let connection = Arc::new(WriteConnection {
+            connection: Mutex::new(connection),
+            lock_path,
+            lock_file: Mutex::new(lock_file),
+            compression_dictionary: Mutex::new(compression_dictionary),
+        });
         let db = Self {
             executor,
-            connection: Arc::new(Mutex::new(connection)),
+            backups_dir,
+            read_connections: Arc::new(ReadConnections {
+                connections: read_connections,
+                write_connection: connection.clone(),
+            }),
+            connection,
+            saved_messages: Arc::new(Mutex::new(HashMap::default())),
         };
 
         Ok(db)
     }
 
+    /// Whether another process currently owns writes. Writes made while read-only
+    /// fail with [`ThreadsDatabaseReadOnly`] unless the other process has since exited.
+    pub fn is_read_only(&self) -> bool {
+        self.connection.is_read_only()
+    }
+
+    fn migrate(connection: &Connection) -> Result<()> {
+        connection
+            .with_savepoint("adopt_unversioned_threads_schema", || {
+                Self::adopt_unversioned_schema(connection)
+            })
+            .and_then(|()| connection.migrate(MIGRATION_DOMAIN, MIGRATIONS, |_, _, _| false))
+            .map_err(|e| anyhow!("Failed to migrate threads database: {}", e))
+    }
+
+    /// Databases created before migrations were introduced have the original
+    /// `threads` table and, depending on the version that created them, some of
+    /// the tables and columns of the first [`UNVERSIONED_MIGRATION_STEPS`], which
+    /// can't be added again. Adds whatever is missing and records those steps as
+    /// run, so that sqlez only runs the later ones.
+    fn adopt_unversioned_schema(connection: &Connection) -> Result<()> {
+        let table_exists = |table: &str| -> Result<bool> {
+            let count = connection.select_row_bound::<&str, usize>(indoc! {"
+                SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?
+            "})?(table)?;
+            Ok(count.unwrap_or_default() > 0)
+        };
+        if !table_exists("threads")? {
+            return Ok(());
+        }
+        if table_exists("migrations")? {
+            let completed_steps = connection.select_row_bound::<&str, usize>(indoc! {"
+                SELECT COUNT(*) FROM migrations WHERE domain = ?
+            "})?(MIGRATION_DOMAIN)?;
+            if completed_steps.unwrap_or_default() > 0 {
+                return Ok(());
+            }
+        }
+
+        let columns = connection.select::<String>(indoc! {"
+            SELECT name FROM pragma_table_info('threads')
+        "})?()?;
+        if !columns.iter().any(|column| column == "header") {
+            connection.exec("ALTER TABLE threads ADD COLUMN header TEXT")?()?;
+        }
+        if !columns.iter().any(|column| column == "dictionary_id") {
+            connection.exec("ALTER TABLE threads ADD COLUMN dictionary_id INTEGER")?()?;
+        }
+        connection.exec(indoc! {"
+            CREATE TABLE IF NOT EXISTS thread_tags (
+                thread_id TEXT NOT NULL,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (thread_id, tag)
+            )
+        "})?()?;
+        connection.exec(indoc! {"
+            CREATE TABLE IF NOT EXISTS thread_messages (
+                thread_id TEXT NOT NULL,
+                message_index INTEGER NOT NULL,
+                data TEXT NOT NULL,
+                PRIMARY KEY (thread_id, message_index)
+            )
+        "})?()?;
+        connection.exec(indoc! {"
+            CREATE TABLE IF NOT EXISTS compression_dictionaries (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                data BLOB NOT NULL,
+                created_at TEXT NOT NULL
+            )
+        "})?()?;
+
+        // The same table sqlez creates, which compares the recorded steps with
+        // `MIGRATIONS` after formatting both.
+        connection.exec(indoc! {"
+            CREATE TABLE IF NOT EXISTS migrations (
+                domain TEXT,
+                step INTEGER,
+                migration TEXT
+            )
+        "})?()?;
+        let mut record_step = connection.exec_bound::<(&str, usize, &str)>(indoc! {"
+            INSERT INTO migrations (domain, step, migration) VALUES (?, ?, ?)
+        "})?;
+        for (step, migration) in MIGRATIONS[..UNVERSIONED_MIGRATION_STEPS].iter().enumerate() {
+            record_step((MIGRATION_DOMAIN, step, *migration))?;
+        }
+        Ok(())
+    }
+
+    /// Fails with [`ThreadsDatabaseOutdated`] unless every migration has run.
+    fn ensure_migrated(connection: &Connection) -> Result<()> {
+        let completed_steps = connection
+            .select_row_bound::<&str, usize>(indoc! {"
+                SELECT COUNT(*) FROM migrations WHERE domain = ?
+            "})
+            .and_then(|mut count| count(MIGRATION_DOMAIN))
+            // Databases that were never migrated have no migrations table.
+            .unwrap_or_default()
+            .unwrap_or_default();
+        if completed_steps < MIGRATIONS.len() {
+            return Err(ThreadsDatabaseOutdated.into());
+        }
+        Ok(())
+    }
+
+    fn latest_dictionary_sync(connection: &Connection) -> Result<Option<CompressionDictionary>> {
+        let dictionary = connection.select_row::<(i64, Vec<u8>)>(indoc! {"
+            SELECT id, data FROM compression_dictionaries ORDER BY id DESC LIMIT 1
+        "})?()?;
+        Ok(dictionary.map(|(id, data)| CompressionDictionary {
+            id,
+            data: data.into(),
+        }))
+    }
+
+    fn dictionaries_sync(connection: &Connection) -> Result<HashMap<i64, Vec<u8>>> {
+        let mut select = connection.select::<(i64, Vec<u8>)>(indoc! {"
+            SELECT id, data FROM compression_dictionaries
+        "})?;
+        Ok(select()?.into_iter().collect())
+    }
+
+    fn store_dictionary_sync(connection: &Connection, dictionary: &[u8]) -> Result<i64> {
+        connection.select_row_bound::<(&[u8], String), i64>(indoc! {"
+            INSERT INTO compression_dictionaries (data, created_at) VALUES (?, ?) RETURNING id
+        "})?((dictionary, Utc::now().to_rfc3339()))?
+        .context("storing compression dictionary")
+    }
+
+    fn dictionary_sync(connection: &Connection, id: i64) -> Result<Vec<u8>> {
+        connection.select_row_bound::<i64, Vec<u8>>(indoc! {"
+            SELECT data FROM compression_dictionaries WHERE id = ?
+        "})?(id)?
+        .with_context(|| format!("missing compression dictionary {id}"))
+    }
+
+    /// Writes the thread, appending only new or changed messages to `thread_messages`
+    /// when the previously saved messages are still a prefix of the thread. Otherwise,
+    /// or once too many messages have been appended, the whole thread is compacted
+    /// back into a single blob.
     fn save_thread_sync(
-        connection: &Arc<Mutex<Connection>>,
+        write_connection: &WriteConnection,
+        saved_messages: &Mutex<HashMap<Arc<str>, SavedMessages>>,
         id: acp::SessionId,
         thread: DbThread,
     ) -> Result<()> {
-        const COMPRESSION_LEVEL: i32 = 3;
+        let connection = write_connection.lock()?;
+        Self::save_thread_locked(&connection, write_connection, saved_messages, id, thread)
+    }
 
-        #[derive(Serialize)]
-        struct SerializedThread {
-            #[serde(flatten)]
-            thread: DbThread,
-            version: &'static str,
-        }
+    /// Like [`Self::save_thread_sync`], for callers that already hold the write
+    /// connection's lock.
+    fn save_thread_locked(
+        connection: &Connection,
+        write_connection: &WriteConnection,
+        saved_messages: &Mutex<HashMap<Arc<str>, SavedMessages>>,
+        id: acp::SessionId,
+        mut thread: DbThread,
+    ) -> Result<()> {
+        let message_jsons = thread
+            .messages
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        let message_hashes = message_jsons
+            .iter()
+            .map(|json| {
+                let mut hasher = DefaultHasher::new();
+                json.hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect::<Vec<_>>();
 
         let title = thread.title.to_string();
         let updated_at = thread.updated_at.to_rfc3339();
-        let json_data = serde_json::to_string(&SerializedThread {
-            thread,
-            version: DbThread::VERSION,
-        })?;
+        let tags = thread.tags.take();
 
-        let connection = connection.lock();
+        let mut saved_messages = saved_messages.lock();
 
-        let compressed = zstd::encode_all(json_data.as_bytes(), COMPRESSION_LEVEL)?;
-        let data_type = DataType::Zstd;
-        let data = compressed;
+        let appendable = saved_messages
+            .get(&id.0)
+            .filter(|saved| saved.can_append(&message_hashes))
+            .map(|saved| (saved.compacted_len, saved.hashes.clone()));
 
-        let mut insert = connection.exec_bound::<(Arc<str>, String, String, DataType, Vec<u8>)>(indoc! {"
-            INSERT OR REPLACE INTO threads (id, summary, updated_at, data_type, data) VALUES (?, ?, ?, ?, ?)
-        "})?;
+        if let Some((compacted_len, previous_hashes)) = appendable {
+            thread.messages.clear();
+            let header = serde_json::to_string(&SerializedThread {
+                thread,
+                version: DbThread::VERSION,
+            })?;
 
-        insert((id.0, title, updated_at, data_type, data))?;
+            connection.with_savepoint("append_thread_messages", || {
+                let mut upsert_message = connection.exec_bound::<(Arc<str>, usize, String)>(indoc! {"
+                    INSERT OR REPLACE INTO thread_messages (thread_id, message_index, data) VALUES (?, ?, ?)
+                "})?;
+                for (index, (json, hash)) in message_jsons
+                    .into_iter()
+                    .zip(&message_hashes)
+                    .enumerate()
+                    .skip(compacted_len)
+                {
+                    if previous_hashes.get(index) != Some(hash) {
+                        upsert_message((id.0.clone(), index, json))?;
+                    }
+                }
+
+                let mut delete_stale_messages = connection.exec_bound::<(Arc<str>, usize)>(indoc! {"
+                    DELETE FROM thread_messages WHERE thread_id = ? AND message_index >= ?
+                "})?;
+                delete_stale_messages((id.0.clone(), message_hashes.len()))?;
+
+                let mut update = connection.exec_bound::<(String, String, String, Arc<str>)>(indoc! {"
+                    UPDATE threads SET summary = ?, updated_at = ?, header = ? WHERE id = ?
+                "})?;
+                update((title, updated_at, header, id.0.clone()))?;
+
+                if let Some(tags) = &tags {
+                    Self::replace_tags_sync(connection, &id, tags)?;
+                }
+
+                Ok(())
+            })?;
+
+            saved_messages.insert(
+                id.0,
+                SavedMessages {
+                    compacted_len,
+                    hashes: message_hashes,
+                },
+            );
+        } else {
+            let json_data = serde_json::to_string(&SerializedThread {
+                thread,
+                version: DbThread::VERSION,
+            })?;
+
+            let (data_type, data, dictionary_id) =
+                match write_connection.compression_dictionary.lock().as_ref() {
+                    Some(dictionary) => (
+                        DataType::ZstdDict,
+                        compress_with_dictionary(json_data.as_bytes(), &dictionary.data)?,
+                        Some(dictionary.id),
+                    ),
+                    None => (
+                        DataType::Zstd,
+                        zstd::encode_all(json_data.as_bytes(), THREAD_COMPRESSION_LEVEL)?,
+                        None,
+                    ),
+                };
+
+            connection.with_savepoint("compact_thread", || {
+                let mut insert = connection.exec_bound::<(Arc<str>, String, String, DataType, Vec<u8>, Option<i64>)>(indoc! {"
+                    INSERT INTO threads (id, summary, updated_at, data_type, data, header, dictionary_id) VALUES (?, ?, ?, ?, ?, NULL, ?)
+                    ON CONFLICT (id) DO UPDATE SET
+                        summary = excluded.summary,
+                        updated_at = excluded.updated_at,
+                        data_type = excluded.data_type,
+                        data = excluded.data,
+                        header = NULL,
+                        dictionary_id = excluded.dictionary_id
+                "})?;
+                insert((id.0.clone(), title, updated_at, data_type, data, dictionary_id))?;
+
+                let mut delete_messages = connection.exec_bound::<Arc<str>>(indoc! {"
+                    DELETE FROM thread_messages WHERE thread_id = ?
+                "})?;
+                delete_messages(id.0.clone())?;
+
+                if let Some(tags) = &tags {
+                    Self::replace_tags_sync(connection, &id, tags)?;
+                }
+
+                Ok(())
+            })?;
+
+            saved_messages.insert(
+                id.0,
+                SavedMessages {
+                    compacted_len: message_hashes.len(),
+                    hashes: message_hashes,
+                },
+            );
+        }
 
         Ok(())
     }
 
     pub fn list_threads(&self) -> Task<Result<Vec<DbThreadMetadata>>> {
-        let connection = self.connection.clone();
+        let read_connections = self.read_connections.clone();
 
         self.executor.spawn(async move {
-            let connection = connection.lock();
+            let connection = read_connections.lock();
 
             let mut select =
-                connection.select_bound::<(), (Arc<str>, String, String)>(indoc! {"
-                SELECT id, summary, updated_at FROM threads ORDER BY updated_at DESC
+                connection.select_bound::<(), (Arc<str>, String, String, bool)>(indoc! {"
+                SELECT id, summary, updated_at, pinned FROM threads ORDER BY pinned DESC, updated_at DESC
             "})?;
 
             let rows = select(())?;
             let mut threads = Vec::new();
 
-            for (id, summary, updated_at) in rows {
+            for (id, summary, updated_at, pinned) in rows {
                 threads.push(DbThreadMetadata {
                     id: acp::SessionId::new(id),
                     title: summary.into(),
                     updated_at: DateTime::parse_from_rfc3339(&updated_at)?.with_timezone(&Utc),
+                    pinned,
                 });
             }
 
@@ -377,67 +1121,1481 @@ impl ThreadsDatabase {
     }
 
     pub fn load_thread(&self, id: acp::SessionId) -> Task<Result<Option<DbThread>>> {
-        let connection = self.connection.clone();
+        let read_connections = self.read_connections.clone();
 
         self.executor.spawn(async move {
-            let connection = connection.lock();
-            let mut select = connection.select_bound::<Arc<str>, (DataType, Vec<u8>)>(indoc! {"
-                SELECT data_type, data FROM threads WHERE id = ? LIMIT 1
-            "})?;
+            let connection = read_connections.lock();
+            // Reading inside a transaction keeps the blob and its appended messages consistent
+            // even if the write connection saves this thread concurrently.
+            connection.with_savepoint("load_thread", || Self::load_thread_sync(&connection, &id))
+        })
+    }
+
+    fn load_thread_sync(connection: &Connection, id: &acp::SessionId) -> Result<Option<DbThread>> {
+        let mut select = connection
+            .select_bound::<Arc<str>, (DataType, Vec<u8>, Option<String>, Option<i64>)>(
+                indoc! {"
+                    SELECT data_type, data, header, dictionary_id FROM threads WHERE id = ? LIMIT 1
+                "},
+            )?;
+
+        let rows = select(id.0.clone())?;
+        if let Some((data_type, data, header, dictionary_id)) = rows.into_iter().next() {
+            let dictionary = dictionary_id
+                .map(|dictionary_id| Self::dictionary_sync(connection, dictionary_id))
+                .transpose()?;
+            let mut json_data = Vec::new();
+            thread_data_reader(&data_type, &data, dictionary.as_deref())?
+                .read_to_end(&mut json_data)?;
+            let mut thread = DbThread::from_json(&json_data)?;
+            if let Some(header) = header {
+                let messages = mem::take(&mut thread.messages);
+                thread = DbThread::from_json(header.as_bytes())?;
+                thread.messages = messages;
+            }
+            Self::load_appended_messages_sync(connection, id, &mut thread.messages)?;
+            thread.tags = Some(Self::thread_tags_sync(connection, id)?);
+            Ok(Some(thread))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn export_thread(
+        &self,
+        id: acp::SessionId,
+        format: ThreadExportFormat,
+        path: PathBuf,
+    ) -> Task<Result<()>> {
+        let read_connections = self.read_connections.clone();
+
+        self.executor.spawn(async move {
+            let thread = {
+                let connection = read_connections.lock();
+                connection
+                    .with_savepoint("export_thread", || Self::load_thread_sync(&connection, &id))?
+            };
+            let thread = thread.with_context(|| format!("no thread found with ID: {id:?}"))?;
+
+            let contents = match format {
+                ThreadExportFormat::Markdown => thread.to_markdown(),
+                ThreadExportFormat::Json => serde_json::to_string_pretty(&ThreadsBundle::new(
+                    vec![BundledThread::new(id, thread)?],
+                ))?,
+            };
+            std::fs::write(&path, contents).with_context(|| format!("writing {path:?}"))?;
+
+            Ok(())
+        })
+    }
 
-            let rows = select(id.0)?;
-            if let Some((data_type, data)) = rows.into_iter().next() {
-                let json_data = match data_type {
-                    DataType::Zstd => {
-                        let decompressed = zstd::decode_all(&data[..])?;
-                        String::from_utf8(decompressed)?
+    /// Exports every thread, returning how many were written. Markdown exports
+    /// write one `<id>.md` file per thread into the directory at `path`, while
+    /// JSON exports write a single [`ThreadsBundle`] to `path`.
+    pub fn export_all(&self, format: ThreadExportFormat, path: PathBuf) -> Task<Result<usize>> {
+        let read_connections = self.read_connections.clone();
+
+        self.executor.spawn(async move {
+            let threads = {
+                let connection = read_connections.lock();
+                connection.with_savepoint("export_all", || {
+                    let mut select = connection.select::<Arc<str>>(indoc! {"
+                        SELECT id FROM threads ORDER BY updated_at DESC
+                    "})?;
+
+                    let mut threads = Vec::new();
+                    for id in select()? {
+                        let id = acp::SessionId::new(id);
+                        if let Some(thread) = Self::load_thread_sync(&connection, &id)? {
+                            threads.push((id, thread));
+                        }
                     }
-                    DataType::Json => String::from_utf8(data)?,
-                };
-                let thread = DbThread::from_json(json_data.as_bytes())?;
-                Ok(Some(thread))
-            } else {
-                Ok(None)
+                    Ok(threads)
+                })?
+            };
+            let count = threads.len();
+
+            match format {
+                ThreadExportFormat::Markdown => {
+                    std::fs::create_dir_all(&path)?;
+                    for (id, thread) in threads {
+                        let thread_path = path.join(format!("{id}.md"));
+                        std::fs::write(&thread_path, thread.to_markdown())
+                            .with_context(|| format!("writing {thread_path:?}"))?;
+                    }
+                }
+                ThreadExportFormat::Json => {
+                    let threads = threads
+                        .into_iter()
+                        .map(|(id, thread)| BundledThread::new(id, thread))
+                        .collect::<Result<Vec<_>>>()?;
+                    let contents = serde_json::to_string_pretty(&ThreadsBundle::new(threads))?;
+                    std::fs::write(&path, contents).with_context(|| format!("writing {path:?}"))?;
+                }
             }
+
+            Ok(count)
         })
     }
 
-    pub fn save_thread(&self, id: acp::SessionId, thread: DbThread) -> Task<Result<()>> {
+    /// Imports threads from a [`ThreadsBundle`], or from a single thread saved in
+    /// the current or legacy thread JSON format. Threads whose ID already exists
+    /// are left untouched so importing the same bundle twice is harmless.
+    pub fn import_threads(&self, path: PathBuf) -> Task<Result<ThreadImportSummary>> {
         let connection = self.connection.clone();
+        let saved_messages = self.saved_messages.clone();
 
-        self.executor
-            .spawn(async move { Self::save_thread_sync(&connection, id, thread) })
+        self.executor.spawn(async move {
+            let json = std::fs::read(&path).with_context(|| format!("reading {path:?}"))?;
+            let value = serde_json::from_slice::<serde_json::Value>(&json)?;
+
+            let threads = if value.get("threads").is_some() {
+                let bundle = serde_json::from_value::<ThreadsBundle>(value)?;
+                anyhow::ensure!(
+                    bundle.version == ThreadsBundle::VERSION,
+                    "unsupported thread bundle version: {}",
+                    bundle.version
+                );
+                bundle
+                    .threads
+                    .into_iter()
+                    .map(|bundled| {
+                        let mut thread =
+                            DbThread::from_json(&serde_json::to_vec(&bundled.thread)?)?;
+                        thread.tags = Some(bundled.tags);
+                        Ok((bundled.id, thread))
+                    })
+                    .collect::<Result<Vec<_>>>()?
+            } else {
+                let id = acp::SessionId::new(uuid::Uuid::new_v4().to_string());
+                vec![(id, DbThread::from_json(&json)?)]
+            };
+
+            let mut summary = ThreadImportSummary::default();
+            for (id, thread) in threads {
+                // Checking and saving under one lock keeps threads saved in the
+                // meantime from being overwritten.
+                let write_connection = connection.lock()?;
+                let imported = write_connection.with_savepoint("import_thread", || {
+                    let exists =
+                        write_connection.select_row_bound::<Arc<str>, usize>(indoc! {"
+                            SELECT COUNT(*) FROM threads WHERE id = ?
+                        "})?(id.0.clone())?
+                        .unwrap_or(0)
+                            > 0;
+                    if exists {
+                        return Ok(false);
+                    }
+                    Self::save_thread_locked(
+                        &write_connection,
+                        &connection,
+                        &saved_messages,
+                        id,
+                        thread,
+                    )?;
+                    Ok(true)
+                })?;
+                if imported {
+                    summary.imported += 1;
+                } else {
+                    summary.skipped += 1;
+                }
+            }
+
+            Ok(summary)
+        })
     }
 
-    pub fn delete_thread(&self, id: acp::SessionId) -> Task<Result<()>> {
+    /// Creates a new thread containing the first `at_message_index` messages of
+    /// thread `id`, leaving the original untouched, and returns the new thread's ID.
+    pub fn fork_thread(
+        &self,
+        id: acp::SessionId,
+        at_message_index: usize,
+    ) -> Task<Result<acp::SessionId>> {
         let connection = self.connection.clone();
+        let saved_messages = self.saved_messages.clone();
 
         self.executor.spawn(async move {
-            let connection = connection.lock();
+            let thread = {
+                let connection = connection.lock()?;
+                connection
+                    .with_savepoint("fork_thread", || Self::load_thread_sync(&connection, &id))?
+            };
+            let mut thread = thread.with_context(|| format!("no thread found with ID: {id:?}"))?;
+            anyhow::ensure!(
+                at_message_index <= thread.messages.len(),
+                "cannot fork thread at message {at_message_index}, it only has {} messages",
+                thread.messages.len()
+            );
 
-            let mut delete = connection.exec_bound::<Arc<str>>(indoc! {"
-                DELETE FROM threads WHERE id = ?
-            "})?;
+            thread.messages.truncate(at_message_index);
+            let kept_user_messages = thread
+                .messages
+                .iter()
+                .filter_map(|message| match message {
+                    crate::Message::User(message) => Some(message.id.clone()),
+                    _ => None,
+                })
+                .collect::<HashSet<_>>();
+            thread
+                .request_token_usage
+                .retain(|message_id, _| kept_user_messages.contains(message_id));
+            // Tokens spent by the original thread shouldn't be counted again for its fork.
+            thread.cumulative_token_usage = language_model::TokenUsage::default();
+            thread.title = format!("{} (fork)", thread.title).into();
+            thread.updated_at = Utc::now();
 
-            delete(id.0)?;
+            let fork_id = acp::SessionId::new(uuid::Uuid::new_v4().to_string());
+            Self::save_thread_sync(&connection, &saved_messages, fork_id.clone(), thread)?;
 
-            Ok(())
+            Ok(fork_id)
         })
     }
 
-    pub fn delete_threads(&self) -> Task<Result<()>> {
-        let connection = self.connection.clone();
+    pub fn storage_usage(&self) -> Task<Result<StorageUsage>> {
+        let read_connections = self.read_connections.clone();
 
         self.executor.spawn(async move {
-            let connection = connection.lock();
+            let connection = read_connections.lock();
+            connection.with_savepoint("storage_usage", || {
+                let dictionaries = Self::dictionaries_sync(&connection)?;
+                let mut select =
+                    connection.select::<(Arc<str>, String, DataType, Vec<u8>, Option<i64>, u64)>(
+                        indoc! {"
+                            SELECT
+                                id,
+                                summary,
+                                data_type,
+                                data,
+                                dictionary_id,
+                                COALESCE(length(CAST(header AS BLOB)), 0) + COALESCE(
+                                    (
+                                        SELECT SUM(length(CAST(thread_messages.data AS BLOB)))
+                                        FROM thread_messages
+                                        WHERE thread_messages.thread_id = threads.id
+                                    ),
+                                    0
+                                )
+                            FROM threads
+                        "},
+                    )?;
 
-            let mut delete = connection.exec_bound::<()>(indoc! {"
-                DELETE FROM threads
-            "})?;
+                let mut threads = Vec::new();
+                for (id, summary, data_type, data, dictionary_id, uncompressed_extra_bytes) in
+                    select()?
+                {
+                    let dictionary = dictionary_id
+                        .and_then(|dictionary_id| dictionaries.get(&dictionary_id))
+                        .map(Vec::as_slice);
+                    let blob_uncompressed_bytes = std::io::copy(
+                        &mut thread_data_reader(&data_type, &data, dictionary)?,
+                        &mut std::io::sink(),
+                    )?;
+                    threads.push(ThreadStorageUsage {
+                        id: acp::SessionId::new(id),
+                        title: summary.into(),
+                        stored_bytes: data.len() as u64 + uncompressed_extra_bytes,
+                        uncompressed_bytes: blob_uncompressed_bytes + uncompressed_extra_bytes,
+                    });
+                }
+                threads.sort_by_key(|thread| std::cmp::Reverse(thread.stored_bytes));
 
-            delete(())?;
+                let database_bytes = connection.select_row::<u64>(indoc! {"
+                    SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()
+                "})?()?
+                .unwrap_or(0);
 
-            Ok(())
+                Ok(StorageUsage {
+                    threads,
+                    database_bytes,
+                })
+            })
+        })
+    }
+
+    /// Token usage per day, oldest first. Threads are counted on the day they
+    /// were last updated, since per-request usage isn't timestamped.
+    pub fn usage_by_day(&self) -> Task<Result<Vec<TokenUsageSummary<NaiveDate>>>> {
+        let summaries = self.usage_by(|usage| usage.updated_at.date_naive());
+
+        self.executor.spawn(async move {
+            let mut summaries = summaries.await?;
+            summaries.sort_by_key(|summary| summary.key);
+            Ok(summaries)
+        })
+    }
+
+    /// Token usage per agent profile, most tokens first. Threads saved without
+    /// a profile are grouped under `None`.
+    pub fn usage_by_agent(&self) -> Task<Result<Vec<TokenUsageSummary<Option<AgentProfileId>>>>> {
+        self.usage_by(|usage| usage.profile.clone())
+    }
+
+    /// Token usage per model, keyed as `provider/model`, most tokens first.
+    /// Threads saved without a model are grouped under `None`.
+    pub fn usage_by_model(&self) -> Task<Result<Vec<TokenUsageSummary<Option<String>>>>> {
+        self.usage_by(|usage| {
+            usage
+                .model
+                .as_ref()
+                .map(|model| format!("{}/{}", model.provider, model.model))
         })
     }
+
+    fn usage_by<K: Hash + Eq + Send + 'static>(
+        &self,
+        key: impl Fn(&ThreadTokenUsage) -> K + Send + 'static,
+    ) -> Task<Result<Vec<TokenUsageSummary<K>>>> {
+        let read_connections = self.read_connections.clone();
+
+        self.executor.spawn(async move {
+            let usages = {
+                let connection = read_connections.lock();
+                connection
+                    .with_savepoint("usage_by", || Self::thread_token_usage_sync(&connection))?
+            };
+
+            let mut totals = HashMap::<K, (usize, language_model::TokenUsage)>::default();
+            for usage in usages {
+                let (thread_count, token_usage) = totals.entry(key(&usage)).or_default();
+                *thread_count += 1;
+                *token_usage = *token_usage + usage.cumulative_token_usage;
+            }
+
+            let mut summaries = totals
+                .into_iter()
+                .map(|(key, (thread_count, token_usage))| TokenUsageSummary {
+                    key,
+                    thread_count,
+                    token_usage,
+                })
+                .collect::<Vec<_>>();
+            summaries.sort_by_key(|summary| std::cmp::Reverse(summary.token_usage.total_tokens()));
+            Ok(summaries)
+        })
+    }
+
+    fn thread_token_usage_sync(connection: &Connection) -> Result<Vec<ThreadTokenUsage>> {
+        let dictionaries = Self::dictionaries_sync(connection)?;
+        let mut select =
+            connection.select::<(DataType, Vec<u8>, Option<String>, Option<i64>)>(indoc! {"
+                SELECT data_type, data, header, dictionary_id FROM threads
+            "})?;
+
+        let mut usages = Vec::new();
+        for (data_type, data, header, dictionary_id) in select()? {
+            // When set, the header is newer than the blob and holds everything but the messages.
+            let json = match header {
+                Some(header) => header.into_bytes(),
+                None => {
+                    let dictionary = dictionary_id
+                        .and_then(|dictionary_id| dictionaries.get(&dictionary_id))
+                        .map(Vec::as_slice);
+                    let mut json = Vec::new();
+                    thread_data_reader(&data_type, &data, dictionary)?.read_to_end(&mut json)?;
+                    json
+                }
+            };
+
+            match serde_json::from_slice::<ThreadTokenUsage>(&json) {
+                Ok(usage) if usage.version.as_deref() == Some(DbThread::VERSION) => {
+                    usages.push(usage)
+                }
+                _ => {
+                    let thread = DbThread::from_json(&json)?;
+                    usages.push(ThreadTokenUsage {
+                        version: None,
+                        updated_at: thread.updated_at,
+                        cumulative_token_usage: thread.cumulative_token_usage,
+                        model: thread.model,
+                        profile: thread.profile,
+                    });
+                }
+            }
+        }
+
+        Ok(usages)
+    }
+
+    /// Trains a zstd dictionary on the most recently updated threads and uses it
+    /// for every thread compacted from now on. Existing threads keep their current
+    /// compression until they're next compacted.
+    pub fn train_compression_dictionary(&self) -> Task<Result<CompressionDictionaryReport>> {
+        let connection = self.connection.clone();
+        let read_connections = self.read_connections.clone();
+
+        self.executor.spawn(async move {
+            let samples = {
+                let connection = read_connections.lock();
+                let dictionaries = Self::dictionaries_sync(&connection)?;
+                let mut select = connection
+                    .select_bound::<usize, (DataType, Vec<u8>, Option<i64>)>(indoc! {"
+                        SELECT data_type, data, dictionary_id FROM threads ORDER BY updated_at DESC LIMIT ?
+                    "})?;
+
+                let mut samples = Vec::new();
+                for (data_type, data, dictionary_id) in select(MAX_DICTIONARY_SAMPLES)? {
+                    let dictionary = dictionary_id
+                        .and_then(|dictionary_id| dictionaries.get(&dictionary_id))
+                        .map(Vec::as_slice);
+                    let mut sample = Vec::new();
+                    thread_data_reader(&data_type, &data, dictionary)?
+                        .take(MAX_DICTIONARY_SAMPLE_BYTES)
+                        .read_to_end(&mut sample)?;
+                    samples.push(sample);
+                }
+                samples
+            };
+            anyhow::ensure!(
+                samples.len() >= MIN_DICTIONARY_SAMPLES,
+                "training a compression dictionary needs at least {MIN_DICTIONARY_SAMPLES} threads, found {}",
+                samples.len()
+            );
+
+            let dictionary = zstd::dict::from_samples(&samples, MAX_DICTIONARY_BYTES)
+                .context("training compression dictionary")?;
+
+            let mut report = CompressionDictionaryReport {
+                sample_count: samples.len(),
+                dictionary_bytes: dictionary.len(),
+                baseline_bytes: 0,
+                dictionary_compressed_bytes: 0,
+            };
+            for sample in &samples {
+                report.baseline_bytes +=
+                    zstd::encode_all(&sample[..], THREAD_COMPRESSION_LEVEL)?.len() as u64;
+                report.dictionary_compressed_bytes +=
+                    compress_with_dictionary(sample, &dictionary)?.len() as u64;
+            }
+
+            let id = Self::store_dictionary_sync(&connection.lock()?, &dictionary)?;
+            *connection.compression_dictionary.lock() = Some(CompressionDictionary {
+                id,
+                data: dictionary.into(),
+            });
+
+            Ok(report)
+        })
+    }
+
+    fn load_appended_messages_sync(
+        connection: &Connection,
+        id: &acp::SessionId,
+        messages: &mut Vec<DbMessage>,
+    ) -> Result<()> {
+        let mut select = connection.select_bound::<Arc<str>, (usize, String)>(indoc! {"
+            SELECT message_index, data FROM thread_messages WHERE thread_id = ? ORDER BY message_index
+        "})?;
+
+        for (index, data) in select(id.0.clone())? {
+            anyhow::ensure!(
+                index == messages.len(),
+                "thread {id} is missing message {}",
+                messages.len()
+            );
+            messages.push(serde_json::from_str(&data)?);
+        }
+
+        Ok(())
+    }
+
+    pub fn save_thread(&self, id: acp::SessionId, thread: DbThread) -> Task<Result<()>> {
+        let connection = self.connection.clone();
+        let saved_messages = self.saved_messages.clone();
+
+        self.executor
+            .spawn(async move { Self::save_thread_sync(&connection, &saved_messages, id, thread) })
+    }
+
+    pub fn delete_thread(&self, id: acp::SessionId) -> Task<Result<()>> {
+        let connection = self.connection.clone();
+        let saved_messages = self.saved_messages.clone();
+
+        self.executor.spawn(async move {
+            let connection = connection.lock()?;
+            saved_messages.lock().remove(&id.0);
+
+            connection.with_savepoint("delete_thread", || {
+                let mut delete = connection.exec_bound::<Arc<str>>(indoc! {"
+                    DELETE FROM threads WHERE id = ?
+                "})?;
+                delete(id.0.clone())?;
+
+                let mut delete_messages = connection.exec_bound::<Arc<str>>(indoc! {"
+                    DELETE FROM thread_messages WHERE thread_id = ?
+                "})?;
+                delete_messages(id.0.clone())?;
+
+                let mut delete_tags = connection.exec_bound::<Arc<str>>(indoc! {"
+                    DELETE FROM thread_tags WHERE thread_id = ?
+                "})?;
+                delete_tags(id.0)?;
+
+                Ok(())
+            })
+        })
+    }
+
+    pub fn delete_threads(&self) -> Task<Result<()>> {
+        let connection = self.connection.clone();
+        let saved_messages = self.saved_messages.clone();
+
+        self.executor.spawn(async move {
+            let connection = connection.lock()?;
+            saved_messages.lock().clear();
+
+            connection.with_savepoint("delete_threads", || {
+                connection.exec(indoc! {"
+                    DELETE FROM threads
+                "})?()?;
+                connection.exec(indoc! {"
+                    DELETE FROM thread_messages
+                "})?()?;
+                connection.exec(indoc! {"
+                    DELETE FROM thread_tags
+                "})?()?;
+
+                Ok(())
+            })
+        })
+    }
+
+    /// Periodically snapshots the database into the backups directory, keeping
+    /// the most recent [`MAX_BACKUPS`] snapshots.
+    fn schedule_backups(self: &Arc<Self>) {
+        let Some(backups_dir) = self.backups_dir.clone() else {
+            return;
+        };
+        let this = Arc::downgrade(self);
+        let executor = self.executor.clone();
+
+        self.executor
+            .spawn(async move {
+                loop {
+                    let since_last_backup = latest_backup(&backups_dir)
+                        .ok()
+                        .flatten()
+                        .and_then(|(_, modified)| modified.elapsed().ok());
+                    if let Some(since_last_backup) = since_last_backup {
+                        executor
+                            .timer(BACKUP_INTERVAL.saturating_sub(since_last_backup))
+                            .await;
+                    }
+
+                    let Some(this) = this.upgrade() else {
+                        break;
+                    };
+                    // The process that owns writes takes the backups.
+                    if this.is_read_only() {
+                        drop(this);
+                        executor.timer(BACKUP_INTERVAL).await;
+                        continue;
+                    }
+                    let backup = this.backup();
+                    drop(this);
+                    if backup.await.log_err().is_none() {
+                        // Don't retry in a tight loop when backups keep failing.
+                        executor.timer(BACKUP_INTERVAL).await;
+                    }
+                }
+            })
+            .detach();
+    }
+
+    /// Writes a snapshot of the database to the backups directory using the SQLite
+    /// backup API and prunes old snapshots, returning the path of the new snapshot.
+    pub fn backup(&self) -> Task<Result<PathBuf>> {
+        let connection = self.connection.clone();
+        let backups_dir = self.backups_dir.clone();
+
+        self.executor.spawn(async move {
+            let backups_dir =
+                backups_dir.context("backups are only supported for databases on disk")?;
+            std::fs::create_dir_all(&backups_dir)?;
+
+            let file_name = format!(
+                "{BACKUP_FILE_PREFIX}{}.{BACKUP_FILE_EXTENSION}",
+                Utc::now().format("%Y%m%dT%H%M%S%.6fZ")
+            );
+            let path = backups_dir.join(file_name);
+            connection.lock()?.backup_main_to(&path)?;
+
+            let mut backups = list_backups(&backups_dir)?;
+            let excess = backups.len().saturating_sub(MAX_BACKUPS);
+            for (old_backup, _) in backups.drain(..excess) {
+                std::fs::remove_file(&old_backup)
+                    .with_context(|| format!("removing old backup {old_backup:?}"))?;
+            }
+
+            Ok(path)
+        })
+    }
+
+    /// Backups sorted from oldest to newest.
+    pub fn list_backups(&self) -> Task<Result<Vec<PathBuf>>> {
+        let backups_dir = self.backups_dir.clone();
+
+        self.executor.spawn(async move {
+            let Some(backups_dir) = backups_dir else {
+                return Ok(Vec::new());
+            };
+            Ok(list_backups(&backups_dir)?
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect())
+        })
+    }
+
+    /// Replaces the contents of the database with the snapshot at `path`.
+    /// The current contents are backed up first, so a restore can be undone.
+    pub fn restore_from_backup(&self, path: PathBuf) -> Task<Result<()>> {
+        let write_connection = self.connection.clone();
+        let saved_messages = self.saved_messages.clone();
+        let backup = self.backups_dir.is_some().then(|| self.backup());
+
+        self.executor.spawn(async move {
+            anyhow::ensure!(path.is_file(), "backup {path:?} does not exist");
+            if let Some(backup) = backup {
+                backup.await.context("backing up before restore")?;
+            }
+
+            let source = Connection::open_file(&path.to_string_lossy());
+            anyhow::ensure!(source.persistent(), "failed to open backup {path:?}");
+            source.select_row::<usize>("SELECT COUNT(*) FROM threads")?()
+                .with_context(|| format!("{path:?} is not a threads database backup"))?;
+
+            let connection = write_connection.lock()?;
+            source.backup_main(&connection)?;
+            // Backups taken by older versions may predate the current schema.
+            Self::migrate(&connection)?;
+            saved_messages.lock().clear();
+            *write_connection.compression_dictionary.lock() =
+                Self::latest_dictionary_sync(&connection)?;
+
+            Ok(())
+        })
+    }
+
+    pub fn pin_thread(&self, id: acp::SessionId) -> Task<Result<()>> {
+        self.set_thread_pinned(id, true)
+    }
+
+    pub fn unpin_thread(&self, id: acp::SessionId) -> Task<Result<()>> {
+        self.set_thread_pinned(id, false)
+    }
+
+    fn set_thread_pinned(&self, id: acp::SessionId, pinned: bool) -> Task<Result<()>> {
+        let connection = self.connection.clone();
+
+        self.executor.spawn(async move {
+            let connection = connection.lock()?;
+
+            let mut update = connection.exec_bound::<(bool, Arc<str>)>(indoc! {"
+                UPDATE threads SET pinned = ? WHERE id = ?
+            "})?;
+
+            update((pinned, id.0))?;
+
+            Ok(())
+        })
+    }
+
+    fn thread_tags_sync(connection: &Connection, id: &acp::SessionId) -> Result<Vec<SharedString>> {
+        let mut select = connection.select_bound::<Arc<str>, String>(indoc! {"
+            SELECT tag FROM thread_tags WHERE thread_id = ? ORDER BY tag
+        "})?;
+
+        Ok(select(id.0.clone())?
+            .into_iter()
+            .map(SharedString::from)
+            .collect())
+    }
+
+    pub fn add_tag(&self, id: acp::SessionId, tag: SharedString) -> Task<Result<()>> {
+        let connection = self.connection.clone();
+
+        self.executor
+            .spawn(async move { Self::add_tag_sync(&connection.lock()?, &id, &tag) })
+    }
+
+    fn add_tag_sync(connection: &Connection, id: &acp::SessionId, tag: &str) -> Result<()> {
+        let tag = normalize_tag(tag)?;
+
+        let thread_exists = connection.select_row_bound::<Arc<str>, bool>(indoc! {"
+            SELECT EXISTS (SELECT 1 FROM threads WHERE id = ?)
+        "})?(id.0.clone())?
+        .unwrap_or(false);
+        anyhow::ensure!(thread_exists, "no thread found with ID: {id:?}");
+
+        let mut insert = connection.exec_bound::<(Arc<str>, String)>(indoc! {"
+            INSERT OR IGNORE INTO thread_tags (thread_id, tag) VALUES (?, ?)
+        "})?;
+
+        insert((id.0.clone(), tag))?;
+
+        Ok(())
+    }
+
+    fn replace_tags_sync(
+        connection: &Connection,
+        id: &acp::SessionId,
+        tags: &[SharedString],
+    ) -> Result<()> {
+        let mut delete_tags = connection.exec_bound::<Arc<str>>(indoc! {"
+            DELETE FROM thread_tags WHERE thread_id = ?
+        "})?;
+        delete_tags(id.0.clone())?;
+
+        for tag in tags {
+            Self::add_tag_sync(connection, id, tag)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn remove_tag(&self, id: acp::SessionId, tag: SharedString) -> Task<Result<()>> {
+        let connection = self.connection.clone();
+
+        self.executor.spawn(async move {
+            let tag = normalize_tag(&tag)?;
+            let connection = connection.lock()?;
+
+            let mut delete = connection.exec_bound::<(Arc<str>, String)>(indoc! {"
+                DELETE FROM thread_tags WHERE thread_id = ? AND tag = ?
+            "})?;
+
+            delete((id.0, tag))?;
+
+            Ok(())
+        })
+    }
+
+    pub fn list_by_tag(&self, tag: SharedString) -> Task<Result<Vec<DbThreadMetadata>>> {
+        let read_connections = self.read_connections.clone();
+
+        self.executor.spawn(async move {
+            let tag = normalize_tag(&tag)?;
+            let connection = read_connections.lock();
+
+            let mut select =
+                connection.select_bound::<String, (Arc<str>, String, String, bool)>(indoc! {"
+                SELECT threads.id, threads.summary, threads.updated_at, threads.pinned
+                FROM threads
+                JOIN thread_tags ON thread_tags.thread_id = threads.id
+                WHERE thread_tags.tag = ?
+                ORDER BY threads.pinned DESC, threads.updated_at DESC
+            "})?;
+
+            let rows = select(tag)?;
+            let mut threads = Vec::new();
+
+            for (id, summary, updated_at, pinned) in rows {
+                threads.push(DbThreadMetadata {
+                    id: acp::SessionId::new(id),
+                    title: summary.into(),
+                    updated_at: DateTime::parse_from_rfc3339(&updated_at)?.with_timezone(&Utc),
+                    pinned,
+                });
+            }
+
+            Ok(threads)
+        })
+    }
+}
+
+fn list_backups(backups_dir: &Path) -> Result<Vec<(PathBuf, SystemTime)>> {
+    if !backups_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut backups = Vec::new();
+    for entry in std::fs::read_dir(backups_dir)? {
+        let path = entry?.path();
+        let is_backup = path
+            .extension()
+            .is_some_and(|extension| extension == BACKUP_FILE_EXTENSION)
+            && path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(BACKUP_FILE_PREFIX));
+        if is_backup {
+            let modified = path.metadata()?.modified()?;
+            backups.push((path, modified));
+        }
+    }
+
+    // Timestamped file names sort chronologically.
+    backups.sort();
+    Ok(backups)
+}
+
+fn latest_backup(backups_dir: &Path) -> Result<Option<(PathBuf, SystemTime)>> {
+    Ok(list_backups(backups_dir)?.pop())
+}
+
+fn compress_with_dictionary(data: &[u8], dictionary: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder =
+        zstd::Encoder::with_dictionary(Vec::new(), THREAD_COMPRESSION_LEVEL, dictionary)?;
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Reads the thread JSON stored in a `threads.data` blob. `dictionary` must be the
+/// dictionary referenced by the row when `data_type` is [`DataType::ZstdDict`].
+fn thread_data_reader<'a>(
+    data_type: &DataType,
+    data: &'a [u8],
+    dictionary: Option<&[u8]>,
+) -> Result<Box<dyn Read + 'a>> {
+    Ok(match data_type {
+        DataType::Json => Box::new(data),
+        DataType::Zstd => Box::new(zstd::Decoder::new(data)?),
+        DataType::ZstdDict => {
+            let dictionary =
+                dictionary.context("thread was compressed with a dictionary that is missing")?;
+            Box::new(zstd::Decoder::with_dictionary(data, dictionary)?)
+        }
+    })
+}
+
+/// Tags are matched case-insensitively, so "Bug-Hunt" and "bug-hunt" refer to the same tag.
+fn normalize_tag(tag: &str) -> Result<String> {
+    let tag = tag.trim().to_lowercase();
+    anyhow::ensure!(!tag.is_empty(), "tag cannot be empty");
+    Ok(tag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gpui::TestAppContext;
+
+    fn user_message(text: &str) -> DbMessage {
+        crate::Message::User(UserMessage {
+            id: UserMessageId::new(),
+            content: vec![UserMessageContent::Text(text.to_string())],
+        })
+    }
+
+    fn db_thread(title: &str, messages: Vec<DbMessage>) -> DbThread {
+        DbThread {
+            title: title.to_string().into(),
+            messages,
+            updated_at: Utc::now(),
+            detailed_summary: None,
+            initial_project_snapshot: None,
+            cumulative_token_usage: Default::default(),
+            request_token_usage: HashMap::default(),
+            model: None,
+            completion_mode: None,
+            profile: None,
+            tags: None,
+        }
+    }
+
+    #[gpui::test]
+    async fn test_incremental_message_saves(cx: &mut TestAppContext) {
+        let database = ThreadsDatabase::new(cx.executor()).unwrap();
+        let id = acp::SessionId::new("incremental");
+        let mut messages = vec![user_message("one"), user_message("two")];
+
+        database
+            .save_thread(id.clone(), db_thread("First", messages.clone()))
+            .await
+            .unwrap();
+        messages.push(user_message("three"));
+        database
+            .save_thread(id.clone(), db_thread("Second", messages.clone()))
+            .await
+            .unwrap();
+
+        let loaded = database.load_thread(id.clone()).await.unwrap().unwrap();
+        assert_eq!(loaded.title.as_ref(), "Second");
+        assert_eq!(loaded.messages, messages);
+
+        // Rewriting history must not leave stale appended messages behind.
+        messages.truncate(1);
+        messages.push(user_message("edited"));
+        database
+            .save_thread(id.clone(), db_thread("Third", messages.clone()))
+            .await
+            .unwrap();
+
+        let loaded = database.load_thread(id.clone()).await.unwrap().unwrap();
+        assert_eq!(loaded.title.as_ref(), "Third");
+        assert_eq!(loaded.messages, messages);
+
+        for index in 0..MESSAGE_COMPACTION_THRESHOLD + 1 {
+            messages.push(user_message(&format!("message {index}")));
+            database
+                .save_thread(id.clone(), db_thread("Fourth", messages.clone()))
+                .await
+                .unwrap();
+        }
+
+        let loaded = database.load_thread(id).await.unwrap().unwrap();
+        assert_eq!(loaded.messages, messages);
+    }
+
+    #[gpui::test]
+    async fn test_thread_tags(cx: &mut TestAppContext) {
+        let database = ThreadsDatabase::new(cx.executor()).unwrap();
+        let id = acp::SessionId::new("tagged");
+        let other_id = acp::SessionId::new("untagged");
+        let mut thread = db_thread("Tagged", vec![user_message("hello")]);
+        thread.tags = Some(vec!["refactor".into()]);
+        database.save_thread(id.clone(), thread).await.unwrap();
+        database
+            .save_thread(other_id.clone(), db_thread("Untagged", Vec::new()))
+            .await
+            .unwrap();
+
+        database
+            .add_tag(id.clone(), " Bug-Hunt ".into())
+            .await
+            .unwrap();
+        let loaded = database.load_thread(id.clone()).await.unwrap().unwrap();
+        assert_eq!(
+            loaded.tags,
+            Some(vec!["bug-hunt".into(), "refactor".into()])
+        );
+        let listed = database.list_by_tag("bug-hunt".into()).await.unwrap();
+        assert_eq!(
+            listed.iter().map(|thread| &thread.id).collect::<Vec<_>>(),
+            [&id]
+        );
+        assert!(
+            database
+                .add_tag(acp::SessionId::new("missing"), "research".into())
+                .await
+                .is_err()
+        );
+
+        // Saving without tags keeps the stored ones, and saving with tags replaces them.
+        database
+            .save_thread(id.clone(), db_thread("Tagged", vec![user_message("hi")]))
+            .await
+            .unwrap();
+        let mut loaded = database.load_thread(id.clone()).await.unwrap().unwrap();
+        assert_eq!(
+            loaded.tags,
+            Some(vec!["bug-hunt".into(), "refactor".into()])
+        );
+        loaded.tags = Some(vec!["research".into()]);
+        database.save_thread(id.clone(), loaded).await.unwrap();
+        database
+            .remove_tag(id.clone(), "research".into())
+            .await
+            .unwrap();
+        database.add_tag(id.clone(), "docs".into()).await.unwrap();
+        let loaded = database.load_thread(id.clone()).await.unwrap().unwrap();
+        assert_eq!(loaded.tags, Some(vec!["docs".into()]));
+
+        database.delete_thread(id).await.unwrap();
+        assert!(
+            database
+                .list_by_tag("docs".into())
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        database
+            .save_thread(acp::SessionId::new("tagged"), db_thread("New", Vec::new()))
+            .await
+            .unwrap();
+        assert!(
+            database
+                .list_by_tag("docs".into())
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[gpui::test]
+    async fn test_fork_thread(cx: &mut TestAppContext) {
+        let database = ThreadsDatabase::new(cx.executor()).unwrap();
+        let id = acp::SessionId::new("source");
+        let messages = vec![
+            user_message("one"),
+            user_message("two"),
+            user_message("three"),
+        ];
+        database
+            .save_thread(id.clone(), db_thread("Source", messages.clone()))
+            .await
+            .unwrap();
+        database
+            .add_tag(id.clone(), "research".into())
+            .await
+            .unwrap();
+
+        let fork_id = database.fork_thread(id.clone(), 2).await.unwrap();
+        assert_ne!(fork_id, id);
+        let fork = database.load_thread(fork_id).await.unwrap().unwrap();
+        assert_eq!(fork.title.as_ref(), "Source (fork)");
+        assert_eq!(fork.messages, messages[..2]);
+        assert_eq!(fork.tags, Some(vec!["research".into()]));
+
+        let source = database.load_thread(id.clone()).await.unwrap().unwrap();
+        assert_eq!(source.title.as_ref(), "Source");
+        assert_eq!(source.messages, messages);
+        assert_eq!(database.list_threads().await.unwrap().len(), 2);
+
+        assert!(database.fork_thread(id, 4).await.is_err());
+    }
+
+    #[gpui::test]
+    async fn test_backup_and_restore(cx: &mut TestAppContext) {
+        let dir = tempfile::tempdir().unwrap();
+        let mut database = ThreadsDatabase::new(cx.executor()).unwrap();
+        database.backups_dir = Some(dir.path().join("backups"));
+        let id = acp::SessionId::new("backed-up");
+        let messages = vec![user_message("hello")];
+        database
+            .save_thread(id.clone(), db_thread("Before", messages.clone()))
+            .await
+            .unwrap();
+        let backup = database.backup().await.unwrap();
+
+        database
+            .save_thread(
+                id.clone(),
+                db_thread("After", vec![user_message("hello"), user_message("again")]),
+            )
+            .await
+            .unwrap();
+        database
+            .save_thread(acp::SessionId::new("new"), db_thread("New", Vec::new()))
+            .await
+            .unwrap();
+
+        database.restore_from_backup(backup.clone()).await.unwrap();
+        let threads = database.list_threads().await.unwrap();
+        assert_eq!(
+            threads.iter().map(|thread| &thread.id).collect::<Vec<_>>(),
+            [&id]
+        );
+        let restored = database.load_thread(id).await.unwrap().unwrap();
+        assert_eq!(restored.title.as_ref(), "Before");
+        assert_eq!(restored.messages, messages);
+        // The contents replaced by the restore were backed up first.
+        let backups = database.list_backups().await.unwrap();
+        assert_eq!(backups.len(), 2);
+        assert_eq!(backups[0], backup);
+    }
+
+    #[gpui::test]
+    async fn test_export_and_import_bundle(cx: &mut TestAppContext) {
+        let database = ThreadsDatabase::new(cx.executor()).unwrap();
+        let id = acp::SessionId::new("exported");
+        let messages = vec![user_message("hello"), user_message("world")];
+        database
+            .save_thread(id.clone(), db_thread("Exported", messages.clone()))
+            .await
+            .unwrap();
+        database
+            .add_tag(id.clone(), "Research".into())
+            .await
+            .unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let bundle_path = dir.path().join("threads.json");
+        let exported = database
+            .export_all(ThreadExportFormat::Json, bundle_path.clone())
+            .await
+            .unwrap();
+        assert_eq!(exported, 1);
+
+        database.delete_threads().await.unwrap();
+        let summary = database.import_threads(bundle_path.clone()).await.unwrap();
+        assert_eq!(
+            summary,
+            ThreadImportSummary {
+                imported: 1,
+                skipped: 0
+            }
+        );
+
+        let imported = database.load_thread(id).await.unwrap().unwrap();
+        assert_eq!(imported.title.as_ref(), "Exported");
+        assert_eq!(imported.messages, messages);
+        assert_eq!(imported.tags, Some(vec![SharedString::from("research")]));
+
+        let summary = database.import_threads(bundle_path).await.unwrap();
+        assert_eq!(
+            summary,
+            ThreadImportSummary {
+                imported: 0,
+                skipped: 1
+            }
+        );
+    }
+
+    #[gpui::test]
+    async fn test_dictionary_compressed_threads(cx: &mut TestAppContext) {
+        let database = ThreadsDatabase::new(cx.executor()).unwrap();
+        let id = acp::SessionId::new("dictionary");
+        let messages = vec![user_message("hello"), user_message("world")];
+        database
+            .save_thread(id.clone(), db_thread("Dictionary", messages.clone()))
+            .await
+            .unwrap();
+        assert!(database.train_compression_dictionary().await.is_err());
+
+        // zstd treats bytes without a dictionary header as a raw content dictionary.
+        let dictionary = br#"{"title":"","messages":[{"User":{"content":[{"Text":""}]}}]}"#;
+        {
+            let connection = database.connection.lock().unwrap();
+            let dictionary_id =
+                ThreadsDatabase::store_dictionary_sync(&connection, dictionary).unwrap();
+            *database.connection.compression_dictionary.lock() = Some(CompressionDictionary {
+                id: dictionary_id,
+                data: dictionary.as_slice().into(),
+            });
+        }
+
+        let compressed_id = acp::SessionId::new("dictionary-compressed");
+        database
+            .save_thread(
+                compressed_id.clone(),
+                db_thread("Compressed", messages.clone()),
+            )
+            .await
+            .unwrap();
+        let data_type = database
+            .connection
+            .lock_for_read()
+            .select_row_bound::<Arc<str>, DataType>("SELECT data_type FROM threads WHERE id = ?")
+            .unwrap()(compressed_id.0.clone())
+        .unwrap();
+        assert_eq!(data_type, Some(DataType::ZstdDict));
+
+        let loaded = database.load_thread(compressed_id).await.unwrap().unwrap();
+        assert_eq!(loaded.title.as_ref(), "Compressed");
+        assert_eq!(loaded.messages, messages);
+
+        // Threads compressed before the dictionary existed still load.
+        let loaded = database.load_thread(id).await.unwrap().unwrap();
+        assert_eq!(loaded.messages, messages);
+
+        let usage = database.storage_usage().await.unwrap();
+        assert_eq!(usage.threads.len(), 2);
+    }
+
+    #[gpui::test]
+    async fn test_pinned_threads_are_listed_first(cx: &mut TestAppContext) {
+        let database = ThreadsDatabase::new(cx.executor()).unwrap();
+        let older_id = acp::SessionId::new("older");
+        let newer_id = acp::SessionId::new("newer");
+        let mut older = db_thread("Older", vec![user_message("one")]);
+        older.updated_at = Utc::now() - chrono::Duration::hours(1);
+        database.save_thread(older_id.clone(), older).await.unwrap();
+        database
+            .save_thread(
+                newer_id.clone(),
+                db_thread("Newer", vec![user_message("two")]),
+            )
+            .await
+            .unwrap();
+
+        database.pin_thread(older_id.clone()).await.unwrap();
+        let threads = database.list_threads().await.unwrap();
+        assert_eq!(
+            threads
+                .iter()
+                .map(|thread| (thread.id.clone(), thread.pinned))
+                .collect::<Vec<_>>(),
+            vec![(older_id.clone(), true), (newer_id.clone(), false)]
+        );
+
+        // Compacting a thread rewrites its row, which must keep it pinned.
+        database
+            .save_thread(
+                older_id.clone(),
+                db_thread("Older", vec![user_message("edited")]),
+            )
+            .await
+            .unwrap();
+        let threads = database.list_threads().await.unwrap();
+        assert!(threads[0].pinned);
+
+        database.unpin_thread(older_id.clone()).await.unwrap();
+        let threads = database.list_threads().await.unwrap();
+        assert!(threads.iter().all(|thread| !thread.pinned));
+    }
+
+    #[gpui::test]
+    async fn test_usage_by_model_and_agent(cx: &mut TestAppContext) {
+        let database = ThreadsDatabase::new(cx.executor()).unwrap();
+        let usage = |input_tokens| language_model::TokenUsage {
+            input_tokens,
+            output_tokens: 10,
+            ..Default::default()
+        };
+        for (id, model, profile, input_tokens) in [
+            ("first", Some("claude"), Some("write"), 100),
+            ("second", Some("claude"), Some("ask"), 50),
+            ("third", None, Some("write"), 5),
+        ] {
+            let mut thread = db_thread(id, vec![user_message(id)]);
+            thread.model = model.map(|model| DbLanguageModel {
+                provider: "anthropic".to_string(),
+                model: model.to_string(),
+            });
+            thread.profile = profile.map(|profile| AgentProfileId(profile.into()));
+            thread.cumulative_token_usage = usage(input_tokens);
+            database
+                .save_thread(acp::SessionId::new(id), thread)
+                .await
+                .unwrap();
+        }
+
+        let by_model = database.usage_by_model().await.unwrap();
+        assert_eq!(
+            by_model,
+            vec![
+                TokenUsageSummary {
+                    key: Some("anthropic/claude".to_string()),
+                    thread_count: 2,
+                    token_usage: usage(150) + usage(0),
+                },
+                TokenUsageSummary {
+                    key: None,
+                    thread_count: 1,
+                    token_usage: usage(5),
+                },
+            ]
+        );
+
+        let by_agent = database.usage_by_agent().await.unwrap();
+        assert_eq!(
+            by_agent
+                .iter()
+                .map(|summary| (summary.key.clone(), summary.thread_count))
+                .collect::<Vec<_>>(),
+            vec![
+                (Some(AgentProfileId("write".into())), 2),
+                (Some(AgentProfileId("ask".into())), 1),
+            ]
+        );
+
+        let by_day = database.usage_by_day().await.unwrap();
+        assert_eq!(by_day.len(), 1);
+        assert_eq!(by_day[0].thread_count, 3);
+    }
+
+    #[gpui::test]
+    async fn test_project_scoped_databases_are_separate(cx: &mut TestAppContext) {
+        let global = ThreadsDatabase::new(cx.executor()).unwrap();
+        let project_scope = ThreadsDatabaseScope::Project(Path::new("/project").into());
+        let project = ThreadsDatabase::new_scoped(cx.executor(), &project_scope).unwrap();
+
+        let id = acp::SessionId::new("project-thread");
+        project
+            .save_thread(id.clone(), db_thread("Project", vec![user_message("hi")]))
+            .await
+            .unwrap();
+
+        assert_eq!(project.list_threads().await.unwrap().len(), 1);
+        assert!(global.list_threads().await.unwrap().is_empty());
+        assert!(global.load_thread(id).await.unwrap().is_none());
+    }
+
+    #[test]
+    fn test_read_only_database_must_be_migrated() {
+        let connection = Connection::open_memory(None);
+        let error = ThreadsDatabase::ensure_migrated(&connection).unwrap_err();
+        assert!(error.downcast_ref::<ThreadsDatabaseOutdated>().is_some());
+
+        // An older version that hasn't run the latest migration owns writes.
+        connection
+            .migrate(
+                MIGRATION_DOMAIN,
+                &MIGRATIONS[..MIGRATIONS.len() - 1],
+                |_, _, _| false,
+            )
+            .unwrap();
+        let error = ThreadsDatabase::ensure_migrated(&connection).unwrap_err();
+        assert!(error.downcast_ref::<ThreadsDatabaseOutdated>().is_some());
+
+        ThreadsDatabase::migrate(&connection).unwrap();
+        ThreadsDatabase::ensure_migrated(&connection).unwrap();
+    }
+
+    #[test]
+    fn test_write_connection_takes_over_released_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let lock_path = dir.path().join("threads.db.lock");
+        let other_process_lock = try_lock_file(&lock_path).unwrap();
+        assert!(other_process_lock.is_some());
+
+        let write_connection = WriteConnection {
+            connection: Mutex::new(Connection::open_memory(None)),
+            lock_path: Some(lock_path),
+            lock_file: Mutex::new(None),
+            compression_dictionary: Mutex::new(None),
+        };
+        assert!(write_connection.is_read_only());
+        let error = write_connection.lock().err().unwrap();
+        assert!(error.downcast_ref::<ThreadsDatabaseReadOnly>().is_some());
+
+        drop(other_process_lock);
+        let connection = write_connection.lock().unwrap();
+        assert_eq!(count(&connection, "threads"), 0);
+        drop(connection);
+        assert!(!write_connection.is_read_only());
+    }
+
+    fn exec(connection: &Connection, sql: &str) {
+        connection.exec(sql).unwrap()().unwrap();
+    }
+
+    fn count(connection: &Connection, table: &str) -> usize {
+        connection
+            .select_row::<usize>(&format!("SELECT COUNT(*) FROM {table}"))
+            .unwrap()()
+        .unwrap()
+        .unwrap()
+    }
+
+    /// Opens the in-memory database that `ThreadsDatabase::new` opens in this
+    /// test, so that tests can create older schemas in it first.
+    fn open_test_connection() -> Connection {
+        let thread = std::thread::current();
+        Connection::open_memory(Some(&format!(
+            "THREAD_FALLBACK_{}",
+            thread.name().unwrap_or_default()
+        )))
+    }
+
+    fn create_original_schema(connection: &Connection) {
+        exec(
+            connection,
+            indoc! {"
+                CREATE TABLE threads (
+                    id TEXT PRIMARY KEY,
+                    summary TEXT NOT NULL,
+                    updated_at TEXT NOT NULL,
+                    data_type TEXT NOT NULL,
+                    data BLOB NOT NULL
+                )
+            "},
+        );
+        exec(
+            connection,
+            indoc! {"
+                INSERT INTO threads VALUES ('a', 'A', '2024-01-01T00:00:00Z', 'json', '{}')
+            "},
+        );
+    }
+
+    /// Adds what versions from before migrations were introduced created
+    /// directly for headers, tags and incremental message saves.
+    fn create_unversioned_message_tables(connection: &Connection) {
+        exec(connection, "ALTER TABLE threads ADD COLUMN header TEXT");
+        exec(
+            connection,
+            indoc! {"
+                CREATE TABLE thread_tags (
+                    thread_id TEXT NOT NULL,
+                    tag TEXT NOT NULL,
+                    PRIMARY KEY (thread_id, tag)
+                )
+            "},
+        );
+        exec(
+            connection,
+            indoc! {"
+                CREATE TABLE thread_messages (
+                    thread_id TEXT NOT NULL,
+                    message_index INTEGER NOT NULL,
+                    data TEXT NOT NULL,
+                    PRIMARY KEY (thread_id, message_index)
+                )
+            "},
+        );
+        exec(
+            connection,
+            "INSERT INTO thread_tags VALUES ('a', 'research')",
+        );
+    }
+
+    /// Adds what versions from before migrations were introduced created
+    /// directly for compression dictionaries.
+    fn create_unversioned_dictionaries(connection: &Connection) {
+        exec(
+            connection,
+            indoc! {"
+                CREATE TABLE compression_dictionaries (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    data BLOB NOT NULL,
+                    created_at TEXT NOT NULL
+                )
+            "},
+        );
+        exec(
+            connection,
+            "ALTER TABLE threads ADD COLUMN dictionary_id INTEGER",
+        );
+    }
+
+    /// Migrates the test's database and checks that threads can still be
+    /// listed, saved and loaded.
+    async fn assert_migrates(connection: &Connection, cx: &mut TestAppContext) {
+        let database = ThreadsDatabase::new(cx.executor()).unwrap();
+        // Migrating an up-to-date database is a no-op.
+        ThreadsDatabase::migrate(connection).unwrap();
+
+        let threads = database.list_threads().await.unwrap();
+        assert_eq!(
+            threads
+                .iter()
+                .map(|thread| thread.title.to_string())
+                .collect::<Vec<_>>(),
+            ["A"]
+        );
+
+        let id = acp::SessionId::new("migrated");
+        let mut thread = db_thread("Migrated", vec![user_message("hi")]);
+        thread.tags = Some(vec!["research".into()]);
+        database.save_thread(id.clone(), thread).await.unwrap();
+        let thread = database.load_thread(id).await.unwrap().unwrap();
+        assert_eq!(thread.title.to_string(), "Migrated");
+        assert_eq!(thread.messages.len(), 1);
+        assert_eq!(database.list_threads().await.unwrap().len(), 2);
+    }
+
+    #[gpui::test]
+    async fn test_migrate_original_schema(cx: &mut TestAppContext) {
+        let connection = open_test_connection();
+        create_original_schema(&connection);
+
+        assert_migrates(&connection, cx).await;
+        assert_eq!(count(&connection, "thread_tags"), 1);
+        assert_eq!(count(&connection, "thread_messages"), 0);
+    }
+
+    #[gpui::test]
+    async fn test_migrate_unversioned_message_tables(cx: &mut TestAppContext) {
+        let connection = open_test_connection();
+        create_original_schema(&connection);
+        create_unversioned_message_tables(&connection);
+
+        assert_migrates(&connection, cx).await;
+        assert_eq!(count(&connection, "thread_tags"), 2);
+    }
+
+    #[gpui::test]
+    async fn test_migrate_unversioned_dictionaries(cx: &mut TestAppContext) {
+        let connection = open_test_connection();
+        create_original_schema(&connection);
+        create_unversioned_message_tables(&connection);
+        create_unversioned_dictionaries(&connection);
+
+        assert_migrates(&connection, cx).await;
+        assert_eq!(count(&connection, "thread_tags"), 2);
+        assert_eq!(count(&connection, "compression_dictionaries"), 0);
+    }
 }
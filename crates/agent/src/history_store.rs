@@ -1,16 +1,25 @@
-use crate::{DbThread, DbThreadMetadata, ThreadsDatabase};
+use crate::{
+    DbThread, DbThreadMetadata, ThreadImportSummary, ThreadsDatabase, ThreadsDatabaseScope,
+};
 use acp_thread::MentionUri;
 use agent_client_protocol as acp;
 use anyhow::{Context as _, Result, anyhow};
 use assistant_text_thread::{SavedTextThreadMetadata, TextThread};
 use chrono::{DateTime, Utc};
 use db::kvp::KEY_VALUE_STORE;
+use futures::future::Shared;
 use gpui::{App, AsyncApp, Entity, SharedString, Task, prelude::*};
 use itertools::Itertools;
 use paths::text_threads_dir;
 use project::Project;
 use serde::{Deserialize, Serialize};
-use std::{collections::VecDeque, path::Path, rc::Rc, sync::Arc, time::Duration};
+use std::{
+    collections::VecDeque,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+    time::Duration,
+};
 use ui::ElementId;
 use util::ResultExt as _;
 
@@ -61,6 +70,13 @@ impl HistoryEntry {
         }
     }
 
+    pub fn pinned(&self) -> bool {
+        match self {
+            HistoryEntry::AcpThread(thread) => thread.pinned,
+            HistoryEntry::TextThread(_) => false,
+        }
+    }
+
     pub fn id(&self) -> HistoryEntryId {
         match self {
             HistoryEntry::AcpThread(thread) => HistoryEntryId::AcpThread(thread.id.clone()),
@@ -123,6 +139,7 @@ pub struct HistoryStore {
     threads: Vec<DbThreadMetadata>,
     entries: Vec<HistoryEntry>,
     text_thread_store: Entity<assistant_text_thread::TextThreadStore>,
+    database_scope: ThreadsDatabaseScope,
     recently_opened_entries: VecDeque<HistoryEntryId>,
     _subscriptions: Vec<gpui::Subscription>,
     _save_recently_opened_entries_task: Task<()>,
@@ -132,6 +149,14 @@ impl HistoryStore {
     pub fn new(
         text_thread_store: Entity<assistant_text_thread::TextThreadStore>,
         cx: &mut Context<Self>,
+    ) -> Self {
+        Self::new_with_scope(text_thread_store, ThreadsDatabaseScope::Global, cx)
+    }
+
+    pub fn new_with_scope(
+        text_thread_store: Entity<assistant_text_thread::TextThreadStore>,
+        database_scope: ThreadsDatabaseScope,
+        cx: &mut Context<Self>,
     ) -> Self {
         let subscriptions =
             vec![cx.observe(&text_thread_store, |this, _, cx| this.update_entries(cx))];
@@ -151,6 +176,7 @@ impl HistoryStore {
 
         Self {
             text_thread_store,
+            database_scope,
             recently_opened_entries: VecDeque::default(),
             threads: Vec::default(),
             entries: Vec::default(),
@@ -159,6 +185,50 @@ impl HistoryStore {
         }
     }
 
+    /// Creates a store for `project`'s threads, which moves to the scope of
+    /// the project's first root whenever its worktrees change.
+    pub fn for_project(
+        text_thread_store: Entity<assistant_text_thread::TextThreadStore>,
+        project: &Entity<Project>,
+        cx: &mut Context<Self>,
+    ) -> Self {
+        let database_scope = ThreadsDatabaseScope::for_project(project, cx);
+        let mut this = Self::new_with_scope(text_thread_store, database_scope, cx);
+        this._subscriptions.push(
+            cx.subscribe(project, |this, project, event, cx| match event {
+                project::Event::WorktreeAdded(_)
+                | project::Event::WorktreeRemoved(_)
+                | project::Event::WorktreeOrderChanged => {
+                    let database_scope = ThreadsDatabaseScope::for_project(&project, cx);
+                    this.set_database_scope(database_scope, cx);
+                }
+                _ => {}
+            }),
+        );
+        this
+    }
+
+    pub fn database_scope(&self) -> &ThreadsDatabaseScope {
+        &self.database_scope
+    }
+
+    fn set_database_scope(&mut self, database_scope: ThreadsDatabaseScope, cx: &mut Context<Self>) {
+        if self.database_scope == database_scope {
+            return;
+        }
+        self.database_scope = database_scope;
+        self.threads.clear();
+        self.update_entries(cx);
+        self.reload(cx);
+    }
+
+    fn database(
+        &self,
+        cx: &mut App,
+    ) -> Shared<Task<Result<Arc<ThreadsDatabase>, Arc<anyhow::Error>>>> {
+        ThreadsDatabase::connect_scoped(self.database_scope.clone(), cx)
+    }
+
     pub fn thread_from_session_id(&self, session_id: &acp::SessionId) -> Option<&DbThreadMetadata> {
         self.threads.iter().find(|thread| &thread.id == session_id)
     }
@@ -168,7 +238,7 @@ impl HistoryStore {
         id: acp::SessionId,
         cx: &mut Context<Self>,
     ) -> Task<Result<Option<DbThread>>> {
-        let database_future = ThreadsDatabase::connect(cx);
+        let database_future = self.database(cx);
         cx.background_spawn(async move {
             let database = database_future.await.map_err(|err| anyhow!(err))?;
             database.load_thread(id).await
@@ -180,7 +250,7 @@ impl HistoryStore {
         id: acp::SessionId,
         cx: &mut Context<Self>,
     ) -> Task<Result<()>> {
-        let database_future = ThreadsDatabase::connect(cx);
+        let database_future = self.database(cx);
         cx.spawn(async move |this, cx| {
             let database = database_future.await.map_err(|err| anyhow!(err))?;
             database.delete_thread(id.clone()).await?;
@@ -189,7 +259,7 @@ impl HistoryStore {
     }
 
     pub fn delete_threads(&mut self, cx: &mut Context<Self>) -> Task<Result<()>> {
-        let database_future = ThreadsDatabase::connect(cx);
+        let database_future = self.database(cx);
         cx.spawn(async move |this, cx| {
             let database = database_future.await.map_err(|err| anyhow!(err))?;
             database.delete_threads().await?;
@@ -197,6 +267,66 @@ impl HistoryStore {
         })
     }
 
+    pub fn restore_threads_from_backup(
+        &mut self,
+        path: PathBuf,
+        cx: &mut Context<Self>,
+    ) -> Task<Result<()>> {
+        let database_future = self.database(cx);
+        cx.spawn(async move |this, cx| {
+            let database = database_future.await.map_err(|err| anyhow!(err))?;
+            database.restore_from_backup(path).await?;
+            this.update(cx, |this, cx| this.reload(cx))
+        })
+    }
+
+    pub fn import_threads(
+        &mut self,
+        path: PathBuf,
+        cx: &mut Context<Self>,
+    ) -> Task<Result<ThreadImportSummary>> {
+        let database_future = self.database(cx);
+        cx.spawn(async move |this, cx| {
+            let database = database_future.await.map_err(|err| anyhow!(err))?;
+            let summary = database.import_threads(path).await?;
+            this.update(cx, |this, cx| this.reload(cx))?;
+            Ok(summary)
+        })
+    }
+
+    pub fn fork_thread(
+        &mut self,
+        id: acp::SessionId,
+        at_message_index: usize,
+        cx: &mut Context<Self>,
+    ) -> Task<Result<acp::SessionId>> {
+        let database_future = self.database(cx);
+        cx.spawn(async move |this, cx| {
+            let database = database_future.await.map_err(|err| anyhow!(err))?;
+            let fork_id = database.fork_thread(id, at_message_index).await?;
+            this.update(cx, |this, cx| this.reload(cx))?;
+            Ok(fork_id)
+        })
+    }
+
+    pub fn set_thread_pinned(
+        &mut self,
+        id: acp::SessionId,
+        pinned: bool,
+        cx: &mut Context<Self>,
+    ) -> Task<Result<()>> {
+        let database_future = self.database(cx);
+        cx.spawn(async move |this, cx| {
+            let database = database_future.await.map_err(|err| anyhow!(err))?;
+            if pinned {
+                database.pin_thread(id).await?;
+            } else {
+                database.unpin_thread(id).await?;
+            }
+            this.update(cx, |this, cx| this.reload(cx))
+        })
+    }
+
     pub fn delete_text_thread(
         &mut self,
         path: Arc<Path>,
@@ -216,7 +346,8 @@ impl HistoryStore {
     }
 
     pub fn reload(&self, cx: &mut Context<Self>) {
-        let database_future = ThreadsDatabase::connect(cx);
+        let database_scope = self.database_scope.clone();
+        let database_future = self.database(cx);
         cx.spawn(async move |this, cx| {
             let threads = database_future
                 .await
@@ -225,9 +356,14 @@ impl HistoryStore {
                 .await?;
 
             this.update(cx, |this, cx| {
+                // The scope changed while loading, and the new scope's threads are loading.
+                if this.database_scope != database_scope {
+                    return;
+                }
                 if this.recently_opened_entries.len() < MAX_RECENTLY_OPENED_ENTRIES {
                     for thread in threads
                         .iter()
+                        .sorted_by_key(|thread| std::cmp::Reverse(thread.updated_at))
                         .take(MAX_RECENTLY_OPENED_ENTRIES - this.recently_opened_entries.len())
                         .rev()
                     {
@@ -259,7 +395,12 @@ impl HistoryStore {
                 .map(HistoryEntry::TextThread),
         );
 
-        history_entries.sort_unstable_by_key(|entry| std::cmp::Reverse(entry.updated_at()));
+        history_entries.sort_unstable_by_key(|entry| {
+            (
+                std::cmp::Reverse(entry.pinned()),
+                std::cmp::Reverse(entry.updated_at()),
+            )
+        });
         self.entries = history_entries;
         cx.notify()
     }
@@ -410,3 +551,150 @@ impl HistoryStore {
         self.entries.iter().cloned()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{UserMessage, UserMessageContent};
+    use acp_thread::UserMessageId;
+    use agent_settings::AgentSettings;
+    use fs::FakeFs;
+    use gpui::TestAppContext;
+    use settings::{Settings as _, SettingsStore, ThreadStorage};
+    use util::path;
+
+    fn db_thread(id: &acp::SessionId, updated_at: DateTime<Utc>) -> DbThread {
+        DbThread {
+            title: id.to_string().into(),
+            messages: vec![crate::Message::User(UserMessage {
+                id: UserMessageId::new(),
+                content: vec![UserMessageContent::Text("hello".into())],
+            })],
+            updated_at,
+            detailed_summary: None,
+            initial_project_snapshot: None,
+            cumulative_token_usage: Default::default(),
+            request_token_usage: Default::default(),
+            model: None,
+            completion_mode: None,
+            profile: None,
+            tags: None,
+        }
+    }
+
+    #[gpui::test]
+    async fn test_pinned_threads_stay_first(cx: &mut TestAppContext) {
+        cx.update(|cx| {
+            let settings_store = SettingsStore::test(cx);
+            cx.set_global(settings_store);
+        });
+
+        let database = cx
+            .update(|cx| ThreadsDatabase::connect_scoped(ThreadsDatabaseScope::Global, cx))
+            .await
+            .unwrap();
+        let pinned_id = acp::SessionId::new("pinned");
+        let newest_id = acp::SessionId::new("newest");
+        let middle_id = acp::SessionId::new("middle");
+        for (id, hours_ago) in [(&pinned_id, 2), (&newest_id, 0), (&middle_id, 1)] {
+            let updated_at = Utc::now() - chrono::Duration::hours(hours_ago);
+            database
+                .save_thread(id.clone(), db_thread(id, updated_at))
+                .await
+                .unwrap();
+        }
+        database.pin_thread(pinned_id.clone()).await.unwrap();
+
+        let fs = FakeFs::new(cx.executor());
+        let project = Project::test(fs, [], cx).await;
+        let text_thread_store =
+            cx.new(|cx| assistant_text_thread::TextThreadStore::fake(project.clone(), cx));
+        let history_store = cx.new(|cx| HistoryStore::new(text_thread_store, cx));
+        cx.run_until_parked();
+
+        let entry_ids = |cx: &mut TestAppContext| {
+            history_store.read_with(cx, |store, _| {
+                store.entries().map(|entry| entry.id()).collect::<Vec<_>>()
+            })
+        };
+        let thread_ids = |ids: [&acp::SessionId; 3]| {
+            ids.map(|id| HistoryEntryId::AcpThread(id.clone())).to_vec()
+        };
+        assert_eq!(
+            entry_ids(cx),
+            thread_ids([&pinned_id, &newest_id, &middle_id])
+        );
+        // Recently opened entries are seeded by recency, regardless of pinning.
+        assert_eq!(
+            history_store.read_with(cx, |store, cx| {
+                store
+                    .recently_opened_entries(cx)
+                    .into_iter()
+                    .map(|entry| entry.id())
+                    .collect::<Vec<_>>()
+            }),
+            thread_ids([&newest_id, &middle_id, &pinned_id])
+        );
+
+        history_store
+            .update(cx, |store, cx| {
+                store.set_thread_pinned(pinned_id.clone(), false, cx)
+            })
+            .await
+            .unwrap();
+        cx.run_until_parked();
+        assert_eq!(
+            entry_ids(cx),
+            thread_ids([&newest_id, &middle_id, &pinned_id])
+        );
+    }
+
+    #[gpui::test]
+    async fn test_scope_follows_project_root(cx: &mut TestAppContext) {
+        cx.update(|cx| {
+            let settings_store = SettingsStore::test(cx);
+            cx.set_global(settings_store);
+            let mut settings = AgentSettings::get_global(cx).clone();
+            settings.thread_storage = ThreadStorage::PerProject;
+            AgentSettings::override_global(settings, cx);
+        });
+
+        let project_scope = ThreadsDatabaseScope::Project(Path::new(path!("/a")).into());
+        let database = cx
+            .update(|cx| ThreadsDatabase::connect_scoped(project_scope.clone(), cx))
+            .await
+            .unwrap();
+        let thread_id = acp::SessionId::new("project-thread");
+        database
+            .save_thread(thread_id.clone(), db_thread(&thread_id, Utc::now()))
+            .await
+            .unwrap();
+
+        let fs = FakeFs::new(cx.executor());
+        fs.insert_tree(path!("/a"), serde_json::json!({})).await;
+        let project = Project::test(fs, [], cx).await;
+        let text_thread_store =
+            cx.new(|cx| assistant_text_thread::TextThreadStore::fake(project.clone(), cx));
+        let history_store = cx.new(|cx| HistoryStore::for_project(text_thread_store, &project, cx));
+        cx.run_until_parked();
+        history_store.read_with(cx, |store, _| {
+            assert_eq!(store.database_scope(), &ThreadsDatabaseScope::Global);
+            assert_eq!(store.entries().count(), 0);
+        });
+
+        project
+            .update(cx, |project, cx| {
+                project.find_or_create_worktree(path!("/a"), true, cx)
+            })
+            .await
+            .unwrap();
+        cx.run_until_parked();
+        history_store.read_with(cx, |store, _| {
+            assert_eq!(store.database_scope(), &project_scope);
+            assert_eq!(
+                store.entries().map(|entry| entry.id()).collect::<Vec<_>>(),
+                [HistoryEntryId::AcpThread(thread_id.clone())]
+            );
+        });
+    }
+}
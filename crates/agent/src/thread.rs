@@ -569,6 +569,9 @@ pub struct ToolCallAuthorization {
     pub tool_call: acp::ToolCallUpdate,
     pub options: Vec<acp::PermissionOption>,
     pub response: oneshot::Sender<acp::PermissionOptionId>,
+    /// Whether the request can be approved automatically when tool actions
+    /// are always allowed.
+    pub respect_always_allow_setting: bool,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -879,6 +882,7 @@ impl Thread {
             }),
             completion_mode: Some(self.completion_mode),
             profile: Some(self.profile_id.clone()),
+            tags: None,
         };
 
         cx.background_spawn(async move {
@@ -1007,7 +1011,7 @@ impl Thread {
         self.add_tool(RestoreFileFromDiskTool::new(self.project.clone()));
         self.add_tool(TerminalTool::new(self.project.clone(), environment));
         self.add_tool(ThinkingTool);
-        self.add_tool(WebSearchTool);
+        self.add_tool(WebSearchTool::new(cx.weak_entity()));
     }
 
     pub fn add_tool<T: AgentTool>(&mut self, tool: T) {
@@ -2488,6 +2492,7 @@ impl ToolCallEventStream {
                         ),
                     ],
                     response: response_tx,
+                    respect_always_allow_setting: true,
                 },
             )))
             .ok();
@@ -2511,6 +2516,43 @@ impl ToolCallEventStream {
             _ => Err(anyhow!("Permission to run tool denied by user")),
         })
     }
+
+    /// Asks the user to allow this one call, even when tool actions are always
+    /// allowed, for calls that a profile requires to be confirmed every time.
+    pub fn confirm(&self, title: impl Into<String>, cx: &mut App) -> Task<Result<()>> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.stream
+            .0
+            .unbounded_send(Ok(ThreadEvent::ToolCallAuthorization(
+                ToolCallAuthorization {
+                    tool_call: acp::ToolCallUpdate::new(
+                        self.tool_use_id.to_string(),
+                        acp::ToolCallUpdateFields::new().title(title.into()),
+                    ),
+                    options: vec![
+                        acp::PermissionOption::new(
+                            acp::PermissionOptionId::new("allow"),
+                            "Allow",
+                            acp::PermissionOptionKind::AllowOnce,
+                        ),
+                        acp::PermissionOption::new(
+                            acp::PermissionOptionId::new("deny"),
+                            "Deny",
+                            acp::PermissionOptionKind::RejectOnce,
+                        ),
+                    ],
+                    response: response_tx,
+                    respect_always_allow_setting: false,
+                },
+            )))
+            .ok();
+        cx.background_spawn(async move {
+            match response_rx.await?.0.as_ref() {
+                "allow" => Ok(()),
+                _ => Err(anyhow!("Permission to run tool denied by user")),
+            }
+        })
+    }
 }
 
 #[cfg(any(test, feature = "test-support"))]
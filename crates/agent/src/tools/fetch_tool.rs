@@ -12,9 +12,15 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use ui::SharedString;
 use util::markdown::MarkdownEscaped;
+use web_search::truncate_text;
 
 use crate::{AgentTool, ToolCallEventStream};
 
+/// The most bytes read from a response. Anything past this is dropped.
+const MAX_RESPONSE_BYTES: usize = 5 * 1024 * 1024;
+/// The most characters returned to the model, roughly 25k tokens.
+const MAX_CONTENT_LENGTH: usize = 100_000;
+
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 enum ContentType {
     Html,
@@ -22,7 +28,8 @@ enum ContentType {
     Json,
 }
 
-/// Fetches a URL and returns the content as Markdown.
+/// Fetches a URL and returns the content as Markdown. Redirects are followed,
+/// and long pages are truncated.
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct FetchToolInput {
     /// The URL to fetch.
@@ -50,9 +57,12 @@ impl FetchTool {
         let mut body = Vec::new();
         response
             .body_mut()
+            .take(MAX_RESPONSE_BYTES as u64 + 1)
             .read_to_end(&mut body)
             .await
             .context("error reading response body")?;
+        let body_truncated = body.len() > MAX_RESPONSE_BYTES;
+        body.truncate(MAX_RESPONSE_BYTES);
 
         if response.status().is_client_error() {
             let text = String::from_utf8_lossy(body.as_slice());
@@ -69,15 +79,11 @@ impl FetchTool {
             .to_str()
             .context("invalid Content-Type header")?;
 
-        let content_type = if content_type.starts_with("text/plain") {
-            ContentType::Plaintext
-        } else if content_type.starts_with("application/json") {
-            ContentType::Json
-        } else {
-            ContentType::Html
+        let Some(content_type) = parse_content_type(content_type) else {
+            bail!("unsupported Content-Type {content_type:?}, only text pages can be fetched");
         };
 
-        match content_type {
+        let text = match content_type {
             ContentType::Html => {
                 let mut handlers: Vec<TagHandler> = vec![
                     Rc::new(RefCell::new(markdown::WebpageChromeRemover)),
@@ -101,7 +107,11 @@ impl FetchTool {
 
                 convert_html_to_markdown(&body[..], &mut handlers)
             }
-            ContentType::Plaintext => Ok(std::str::from_utf8(&body)?.to_owned()),
+            ContentType::Plaintext => Ok(String::from_utf8_lossy(&body).into_owned()),
+            // A JSON document that was cut off can't be parsed, so it's shown as is.
+            ContentType::Json if body_truncated => {
+                Ok(format!("```json\n{}\n```", String::from_utf8_lossy(&body)))
+            }
             ContentType::Json => {
                 let json: serde_json::Value = serde_json::from_slice(&body)?;
 
@@ -110,7 +120,53 @@ impl FetchTool {
                     serde_json::to_string_pretty(&json)?
                 ))
             }
+        }?;
+
+        if text.trim().is_empty() {
+            bail!("no textual content found");
+        }
+        let content = truncate_text(&text, MAX_CONTENT_LENGTH);
+        let truncated = body_truncated || matches!(content, Cow::Owned(_));
+        let mut message = format!("Source: {url}\n\n{content}");
+        if truncated {
+            message.push_str("\n\n[The page was too long and has been truncated.]");
+        }
+        Ok(message)
+    }
+}
+
+/// How a response is converted to text, from its `Content-Type`, or `None` if
+/// it's known not to be text, such as an image or a PDF. Other types are
+/// treated as HTML.
+fn parse_content_type(content_type: &str) -> Option<ContentType> {
+    let mime_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    match mime_type.as_str() {
+        "text/html" | "application/xhtml+xml" => Some(ContentType::Html),
+        "application/json" => Some(ContentType::Json),
+        mime_type if mime_type.ends_with("+json") => Some(ContentType::Json),
+        mime_type
+            if mime_type.starts_with("text/")
+                || mime_type.ends_with("+xml")
+                || mime_type == "application/xml" =>
+        {
+            Some(ContentType::Plaintext)
+        }
+        mime_type
+            if ["image/", "audio/", "video/", "font/"]
+                .iter()
+                .any(|prefix| mime_type.starts_with(prefix)) =>
+        {
+            None
+        }
+        "application/pdf" | "application/octet-stream" | "application/zip" | "application/gzip" => {
+            None
         }
+        _ => Some(ContentType::Html),
     }
 }
 
@@ -145,20 +201,106 @@ impl AgentTool for FetchTool {
     ) -> Task<Result<Self::Output>> {
         let authorize = event_stream.authorize(input.url.clone(), cx);
 
-        let text = cx.background_spawn({
-            let http_client = self.http_client.clone();
-            async move {
-                authorize.await?;
-                Self::build_message(http_client, &input.url).await
-            }
+        let http_client = self.http_client.clone();
+        cx.background_spawn(async move {
+            authorize.await?;
+            Self::build_message(http_client, &input.url).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gpui::TestAppContext;
+    use http_client::{FakeHttpClient, Response};
+
+    #[test]
+    fn test_parse_content_type() {
+        assert_eq!(
+            parse_content_type("text/html; charset=utf-8"),
+            Some(ContentType::Html)
+        );
+        assert_eq!(
+            parse_content_type("application/xhtml+xml"),
+            Some(ContentType::Html)
+        );
+        assert_eq!(
+            parse_content_type("TEXT/PLAIN"),
+            Some(ContentType::Plaintext)
+        );
+        assert_eq!(
+            parse_content_type("text/markdown"),
+            Some(ContentType::Plaintext)
+        );
+        assert_eq!(
+            parse_content_type("application/rss+xml"),
+            Some(ContentType::Plaintext)
+        );
+        assert_eq!(
+            parse_content_type("application/json"),
+            Some(ContentType::Json)
+        );
+        assert_eq!(
+            parse_content_type("application/ld+json"),
+            Some(ContentType::Json)
+        );
+        assert_eq!(parse_content_type("image/png"), None);
+        assert_eq!(parse_content_type("application/pdf"), None);
+        assert_eq!(parse_content_type("application/octet-stream"), None);
+        assert_eq!(
+            parse_content_type("application/x-unknown"),
+            Some(ContentType::Html)
+        );
+    }
+
+    #[gpui::test]
+    async fn test_size_limits(_cx: &mut TestAppContext) {
+        let http_client = FakeHttpClient::create(|request| async move {
+            let (content_type, body) = match request.uri().path() {
+                "/short.txt" => ("text/plain", "short page".to_string()),
+                "/long.txt" => ("text/plain", "a".repeat(MAX_CONTENT_LENGTH + 1)),
+                "/huge.json" => (
+                    "application/json",
+                    format!("[{}", "1,".repeat(MAX_RESPONSE_BYTES)),
+                ),
+                _ => ("text/plain", String::new()),
+            };
+            Ok(Response::builder()
+                .header("content-type", content_type)
+                .body(body.into())?)
         });
 
-        cx.foreground_executor().spawn(async move {
-            let text = text.await?;
-            if text.trim().is_empty() {
-                bail!("no textual content found");
-            }
-            Ok(text)
-        })
+        let message = FetchTool::build_message(http_client.clone(), "example.com/short.txt")
+            .await
+            .unwrap();
+        assert_eq!(
+            message,
+            "Source: https://example.com/short.txt\n\nshort page"
+        );
+
+        let message = FetchTool::build_message(http_client.clone(), "example.com/long.txt")
+            .await
+            .unwrap();
+        assert!(message.ends_with("\n\n[The page was too long and has been truncated.]"));
+        let content = message
+            .strip_prefix("Source: https://example.com/long.txt\n\n")
+            .unwrap()
+            .strip_suffix("\n\n[The page was too long and has been truncated.]")
+            .unwrap();
+        assert_eq!(content.chars().count(), MAX_CONTENT_LENGTH);
+
+        // The JSON is cut off at the byte limit, so it's returned unparsed.
+        let message = FetchTool::build_message(http_client.clone(), "example.com/huge.json")
+            .await
+            .unwrap();
+        assert!(message.starts_with("Source: https://example.com/huge.json\n\n```json\n[1,1,"));
+        assert!(message.ends_with("\n\n[The page was too long and has been truncated.]"));
+
+        assert!(
+            FetchTool::build_message(http_client, "example.com/empty.txt")
+                .await
+                .is_err()
+        );
     }
 }
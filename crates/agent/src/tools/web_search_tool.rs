@@ -1,17 +1,40 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use crate::{AgentTool, ToolCallEventStream};
+use crate::{AgentTool, Thread, ToolCallEventStream};
 use agent_client_protocol as acp;
+use agent_settings::AgentSettings;
 use anyhow::{Result, anyhow};
-use cloud_llm_client::WebSearchResponse;
-use gpui::{App, AppContext, Task};
+use chrono::{DateTime, NaiveDate, Utc};
+use cloud_llm_client::{WebSearchResponse, WebSearchResult};
+use futures::StreamExt as _;
+use gpui::{App, AppContext, AsyncApp, Entity, Task, WeakEntity};
 use language_model::{
-    LanguageModelProviderId, LanguageModelToolResultContent, ZED_CLOUD_PROVIDER_ID,
+    LanguageModel, LanguageModelProviderId, LanguageModelRequest, LanguageModelRequestMessage,
+    LanguageModelToolResultContent, Role,
 };
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use settings::Settings as _;
 use ui::prelude::*;
-use web_search::WebSearchRegistry;
+use util::{ResultExt as _, markdown::MarkdownEscaped};
+use web_search::{
+    PastWebSearch, Recency, SearchKind, WEB_SEARCH_HISTORY, WebSearchOptions, WebSearchRegistry,
+    parse_suggested_queries, plan_queries, query_rewrite_prompt, truncate_text,
+};
+
+/// How long to wait for slower providers when searching all of them.
+const SEARCH_ALL_DEADLINE: Duration = Duration::from_secs(10);
+/// How much of a result's text to show under its link. Some providers return
+/// whole paragraphs.
+const MAX_DESCRIPTION_LENGTH: usize = 300;
+/// The most queries searched by one tool call, counting the main query.
+const MAX_QUERIES: usize = 5;
+/// The most result pages fetched for each query.
+const MAX_FETCH_TOP: usize = 3;
+/// How much of each fetched page is included in the output.
+const MAX_FETCHED_CONTENT_LENGTH: usize = 8_000;
+/// How old past searches returned instead of searching again can be.
+const MAX_PAST_SEARCH_AGE: Duration = Duration::from_secs(7 * 24 * 60 * 60);
 
 /// Search the web for information using your query.
 /// Use this when you need real-time information, facts, or data that might not be in your training.
@@ -20,21 +43,342 @@ use web_search::WebSearchRegistry;
 pub struct WebSearchToolInput {
     /// The search term or question to query on the web.
     query: String,
+    /// Other queries to search at the same time, up to 4, when researching something that needs several searches. Results are grouped by query.
+    #[serde(default)]
+    additional_queries: Vec<String>,
+    /// Skip previously cached results for this query, e.g. when the user asks for the very latest information.
+    #[serde(default)]
+    bypass_cache: bool,
+    /// Query every available search provider and merge their results. Slower, but finds more; use it when thoroughness matters more than speed.
+    #[serde(default)]
+    search_all_providers: bool,
+    /// The most results to return.
+    #[serde(default)]
+    max_results: Option<usize>,
+    /// Which page of results to return, starting at 1. Request the next page to get more results for a query you already searched, instead of searching again.
+    #[serde(default)]
+    page: Option<u32>,
+    /// Use `news` for current events, e.g. "what happened this week", to search news articles instead of general web pages.
+    #[serde(default)]
+    kind: SearchKind,
+    /// Only return results from these domains or their subdomains, e.g. `docs.rs` when looking for a crate's documentation.
+    #[serde(default)]
+    include_domains: Vec<String>,
+    /// Never return results from these domains or their subdomains.
+    #[serde(default)]
+    exclude_domains: Vec<String>,
+    /// Only return results published within the last day, week, month or year.
+    #[serde(default)]
+    recency: Option<Recency>,
+    /// Also search for rephrased versions of the query and combine the results. Use it when the query is phrased as a question or a first search found little.
+    #[serde(default)]
+    expand_query: bool,
+    /// Also fetch the content of the first N results, up to 3, and include it in the output. Use it instead of fetching the top results one by one when the snippets won't be enough.
+    #[serde(default)]
+    fetch_top: usize,
+    /// Return the results of related searches from the past week instead of searching again, when there are any. Use it when the topic was probably looked up recently.
+    #[serde(default)]
+    use_past_searches: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum WebSearchToolOutput {
+    /// The results for each query, when several were searched.
+    Grouped(Vec<QueryResults>),
+    Single(WebSearchResponse),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-#[serde(transparent)]
-pub struct WebSearchToolOutput(WebSearchResponse);
+pub struct QueryResults {
+    query: String,
+    #[serde(flatten)]
+    response: WebSearchResponse,
+    /// Why the search failed, when it did. The other queries' results are
+    /// still returned.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    error: Option<String>,
+    /// When the results come from a past search, when it was run.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    searched_at: Option<DateTime<Utc>>,
+}
+
+impl From<PastWebSearch> for QueryResults {
+    fn from(search: PastWebSearch) -> Self {
+        Self {
+            query: search.query,
+            response: search.response,
+            error: None,
+            searched_at: Some(search.searched_at),
+        }
+    }
+}
+
+/// One of the tool's queries, with the queries sent for it.
+struct PlannedQuery {
+    query: String,
+    /// The query and its rewrites, when it's expanded.
+    expansions: Option<Vec<String>>,
+}
 
 impl From<WebSearchToolOutput> for LanguageModelToolResultContent {
     fn from(value: WebSearchToolOutput) -> Self {
-        serde_json::to_string(&value.0)
+        serde_json::to_string(&value)
             .expect("Failed to serialize WebSearchResponse")
             .into()
     }
 }
 
-pub struct WebSearchTool;
+pub struct WebSearchTool {
+    thread: WeakEntity<Thread>,
+}
+
+impl WebSearchTool {
+    pub fn new(thread: WeakEntity<Thread>) -> Self {
+        Self { thread }
+    }
+
+    /// Plans the queries sent for each of the tool's queries, asking the
+    /// thread's model for rewrites when the query is expanded and there is a
+    /// model.
+    fn plan_searches(&self, input: &WebSearchToolInput, cx: &mut App) -> Task<Vec<PlannedQuery>> {
+        let queries = tool_queries(input);
+        if !input.expand_query {
+            return Task::ready(
+                queries
+                    .into_iter()
+                    .map(|query| PlannedQuery {
+                        query,
+                        expansions: None,
+                    })
+                    .collect(),
+            );
+        }
+        let model = self
+            .thread
+            .read_with(cx, |thread, _| {
+                thread.summarization_model().or(thread.model()).cloned()
+            })
+            .ok()
+            .flatten();
+        cx.spawn(async move |cx| {
+            let cx: &AsyncApp = cx;
+            let plans = queries.into_iter().map(|query| {
+                let model = model.clone();
+                async move {
+                    let suggested_queries = match model {
+                        Some(model) => suggest_queries(model, &query, cx)
+                            .await
+                            .log_err()
+                            .unwrap_or_default(),
+                        None => Vec::new(),
+                    };
+                    PlannedQuery {
+                        expansions: Some(plan_queries(&query, suggested_queries)),
+                        query,
+                    }
+                }
+            });
+            futures::future::join_all(plans).await
+        })
+    }
+
+    /// Searches for one of several queries, without reporting partial results.
+    fn search_query(
+        &self,
+        planned: &PlannedQuery,
+        options: WebSearchOptions,
+        input: &WebSearchToolInput,
+        cx: &mut App,
+    ) -> Task<Result<WebSearchResponse>> {
+        WebSearchRegistry::global(cx).update(cx, |registry, cx| {
+            if let Some(expansions) = &planned.expansions {
+                let deadline = input.search_all_providers.then_some(SEARCH_ALL_DEADLINE);
+                registry.search_queries(
+                    expansions.clone(),
+                    options,
+                    input.bypass_cache,
+                    deadline,
+                    cx,
+                )
+            } else if input.search_all_providers {
+                registry.search_all(
+                    planned.query.clone(),
+                    options,
+                    input.bypass_cache,
+                    SEARCH_ALL_DEADLINE,
+                    cx,
+                )
+            } else {
+                registry.search(planned.query.clone(), options, input.bypass_cache, cx)
+            }
+        })
+    }
+
+    /// Searches for all of `queries` at once, grouping the results by query.
+    fn run_queries(
+        &self,
+        queries: Vec<PlannedQuery>,
+        options: WebSearchOptions,
+        input: &WebSearchToolInput,
+        event_stream: ToolCallEventStream,
+        cx: &mut App,
+    ) -> Task<Result<WebSearchToolOutput>> {
+        let searches = queries
+            .into_iter()
+            .map(|planned| {
+                let search = self.search_query(&planned, options.clone(), input, cx);
+                async move { (planned.query, search.await) }
+            })
+            .collect::<Vec<_>>();
+        let fetch_top = input.fetch_top;
+        let registry = WebSearchRegistry::global(cx);
+        cx.spawn(async move |cx| {
+            let outcomes = futures::future::join_all(searches).await;
+            if outcomes.iter().all(|(_, outcome)| outcome.is_err()) {
+                event_stream
+                    .update_fields(acp::ToolCallUpdateFields::new().title("Web Search Failed"));
+                if let Some((_, Err(error))) = outcomes.into_iter().next() {
+                    return Err(error);
+                }
+                anyhow::bail!("no queries to search");
+            }
+
+            let groups = outcomes
+                .into_iter()
+                .map(|(query, outcome)| match outcome {
+                    Ok(response) => QueryResults {
+                        query,
+                        response,
+                        error: None,
+                        searched_at: None,
+                    },
+                    Err(error) => QueryResults {
+                        query,
+                        response: WebSearchResponse {
+                            results: Vec::new(),
+                            answer: None,
+                        },
+                        error: Some(error.to_string()),
+                        searched_at: None,
+                    },
+                })
+                .collect::<Vec<_>>();
+            let groups = futures::future::join_all(groups.into_iter().map(|mut group| {
+                let registry = registry.clone();
+                let mut cx = cx.clone();
+                async move {
+                    group.response =
+                        fetch_top_results(group.response, fetch_top, &registry, &mut cx).await;
+                    group
+                }
+            }))
+            .await;
+            emit_grouped_update(&groups, &event_stream);
+            Ok(WebSearchToolOutput::Grouped(groups))
+        })
+    }
+
+    /// Whether the thread's profile asks for web searches to be confirmed
+    /// before they're sent to a provider.
+    fn requires_confirmation(&self, cx: &App) -> bool {
+        let Ok(profile_id) = self
+            .thread
+            .read_with(cx, |thread, _| thread.profile().clone())
+        else {
+            return false;
+        };
+        AgentSettings::get_global(cx)
+            .profiles
+            .get(&profile_id)
+            .is_some_and(|profile| profile.confirm_web_search)
+    }
+
+    fn confirm_and_search(
+        self: Arc<Self>,
+        input: WebSearchToolInput,
+        options: WebSearchOptions,
+        event_stream: ToolCallEventStream,
+        cx: &mut App,
+    ) -> Task<Result<WebSearchToolOutput>> {
+        let requires_confirmation = self.requires_confirmation(cx);
+        // Rewrites are planned first so that the confirmation shows every query sent.
+        let plan = self.plan_searches(&input, cx);
+        cx.spawn(async move |cx| {
+            let queries = plan.await;
+            if requires_confirmation {
+                // Profiles that require confirmation override `always_allow_tool_actions`.
+                let confirm = cx.update(|cx| {
+                    event_stream.confirm(confirmation_title(&queries, &input, &options, cx), cx)
+                })?;
+                confirm.await?;
+            }
+            cx.update(|cx| self.search(queries, input, options, event_stream, cx))?
+                .await
+        })
+    }
+
+    fn search(
+        &self,
+        mut queries: Vec<PlannedQuery>,
+        input: WebSearchToolInput,
+        options: WebSearchOptions,
+        event_stream: ToolCallEventStream,
+        cx: &mut App,
+    ) -> Task<Result<WebSearchToolOutput>> {
+        if queries.len() > 1 {
+            return self.run_queries(queries, options, &input, event_stream, cx);
+        }
+        let planned = queries.remove(0);
+        let (partial_responses, search_task) = if planned.expansions.is_some() {
+            (None, self.search_query(&planned, options, &input, cx))
+        } else {
+            WebSearchRegistry::global(cx).update(cx, |registry, cx| {
+                if input.search_all_providers {
+                    let (partial_responses, search_task) = registry.search_all_streaming(
+                        planned.query,
+                        options,
+                        input.bypass_cache,
+                        SEARCH_ALL_DEADLINE,
+                        cx,
+                    );
+                    (Some(partial_responses), search_task)
+                } else {
+                    let search_task =
+                        registry.search(planned.query, options, input.bypass_cache, cx);
+                    (None, search_task)
+                }
+            })
+        };
+        let fetch_top = input.fetch_top;
+        let registry = WebSearchRegistry::global(cx);
+        cx.spawn(async move |cx| {
+            // Show results from the providers that have answered while the others are still searching.
+            if let Some(mut partial_responses) = partial_responses {
+                while let Some(response) = partial_responses.next().await {
+                    emit_results(
+                        format!("Searching the web: {} so far", result_count(&response)),
+                        &response,
+                        &event_stream,
+                    );
+                }
+            }
+
+            let response = match search_task.await {
+                Ok(response) => response,
+                Err(err) => {
+                    event_stream
+                        .update_fields(acp::ToolCallUpdateFields::new().title("Web Search Failed"));
+                    return Err(err);
+                }
+            };
+            let response = fetch_top_results(response, fetch_top, &registry, cx).await;
+
+            emit_update(&response, &event_stream);
+            Ok(WebSearchToolOutput::Single(response))
+        })
+    }
+}
 
 impl AgentTool for WebSearchTool {
     type Input = WebSearchToolInput;
@@ -56,9 +400,11 @@ impl AgentTool for WebSearchTool {
         "Searching the Web".into()
     }
 
-    /// We currently only support Zed Cloud as a provider.
-    fn supports_provider(provider: &LanguageModelProviderId) -> bool {
-        provider == &ZED_CLOUD_PROVIDER_ID
+    /// Searches go to the web search providers in `WebSearchRegistry`, not to
+    /// the model's provider, so every model can use this tool. Running it fails
+    /// when no search provider is registered.
+    fn supports_provider(_provider: &LanguageModelProviderId) -> bool {
+        true
     }
 
     fn run(
@@ -67,23 +413,42 @@ impl AgentTool for WebSearchTool {
         event_stream: ToolCallEventStream,
         cx: &mut App,
     ) -> Task<Result<Self::Output>> {
-        let Some(provider) = WebSearchRegistry::read_global(cx).active_provider() else {
+        if WebSearchRegistry::read_global(cx)
+            .providers()
+            .next()
+            .is_none()
+        {
             return Task::ready(Err(anyhow!("Web search is not available.")));
+        }
+        let options = match search_options(&input, Utc::now().date_naive()) {
+            Ok(options) => options,
+            Err(error) => return Task::ready(Err(error)),
         };
-
-        let search_task = provider.search(input.query, cx);
-        cx.background_spawn(async move {
-            let response = match search_task.await {
-                Ok(response) => response,
-                Err(err) => {
-                    event_stream
-                        .update_fields(acp::ToolCallUpdateFields::new().title("Web Search Failed"));
-                    return Err(err);
-                }
-            };
-
-            emit_update(&response, &event_stream);
-            Ok(WebSearchToolOutput(response))
+        // Fail before asking for confirmation when no provider can apply the filters.
+        if let Some(error) = WebSearchRegistry::read_global(cx).unsupported_options_error(&options)
+        {
+            return Task::ready(Err(error));
+        }
+        if !input.use_past_searches {
+            return self.confirm_and_search(input, options, event_stream, cx);
+        }
+        let query = input.query.clone();
+        let past_searches = cx.background_spawn(async move {
+            WEB_SEARCH_HISTORY.related_searches(&query, MAX_PAST_SEARCH_AGE, MAX_QUERIES)
+        });
+        cx.spawn(async move |cx| {
+            let past_searches = past_searches.await.log_err().unwrap_or_default();
+            if past_searches.is_empty() {
+                return cx
+                    .update(|cx| self.confirm_and_search(input, options, event_stream, cx))?
+                    .await;
+            }
+            let groups = past_searches
+                .into_iter()
+                .map(QueryResults::from)
+                .collect::<Vec<_>>();
+            emit_grouped_update(&groups, &event_stream);
+            Ok(WebSearchToolOutput::Grouped(groups))
         })
     }
 
@@ -94,34 +459,637 @@ impl AgentTool for WebSearchTool {
         event_stream: ToolCallEventStream,
         _cx: &mut App,
     ) -> Result<()> {
-        emit_update(&output.0, &event_stream);
+        match &output {
+            WebSearchToolOutput::Grouped(groups) => emit_grouped_update(groups, &event_stream),
+            WebSearchToolOutput::Single(response) => emit_update(response, &event_stream),
+        }
         Ok(())
     }
 }
 
+async fn suggest_queries(
+    model: Arc<dyn LanguageModel>,
+    query: &str,
+    cx: &AsyncApp,
+) -> Result<Vec<String>> {
+    let request = LanguageModelRequest {
+        messages: vec![LanguageModelRequestMessage {
+            role: Role::User,
+            content: vec![query_rewrite_prompt(query).into()],
+            cache: false,
+            reasoning_details: None,
+        }],
+        ..Default::default()
+    };
+    let mut response = model.stream_completion_text(request, cx).await?;
+    let mut text = String::new();
+    while let Some(chunk) = response.stream.next().await {
+        text.push_str(&chunk?);
+    }
+    Ok(parse_suggested_queries(&text))
+}
+
+/// Fetches the content of the first `count` results into them. Pages that
+/// can't be fetched keep just their snippet.
+async fn fetch_top_results(
+    mut response: WebSearchResponse,
+    count: usize,
+    registry: &Entity<WebSearchRegistry>,
+    cx: &mut AsyncApp,
+) -> WebSearchResponse {
+    let count = count.min(MAX_FETCH_TOP);
+    if count == 0 {
+        return response;
+    }
+    let Some(fetches) = registry
+        .update(cx, |registry, cx| {
+            response
+                .results
+                .iter()
+                .take(count)
+                .map(|result| registry.fetch_content(&result.url, cx))
+                .collect::<Vec<_>>()
+        })
+        .log_err()
+    else {
+        return response;
+    };
+    let contents = futures::future::join_all(fetches).await;
+    for (result, content) in response.results.iter_mut().zip(contents) {
+        result.content = content
+            .log_err()
+            .map(|content| truncate_text(&content, MAX_FETCHED_CONTENT_LENGTH).into_owned());
+    }
+    response
+}
+
+fn search_options(input: &WebSearchToolInput, today: NaiveDate) -> Result<WebSearchOptions> {
+    let parse_domains = |domains: &[String]| {
+        domains
+            .iter()
+            .filter(|domain| !domain.trim().is_empty())
+            .map(|domain| parse_domain(domain))
+            .collect::<Result<Vec<_>>>()
+    };
+    Ok(WebSearchOptions {
+        kind: input.kind,
+        max_results: input.max_results,
+        page: input.page,
+        include_domains: parse_domains(&input.include_domains)?,
+        exclude_domains: parse_domains(&input.exclude_domains)?,
+        published_after: input.recency.map(|recency| recency.earliest_date(today)),
+        ..Default::default()
+    })
+}
+
+/// The host of `domain`, which may also be given as a URL, such as
+/// `https://docs.rs/serde`.
+fn parse_domain(domain: &str) -> Result<String> {
+    let domain = domain.trim();
+    let host = match domain.split_once("://") {
+        Some((_, rest)) => rest,
+        None => domain,
+    };
+    let host = host
+        .split(['/', '?', '#'])
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches('.')
+        .to_lowercase();
+    if host.is_empty() || host.contains(char::is_whitespace) || !host.contains('.') {
+        return Err(anyhow!("{domain:?} is not a domain, such as docs.rs"));
+    }
+    Ok(host)
+}
+
+/// Asks to send the exact queries to the provider that will receive them.
+fn confirmation_title(
+    queries: &[PlannedQuery],
+    input: &WebSearchToolInput,
+    options: &WebSearchOptions,
+    cx: &App,
+) -> String {
+    let queries = queries
+        .iter()
+        .flat_map(|planned| match &planned.expansions {
+            Some(expansions) => expansions.as_slice(),
+            None => std::slice::from_ref(&planned.query),
+        })
+        .map(|query| format!("\"{}\"", MarkdownEscaped(query)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let destination = if input.search_all_providers {
+        "all available search providers".to_string()
+    } else {
+        WebSearchRegistry::read_global(cx)
+            .next_provider(options, cx)
+            .map_or_else(
+                || "a search provider".to_string(),
+                |provider| provider.0.to_string(),
+            )
+    };
+    format!("Search {destination} for {queries}")
+}
+
+/// The main query followed by the additional ones, without blanks or
+/// duplicates, up to `MAX_QUERIES`.
+fn tool_queries(input: &WebSearchToolInput) -> Vec<String> {
+    let mut queries = Vec::new();
+    for query in std::iter::once(&input.query).chain(&input.additional_queries) {
+        let query = query.trim();
+        if !query.is_empty() && !queries.iter().any(|existing| existing == query) {
+            queries.push(query.to_string());
+        }
+    }
+    if queries.is_empty() {
+        queries.push(input.query.clone());
+    }
+    queries.truncate(MAX_QUERIES);
+    queries
+}
+
 fn emit_update(response: &WebSearchResponse, event_stream: &ToolCallEventStream) {
-    let result_text = if response.results.len() == 1 {
+    emit_results(
+        format!("Searched the web: {}", result_count(response)),
+        response,
+        event_stream,
+    );
+}
+
+fn result_count(response: &WebSearchResponse) -> String {
+    if response.results.len() == 1 {
         "1 result".to_string()
     } else {
         format!("{} results", response.results.len())
+    }
+}
+
+fn emit_results(title: String, response: &WebSearchResponse, event_stream: &ToolCallEventStream) {
+    event_stream.update_fields(
+        acp::ToolCallUpdateFields::new()
+            .title(title)
+            .content(response_content(response).collect::<Vec<_>>()),
+    );
+}
+
+/// Shows each query with its results under it.
+fn emit_grouped_update(groups: &[QueryResults], event_stream: &ToolCallEventStream) {
+    let result_count = groups
+        .iter()
+        .map(|group| group.response.results.len())
+        .sum::<usize>();
+    let result_count = if result_count == 1 {
+        "1 result".to_string()
+    } else {
+        format!("{result_count} results")
     };
+    let title = if groups.iter().all(|group| group.searched_at.is_some()) {
+        format!("Found {} past searches: {result_count}", groups.len())
+    } else {
+        format!(
+            "Searched the web for {} queries: {result_count}",
+            groups.len()
+        )
+    };
+    let content = groups
+        .iter()
+        .flat_map(|group| {
+            let query = MarkdownEscaped(&group.query);
+            let heading = match (&group.error, group.searched_at) {
+                (Some(error), _) => format!("**{query}** (failed: {error})"),
+                (None, Some(searched_at)) => {
+                    format!("**{query}** (searched {})", searched_at.format("%Y-%m-%d"))
+                }
+                (None, None) => format!("**{query}**"),
+            };
+            std::iter::once(acp::ToolCallContent::from(heading))
+                .chain(response_content(&group.response))
+        })
+        .collect::<Vec<_>>();
     event_stream.update_fields(
         acp::ToolCallUpdateFields::new()
-            .title(format!("Searched the web: {result_text}"))
-            .content(
-                response
-                    .results
-                    .iter()
-                    .map(|result| {
-                        acp::ToolCallContent::Content(acp::Content::new(
-                            acp::ContentBlock::ResourceLink(
-                                acp::ResourceLink::new(result.title.clone(), result.url.clone())
-                                    .title(result.title.clone())
-                                    .description(result.text.clone()),
-                            ),
-                        ))
-                    })
-                    .collect::<Vec<_>>(),
-            ),
+            .title(title)
+            .content(content),
     );
 }
+
+/// The provider's answer, if it wrote one, followed by links to the results.
+fn response_content(
+    response: &WebSearchResponse,
+) -> impl Iterator<Item = acp::ToolCallContent> + '_ {
+    response
+        .answer
+        .iter()
+        .map(|answer| acp::ToolCallContent::from(answer.clone()))
+        .chain(response.results.iter().map(|result| {
+            acp::ToolCallContent::Content(acp::Content::new(acp::ContentBlock::ResourceLink(
+                acp::ResourceLink::new(result.title.clone(), result.url.clone())
+                    .title(result.title.clone())
+                    .description(result_description(result)),
+            )))
+        }))
+}
+
+/// The start of the result's snippet, preceded by where and when it was
+/// published when the provider says.
+fn result_description(result: &WebSearchResult) -> String {
+    let text = truncate_text(&result.text, MAX_DESCRIPTION_LENGTH);
+    let published_at = result
+        .published_at
+        .map(|published_at| published_at.format("%Y-%m-%d").to_string());
+    let details = result
+        .source
+        .iter()
+        .cloned()
+        .chain(published_at)
+        .collect::<Vec<_>>();
+    if details.is_empty() {
+        text.into_owned()
+    } else {
+        format!("{}\n{text}", details.join(" · "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ContextServerRegistry, Templates};
+    use agent_settings::AgentProfileId;
+    use futures::AsyncReadExt as _;
+    use gpui::TestAppContext;
+    use http_client::{AsyncBody, FakeHttpClient, HttpClient, Response, Url};
+    use language_model::fake_provider::FakeLanguageModel;
+    use parking_lot::Mutex;
+    use project::{FakeFs, Project};
+    use prompt_store::ProjectContext;
+    use util::path;
+    use web_search::{WebContentProvider, WebSearchProvider, WebSearchProviderId};
+
+    #[derive(Default)]
+    struct FakeSearchProvider {
+        searched_queries: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl WebSearchProvider for FakeSearchProvider {
+        fn id(&self) -> WebSearchProviderId {
+            WebSearchProviderId("fake".into())
+        }
+
+        fn search(
+            &self,
+            query: String,
+            _options: WebSearchOptions,
+            _cx: &mut App,
+        ) -> Task<Result<WebSearchResponse>> {
+            self.searched_queries.lock().push(query.clone());
+            Task::ready(Ok(WebSearchResponse {
+                results: ["first", "second", "third", "fourth"]
+                    .into_iter()
+                    .map(|page| WebSearchResult {
+                        title: format!("{query} {page}"),
+                        url: format!("https://example.com/{page}"),
+                        ..Default::default()
+                    })
+                    .collect(),
+                answer: None,
+            }))
+        }
+    }
+
+    struct HttpContentProvider(Arc<dyn HttpClient>);
+
+    impl WebContentProvider for HttpContentProvider {
+        fn id(&self) -> WebSearchProviderId {
+            WebSearchProviderId("http".into())
+        }
+
+        fn fetch_content(&self, url: Url, cx: &mut App) -> Task<Result<String>> {
+            let http_client = self.0.clone();
+            cx.background_spawn(async move {
+                let mut response = http_client
+                    .get(url.as_str(), AsyncBody::default(), false)
+                    .await?;
+                anyhow::ensure!(
+                    response.status().is_success(),
+                    "status {}",
+                    response.status()
+                );
+                let mut body = String::new();
+                response.body_mut().read_to_string(&mut body).await?;
+                Ok(body)
+            })
+        }
+    }
+
+    struct TestTool {
+        tool: Arc<WebSearchTool>,
+        thread: Entity<Thread>,
+        searched_queries: Arc<Mutex<Vec<String>>>,
+    }
+
+    async fn init_test(cx: &mut TestAppContext) -> TestTool {
+        let provider = FakeSearchProvider::default();
+        let searched_queries = provider.searched_queries.clone();
+        cx.update(|cx| {
+            let settings_store = settings::SettingsStore::test(cx);
+            cx.set_global(settings_store);
+            web_search::init(cx);
+            WebSearchRegistry::global(cx).update(cx, |registry, cx| {
+                registry.register_provider(provider, cx);
+            });
+        });
+        let fs = FakeFs::new(cx.executor());
+        fs.insert_tree(path!("/root"), serde_json::json!({})).await;
+        let project = Project::test(fs, [path!("/root").as_ref()], cx).await;
+        let context_server_registry =
+            cx.new(|cx| ContextServerRegistry::new(project.read(cx).context_server_store(), cx));
+        let thread = cx.new(|cx| {
+            Thread::new(
+                project,
+                cx.new(|_cx| ProjectContext::default()),
+                context_server_registry,
+                Templates::new(),
+                Some(Arc::new(FakeLanguageModel::default())),
+                cx,
+            )
+        });
+        TestTool {
+            tool: Arc::new(WebSearchTool::new(thread.downgrade())),
+            thread,
+            searched_queries,
+        }
+    }
+
+    fn require_confirmation(cx: &mut TestAppContext) {
+        cx.update(|cx| {
+            let mut settings = AgentSettings::get_global(cx).clone();
+            settings.always_allow_tool_actions = true;
+            let profile_id = AgentProfileId("write".into());
+            settings
+                .profiles
+                .get_mut(&profile_id)
+                .unwrap()
+                .confirm_web_search = true;
+            AgentSettings::override_global(settings, cx);
+        });
+    }
+
+    fn search_input(input: serde_json::Value) -> WebSearchToolInput {
+        serde_json::from_value(input).unwrap()
+    }
+
+    #[gpui::test]
+    async fn test_confirmation_ignores_always_allow(cx: &mut TestAppContext) {
+        let TestTool { tool, .. } = init_test(cx).await;
+        require_confirmation(cx);
+
+        let (event_stream, mut events) = ToolCallEventStream::test();
+        let search = cx.update(|cx| {
+            tool.clone().run(
+                search_input(serde_json::json!({ "query": "zed" })),
+                event_stream,
+                cx,
+            )
+        });
+        let authorization = events.expect_authorization().await;
+        assert!(!authorization.respect_always_allow_setting);
+        assert_eq!(
+            authorization.tool_call.fields.title.as_deref(),
+            Some("Search fake for \"zed\"")
+        );
+        assert_eq!(
+            authorization
+                .options
+                .iter()
+                .map(|option| option.option_id.0.to_string())
+                .collect::<Vec<_>>(),
+            ["allow", "deny"]
+        );
+        authorization
+            .response
+            .send(acp::PermissionOptionId::new("allow"))
+            .unwrap();
+        let WebSearchToolOutput::Single(response) = search.await.unwrap() else {
+            panic!("expected results for a single query");
+        };
+        assert_eq!(response.results.len(), 4);
+    }
+
+    #[gpui::test]
+    async fn test_confirmation_lists_expanded_queries(cx: &mut TestAppContext) {
+        let TestTool {
+            tool,
+            thread,
+            searched_queries,
+        } = init_test(cx).await;
+        require_confirmation(cx);
+        let model = thread.read_with(cx, |thread, _| thread.model().cloned().unwrap());
+
+        let (event_stream, mut events) = ToolCallEventStream::test();
+        let search = cx.update(|cx| {
+            tool.clone().run(
+                search_input(serde_json::json!({
+                    "query": "how do I pin a tab in zed?",
+                    "expand_query": true
+                })),
+                event_stream,
+                cx,
+            )
+        });
+        cx.run_until_parked();
+        let fake_model = model.as_fake();
+        fake_model.send_last_completion_stream_text_chunk("- zed pinned tabs\n- zed keep tab open");
+        fake_model.end_last_completion_stream();
+
+        let authorization = events.expect_authorization().await;
+        assert!(searched_queries.lock().is_empty());
+        authorization
+            .response
+            .send(acp::PermissionOptionId::new("allow"))
+            .unwrap();
+        search.await.unwrap();
+
+        let title = authorization.tool_call.fields.title.unwrap_or_default();
+        let searched_queries = searched_queries.lock().clone();
+        assert!(searched_queries.len() > 1);
+        assert!(
+            searched_queries
+                .iter()
+                .any(|query| query == "zed pinned tabs")
+        );
+        for query in &searched_queries {
+            assert!(
+                title.contains(&format!("\"{}\"", MarkdownEscaped(query))),
+                "{title:?} doesn't mention {query:?}"
+            );
+        }
+    }
+
+    #[gpui::test]
+    async fn test_fetch_top(cx: &mut TestAppContext) {
+        let TestTool { tool, .. } = init_test(cx).await;
+        let requested_paths = Arc::new(Mutex::new(Vec::new()));
+        let http_client = FakeHttpClient::create({
+            let requested_paths = requested_paths.clone();
+            move |request| {
+                let path = request.uri().path().to_string();
+                requested_paths.lock().push(path.clone());
+                async move {
+                    let response = match path.as_str() {
+                        "/first" => {
+                            Response::new("a".repeat(MAX_FETCHED_CONTENT_LENGTH * 2).into())
+                        }
+                        "/second" => Response::builder().status(500).body(AsyncBody::default())?,
+                        _ => Response::new(format!("content of {path}").into()),
+                    };
+                    Ok(response)
+                }
+            }
+        });
+        cx.update(|cx| {
+            WebSearchRegistry::global(cx).update(cx, |registry, _| {
+                registry.register_content_provider(HttpContentProvider(http_client));
+            });
+        });
+
+        let (event_stream, _events) = ToolCallEventStream::test();
+        let search = cx.update(|cx| {
+            tool.clone().run(
+                search_input(serde_json::json!({ "query": "zed", "fetch_top": 5 })),
+                event_stream,
+                cx,
+            )
+        });
+        let WebSearchToolOutput::Single(response) = search.await.unwrap() else {
+            panic!("expected results for a single query");
+        };
+
+        let mut requested_paths = requested_paths.lock().clone();
+        requested_paths.sort();
+        assert_eq!(requested_paths, ["/first", "/second", "/third"]);
+        let contents = response
+            .results
+            .iter()
+            .map(|result| result.content.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            contents[0].map(|content| content.chars().count()),
+            Some(MAX_FETCHED_CONTENT_LENGTH)
+        );
+        assert_eq!(contents[1..], [None, Some("content of /third"), None]);
+    }
+
+    #[gpui::test]
+    async fn test_past_searches(cx: &mut TestAppContext) {
+        let TestTool { tool, .. } = init_test(cx).await;
+        let past_response = WebSearchResponse {
+            results: vec![WebSearchResult {
+                title: "Graceful shutdown".into(),
+                url: "https://tokio.rs/tokio/topics/shutdown".into(),
+                ..Default::default()
+            }],
+            answer: None,
+        };
+        WEB_SEARCH_HISTORY
+            .record_search(
+                "tokio runtime graceful shutdown",
+                &WebSearchProviderId("fake".into()),
+                &past_response,
+            )
+            .await
+            .unwrap();
+
+        let (event_stream, _events) = ToolCallEventStream::test();
+        let search = cx.update(|cx| {
+            tool.clone().run(
+                search_input(serde_json::json!({
+                    "query": "graceful shutdown of the tokio runtime",
+                    "use_past_searches": true
+                })),
+                event_stream,
+                cx,
+            )
+        });
+        let WebSearchToolOutput::Grouped(groups) = search.await.unwrap() else {
+            panic!("expected past searches");
+        };
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].query, "tokio runtime graceful shutdown");
+        assert_eq!(groups[0].response.results[0].title, "Graceful shutdown");
+        assert!(groups[0].searched_at.is_some());
+
+        // Without related past searches, the web is searched.
+        let (event_stream, _events) = ToolCallEventStream::test();
+        let search = cx.update(|cx| {
+            tool.clone().run(
+                search_input(serde_json::json!({
+                    "query": "serde flatten enum",
+                    "use_past_searches": true
+                })),
+                event_stream,
+                cx,
+            )
+        });
+        let WebSearchToolOutput::Single(response) = search.await.unwrap() else {
+            panic!("expected results for a single query");
+        };
+        assert_eq!(response.results[0].title, "serde flatten enum first");
+    }
+
+    #[test]
+    fn test_tool_queries() {
+        let input = serde_json::from_value::<WebSearchToolInput>(serde_json::json!({
+            "query": "rust async",
+            "additional_queries": ["tokio", " ", "rust async", "smol", "async-std", "glommio", "embassy"]
+        }))
+        .unwrap();
+        assert_eq!(
+            tool_queries(&input),
+            ["rust async", "tokio", "smol", "async-std", "glommio"]
+        );
+    }
+
+    #[test]
+    fn test_search_options() {
+        let input = serde_json::from_value::<WebSearchToolInput>(serde_json::json!({
+            "query": "serde",
+            "include_domains": ["docs.rs", "https://Serde.rs/derive.html", " "],
+            "exclude_domains": ["www.reddit.com/r/rust"],
+            "recency": "week"
+        }))
+        .unwrap();
+        let today = NaiveDate::from_ymd_opt(2024, 3, 10).unwrap();
+        let options = search_options(&input, today).unwrap();
+        assert_eq!(options.include_domains, ["docs.rs", "serde.rs"]);
+        assert_eq!(options.exclude_domains, ["www.reddit.com"]);
+        assert_eq!(options.published_after, NaiveDate::from_ymd_opt(2024, 3, 3));
+
+        let input = serde_json::from_value::<WebSearchToolInput>(serde_json::json!({
+            "query": "serde",
+            "include_domains": ["rust docs"]
+        }))
+        .unwrap();
+        assert!(search_options(&input, today).is_err());
+    }
+
+    #[test]
+    fn test_output_deserialization() {
+        let single = serde_json::json!({ "results": [] });
+        assert!(matches!(
+            serde_json::from_value::<WebSearchToolOutput>(single).unwrap(),
+            WebSearchToolOutput::Single(_)
+        ));
+
+        let grouped = serde_json::json!([
+            { "query": "tokio", "results": [] },
+            { "query": "smol", "results": [], "error": "no providers" }
+        ]);
+        let WebSearchToolOutput::Grouped(groups) =
+            serde_json::from_value::<WebSearchToolOutput>(grouped).unwrap()
+        else {
+            panic!("expected grouped results");
+        };
+        assert_eq!(groups[1].query, "smol");
+        assert_eq!(groups[1].error.as_deref(), Some("no providers"));
+    }
+}
@@ -70,6 +70,9 @@ impl AgentProfile {
         let default_model = base_profile
             .as_ref()
             .and_then(|profile| profile.default_model.clone());
+        let confirm_web_search = base_profile
+            .as_ref()
+            .is_some_and(|profile| profile.confirm_web_search);
 
         let profile_settings = AgentProfileSettings {
             name: name.into(),
@@ -77,6 +80,7 @@ impl AgentProfile {
             enable_all_context_servers,
             context_servers,
             default_model,
+            confirm_web_search,
         };
 
         update_settings_file(fs, cx, {
@@ -109,6 +113,8 @@ pub struct AgentProfileSettings {
     pub context_servers: IndexMap<Arc<str>, ContextServerPreset>,
     /// Default language model to apply when this profile becomes active.
     pub default_model: Option<LanguageModelSelection>,
+    /// Whether web searches ask for confirmation before queries are sent.
+    pub confirm_web_search: bool,
 }
 
 impl AgentProfileSettings {
@@ -158,6 +164,7 @@ impl AgentProfileSettings {
                     })
                     .collect(),
                 default_model: self.default_model.clone(),
+                confirm_web_search: Some(self.confirm_web_search),
             },
         );
 
@@ -173,6 +180,7 @@ impl From<AgentProfileContent> for AgentProfileSettings {
             enable_all_context_servers,
             context_servers,
             default_model,
+            confirm_web_search,
         } = content;
 
         Self {
@@ -184,6 +192,7 @@ impl From<AgentProfileContent> for AgentProfileSettings {
                 .map(|(server_id, preset)| (server_id, preset.into()))
                 .collect(),
             default_model,
+            confirm_web_search: confirm_web_search.unwrap_or_default(),
         }
     }
 }
@@ -10,7 +10,7 @@ use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use settings::{
     DefaultAgentView, DockPosition, DockSide, LanguageModelParameters, LanguageModelSelection,
-    NotifyWhenAgentWaiting, RegisterSetting, Settings,
+    NotifyWhenAgentWaiting, RegisterSetting, Settings, ThreadStorage,
 };
 
 pub use crate::agent_profile::*;
@@ -47,6 +47,7 @@ pub struct AgentSettings {
     pub expand_terminal_card: bool,
     pub use_modifier_to_send: bool,
     pub message_editor_min_lines: usize,
+    pub thread_storage: ThreadStorage,
 }
 
 impl AgentSettings {
@@ -183,6 +184,7 @@ impl Settings for AgentSettings {
             expand_terminal_card: agent.expand_terminal_card.unwrap(),
             use_modifier_to_send: agent.use_modifier_to_send.unwrap(),
             message_editor_min_lines: agent.message_editor_min_lines.unwrap(),
+            thread_storage: agent.thread_storage.unwrap(),
         }
     }
 }
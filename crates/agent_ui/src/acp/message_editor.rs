@@ -2152,6 +2152,7 @@ mod tests {
             id: acp::SessionId::new("thread-123"),
             title: "Previous Conversation".into(),
             updated_at: chrono::Utc::now(),
+            pinned: false,
         };
 
         let message_editor = cx.update(|window, cx| {
@@ -4706,6 +4706,7 @@ impl AcpThreadView {
                                     id,
                                     title: name.into(),
                                     updated_at: Default::default(),
+                                    pinned: false,
                                 },
                                 window,
                                 cx,
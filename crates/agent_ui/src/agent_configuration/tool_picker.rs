@@ -315,6 +315,7 @@ impl PickerDelegate for ToolPickerDelegate {
                             })
                             .collect(),
                         default_model: default_profile.default_model.clone(),
+                        confirm_web_search: Some(default_profile.confirm_web_search),
                     });
 
                 if let Some(server_id) = server_id {
@@ -430,6 +430,7 @@ mod tests {
     use project::DisableAiSettings;
     use settings::{
         DefaultAgentView, DockPosition, DockSide, NotifyWhenAgentWaiting, Settings, SettingsStore,
+        ThreadStorage,
     };
 
     #[gpui::test]
@@ -471,6 +472,7 @@ mod tests {
             expand_terminal_card: true,
             use_modifier_to_send: true,
             message_editor_min_lines: 1,
+            thread_storage: ThreadStorage::Global,
         };
 
         cx.update(|cx| {
@@ -141,7 +141,7 @@ impl AgentsPanel {
     ) -> Self {
         let focus_handle = cx.focus_handle();
 
-        let history_store = cx.new(|cx| HistoryStore::new(text_thread_store, cx));
+        let history_store = cx.new(|cx| HistoryStore::for_project(text_thread_store, &project, cx));
         let history = cx.new(|cx| AcpThreadHistory::new(history_store.clone(), window, cx));
 
         let this = cx.weak_entity();
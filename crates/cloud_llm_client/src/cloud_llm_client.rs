@@ -329,13 +329,31 @@ pub struct WebSearchBody {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct WebSearchResponse {
     pub results: Vec<WebSearchResult>,
+    /// An answer to the query that the provider wrote from its results, as
+    /// Markdown with its citations linked to their sources.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub answer: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct WebSearchResult {
     pub title: String,
     pub url: String,
     pub text: String,
+    /// When the page was published, if the provider knows.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub published_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// The name of the site the page belongs to, falling back to its domain.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub source: Option<String>,
+    /// The provider's relevance score. Scales differ between providers.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub score: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub favicon_url: Option<String>,
+    /// The page's content as Markdown, when it was fetched after searching.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub content: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
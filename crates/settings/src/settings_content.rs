@@ -160,6 +160,9 @@ pub struct SettingsContent {
 
     /// Settings related to Vim mode in Zed.
     pub vim: Option<VimSettingsContent>,
+
+    /// Settings for the agent's web search.
+    pub web_search: Option<WebSearchSettingsContent>,
 }
 
 impl SettingsContent {
@@ -748,6 +751,61 @@ pub enum HourFormat {
     Hour24,
 }
 
+/// Settings for the agent's web search.
+#[with_fallible_options]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema, MergeFrom, PartialEq)]
+pub struct WebSearchSettingsContent {
+    /// How long search results are reused for identical queries, in minutes.
+    /// Set to 0 to disable caching.
+    ///
+    /// Default: 60
+    pub cache_ttl_minutes: Option<u64>,
+    /// The id of the provider to search with when it's available, such as "zed.dev".
+    /// When unset, the first provider to be registered is used.
+    ///
+    /// Default: null
+    pub default_provider: Option<String>,
+    /// Settings for individual search providers, keyed by provider id.
+    ///
+    /// Default: {}
+    pub providers: Option<HashMap<String, WebSearchProviderSettingsContent>>,
+}
+
+#[with_fallible_options]
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema, MergeFrom, PartialEq)]
+pub struct WebSearchProviderSettingsContent {
+    /// The most requests to send to this provider in any one minute.
+    pub requests_per_minute: Option<u32>,
+    /// The most requests to send to this provider in a calendar month.
+    pub monthly_quota: Option<u32>,
+    /// What one request to this provider costs, in US dollars, for
+    /// estimating spending.
+    pub cost_per_request: Option<f64>,
+    /// How much to spend on this provider in a calendar month, in US dollars.
+    /// Once it's spent, other providers are preferred over this one.
+    pub monthly_budget: Option<f64>,
+    /// The URL of the provider's API, such as the address of a self-hosted
+    /// SearXNG instance.
+    pub api_url: Option<String>,
+    /// The id of the search engine to query, for providers that host
+    /// several, such as a Google Programmable Search Engine's `cx` id.
+    pub search_engine_id: Option<String>,
+    /// How long to wait for a response from this provider, in seconds.
+    ///
+    /// Default: 30
+    pub request_timeout_secs: Option<u64>,
+    /// How many times to retry a request that timed out, failed to connect
+    /// or got a server error.
+    ///
+    /// Default: 2
+    pub max_retries: Option<u32>,
+    /// How long to wait before the first retry, in milliseconds. Each
+    /// further retry waits twice as long as the one before.
+    ///
+    /// Default: 500
+    pub retry_backoff_ms: Option<u64>,
+}
+
 #[with_fallible_options]
 #[derive(Clone, Default, Serialize, Deserialize, JsonSchema, MergeFrom, Debug, PartialEq)]
 pub struct OutlinePanelSettingsContent {
@@ -116,6 +116,11 @@ pub struct AgentSettingsContent {
     ///
     /// Default: 4
     pub message_editor_min_lines: Option<usize>,
+    /// Whether threads from every project are stored together, or each project
+    /// keeps its own threads.
+    ///
+    /// Default: global
+    pub thread_storage: Option<ThreadStorage>,
 }
 
 impl AgentSettingsContent {
@@ -190,6 +195,11 @@ pub struct AgentProfileContent {
     pub context_servers: IndexMap<Arc<str>, ContextServerPresetContent>,
     /// The default language model selected when using this profile.
     pub default_model: Option<LanguageModelSelection>,
+    /// Whether to ask before each web search, showing the queries and the
+    /// search provider they'll be sent to.
+    ///
+    /// Default: false
+    pub confirm_web_search: Option<bool>,
 }
 
 #[with_fallible_options]
@@ -206,6 +216,19 @@ pub enum DefaultAgentView {
     TextThread,
 }
 
+#[derive(
+    Copy, Clone, Default, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema, MergeFrom,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ThreadStorage {
+    /// Threads from every project share one database.
+    #[default]
+    Global,
+    /// Each project's threads are stored in a database of their own, keyed by
+    /// the project's first worktree.
+    PerProject,
+}
+
 #[derive(
     Copy,
     Clone,
@@ -214,6 +214,7 @@ impl VsCodeSettings {
             title_bar: None,
             vim: None,
             vim_mode: None,
+            web_search: None,
             workspace: self.workspace_settings_content(),
         }
     }
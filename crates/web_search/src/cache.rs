@@ -0,0 +1,188 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use cloud_llm_client::WebSearchResponse;
+use db::{
+    query,
+    sqlez::{domain::Domain, thread_safe_connection::ThreadSafeConnection},
+    sqlez_macros::sql,
+};
+
+use crate::{WebSearchOptions, WebSearchProviderId};
+
+pub struct WebSearchCache(ThreadSafeConnection);
+
+impl Domain for WebSearchCache {
+    const NAME: &str = stringify!(WebSearchCache);
+
+    const MIGRATIONS: &[&str] = &[sql!(
+        CREATE TABLE IF NOT EXISTS web_search_cache(
+            provider TEXT NOT NULL,
+            query TEXT NOT NULL,
+            options TEXT NOT NULL,
+            response TEXT NOT NULL,
+            cached_at INTEGER DEFAULT (unixepoch()) NOT NULL,
+            PRIMARY KEY(provider, query, options)
+        ) STRICT;
+    )];
+}
+
+db::static_connection!(WEB_SEARCH_CACHE, WebSearchCache, []);
+
+impl WebSearchCache {
+    /// Returns the response `provider` gave for `query` with `options` if it
+    /// was cached less than `ttl` ago.
+    pub fn cached_response(
+        &self,
+        provider: &WebSearchProviderId,
+        query: &str,
+        options: &WebSearchOptions,
+        ttl: Duration,
+    ) -> Result<Option<WebSearchResponse>> {
+        let Some(response) = self.select_response(
+            provider.0.to_string(),
+            normalize_query(query),
+            serde_json::to_string(options)?,
+            ttl.as_secs() as i64,
+        )?
+        else {
+            return Ok(None);
+        };
+        Ok(Some(serde_json::from_str(&response)?))
+    }
+
+    /// Caches `response`, dropping entries that are older than `ttl`.
+    pub async fn save_response(
+        &self,
+        provider: &WebSearchProviderId,
+        query: &str,
+        options: &WebSearchOptions,
+        response: &WebSearchResponse,
+        ttl: Duration,
+    ) -> Result<()> {
+        self.insert_response(
+            provider.0.to_string(),
+            normalize_query(query),
+            serde_json::to_string(options)?,
+            serde_json::to_string(response)?,
+        )
+        .await?;
+        self.delete_expired(ttl.as_secs() as i64).await
+    }
+
+    query! {
+        fn select_response(provider: String, query: String, options: String, ttl_secs: i64) -> Result<Option<String>> {
+            SELECT response FROM web_search_cache
+            WHERE provider = (?) AND query = (?) AND options = (?) AND cached_at > unixepoch() - (?)
+        }
+    }
+
+    query! {
+        async fn insert_response(provider: String, query: String, options: String, response: String) -> Result<()> {
+            INSERT OR REPLACE INTO web_search_cache(provider, query, options, response) VALUES ((?), (?), (?), (?))
+        }
+    }
+
+    query! {
+        async fn delete_expired(ttl_secs: i64) -> Result<()> {
+            DELETE FROM web_search_cache WHERE cached_at <= unixepoch() - (?)
+        }
+    }
+
+    query! {
+        pub async fn clear() -> Result<()> {
+            DELETE FROM web_search_cache
+        }
+    }
+}
+
+/// Queries that only differ in case or whitespace share a cache entry.
+fn normalize_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use cloud_llm_client::WebSearchResult;
+
+    use super::*;
+
+    fn response(title: &str) -> WebSearchResponse {
+        WebSearchResponse {
+            results: vec![WebSearchResult {
+                title: title.to_string(),
+                url: "https://example.com".to_string(),
+                ..Default::default()
+            }],
+            answer: None,
+        }
+    }
+
+    #[gpui::test]
+    async fn test_cached_responses() {
+        let cache = WebSearchCache::open_test_db("test_cached_responses").await;
+        let provider = WebSearchProviderId("test".into());
+        let ttl = Duration::from_secs(60);
+        let options = WebSearchOptions::default();
+
+        assert!(
+            cache
+                .cached_response(&provider, "rust async", &options, ttl)
+                .unwrap()
+                .is_none()
+        );
+
+        cache
+            .save_response(
+                &provider,
+                "Rust  async",
+                &options,
+                &response("Async Rust"),
+                ttl,
+            )
+            .await
+            .unwrap();
+
+        let cached = cache
+            .cached_response(&provider, " rust ASYNC", &options, ttl)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cached.results[0].title, "Async Rust");
+
+        let other_provider = WebSearchProviderId("other".into());
+        assert!(
+            cache
+                .cached_response(&other_provider, "rust async", &options, ttl)
+                .unwrap()
+                .is_none()
+        );
+        let narrower_options = WebSearchOptions {
+            max_results: Some(1),
+            ..Default::default()
+        };
+        assert!(
+            cache
+                .cached_response(&provider, "rust async", &narrower_options, ttl)
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            cache
+                .cached_response(&provider, "rust async", &options, Duration::ZERO)
+                .unwrap()
+                .is_none()
+        );
+
+        cache.clear().await.unwrap();
+        assert!(
+            cache
+                .cached_response(&provider, "rust async", &options, ttl)
+                .unwrap()
+                .is_none()
+        );
+    }
+}
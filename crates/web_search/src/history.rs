@@ -0,0 +1,222 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use cloud_llm_client::WebSearchResponse;
+use collections::HashSet;
+use db::{
+    query,
+    sqlez::{domain::Domain, thread_safe_connection::ThreadSafeConnection},
+    sqlez_macros::sql,
+};
+
+use crate::WebSearchProviderId;
+
+/// How long past searches are kept.
+const HISTORY_RETENTION: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+/// How many recent searches are compared against a query.
+const MAX_CANDIDATE_SEARCHES: usize = 1000;
+/// How many results' titles are matched against, along with the query.
+const MATCHED_RESULT_COUNT: usize = 5;
+/// The fraction of a query's words that a past search must contain to be related.
+const MIN_MATCHING_WORD_FRACTION: f32 = 0.6;
+
+pub struct WebSearchHistory(ThreadSafeConnection);
+
+impl Domain for WebSearchHistory {
+    const NAME: &str = stringify!(WebSearchHistory);
+
+    const MIGRATIONS: &[&str] = &[sql!(
+        CREATE TABLE IF NOT EXISTS web_searches(
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            query TEXT NOT NULL,
+            provider TEXT NOT NULL,
+            response TEXT NOT NULL,
+            matched_text TEXT NOT NULL,
+            searched_at INTEGER DEFAULT (unixepoch()) NOT NULL
+        ) STRICT;
+        CREATE INDEX IF NOT EXISTS web_searches_searched_at ON web_searches(searched_at);
+    )];
+}
+
+db::static_connection!(WEB_SEARCH_HISTORY, WebSearchHistory, []);
+
+/// A search that was run before, found with [`WebSearchHistory::related_searches`].
+#[derive(Clone, Debug)]
+pub struct PastWebSearch {
+    pub query: String,
+    pub provider: WebSearchProviderId,
+    pub response: WebSearchResponse,
+    pub searched_at: DateTime<Utc>,
+}
+
+impl WebSearchHistory {
+    /// Records a search, dropping searches older than the retention period.
+    pub async fn record_search(
+        &self,
+        query: &str,
+        provider: &WebSearchProviderId,
+        response: &WebSearchResponse,
+    ) -> Result<()> {
+        let matched_text = std::iter::once(query)
+            .chain(
+                response
+                    .results
+                    .iter()
+                    .take(MATCHED_RESULT_COUNT)
+                    .map(|result| result.title.as_str()),
+            )
+            .collect::<Vec<_>>()
+            .join("\n");
+        self.insert_search(
+            query.to_string(),
+            provider.0.to_string(),
+            serde_json::to_string(response)?,
+            matched_text,
+        )
+        .await?;
+        self.delete_searches_before(HISTORY_RETENTION.as_secs() as i64)
+            .await
+    }
+
+    /// Searches from the last `max_age` that share most of their words with
+    /// `query`, either in their own query or their top results' titles. The
+    /// closest matches come first, followed by the most recent.
+    pub fn related_searches(
+        &self,
+        query: &str,
+        max_age: Duration,
+        limit: usize,
+    ) -> Result<Vec<PastWebSearch>> {
+        let query_words = words(query);
+        if query_words.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut related_searches = Vec::new();
+        for (past_query, provider, response, matched_text, searched_at) in
+            self.select_recent_searches(max_age.as_secs() as i64, MAX_CANDIDATE_SEARCHES as i64)?
+        {
+            let matched_words = words(&matched_text);
+            let matching_word_fraction = query_words
+                .iter()
+                .filter(|word| matched_words.contains(*word))
+                .count() as f32
+                / query_words.len() as f32;
+            if matching_word_fraction < MIN_MATCHING_WORD_FRACTION {
+                continue;
+            }
+            let Some(searched_at) = DateTime::from_timestamp(searched_at, 0) else {
+                continue;
+            };
+            related_searches.push((
+                matching_word_fraction,
+                PastWebSearch {
+                    query: past_query,
+                    provider: WebSearchProviderId(provider.into()),
+                    response: serde_json::from_str(&response)?,
+                    searched_at,
+                },
+            ));
+        }
+
+        // Candidates are ordered newest first, and the sort is stable.
+        related_searches.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        Ok(related_searches
+            .into_iter()
+            .take(limit)
+            .map(|(_, search)| search)
+            .collect())
+    }
+
+    query! {
+        async fn insert_search(query: String, provider: String, response: String, matched_text: String) -> Result<()> {
+            INSERT INTO web_searches(query, provider, response, matched_text) VALUES ((?), (?), (?), (?))
+        }
+    }
+
+    query! {
+        fn select_recent_searches(max_age_secs: i64, limit: i64) -> Result<Vec<(String, String, String, String, i64)>> {
+            SELECT query, provider, response, matched_text, searched_at FROM web_searches
+            WHERE searched_at > unixepoch() - (?)
+            ORDER BY searched_at DESC, id DESC
+            LIMIT (?)
+        }
+    }
+
+    query! {
+        async fn delete_searches_before(retention_secs: i64) -> Result<()> {
+            DELETE FROM web_searches WHERE searched_at <= unixepoch() - (?)
+        }
+    }
+
+    query! {
+        pub async fn clear() -> Result<()> {
+            DELETE FROM web_searches
+        }
+    }
+}
+
+fn words(text: &str) -> HashSet<String> {
+    text.split(|character: char| !character.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use cloud_llm_client::WebSearchResult;
+
+    use super::*;
+
+    fn response(title: &str) -> WebSearchResponse {
+        WebSearchResponse {
+            results: vec![WebSearchResult {
+                title: title.to_string(),
+                url: "https://example.com".to_string(),
+                ..Default::default()
+            }],
+            answer: None,
+        }
+    }
+
+    #[gpui::test]
+    async fn test_related_searches() {
+        let history = WebSearchHistory::open_test_db("test_related_searches").await;
+        let provider = WebSearchProviderId("test".into());
+        let max_age = Duration::from_secs(60 * 60);
+
+        history
+            .record_search(
+                "tokio spawn blocking",
+                &provider,
+                &response("Running blocking code in Tokio"),
+            )
+            .await
+            .unwrap();
+        history
+            .record_search("rust borrow checker", &provider, &response("The Rust Book"))
+            .await
+            .unwrap();
+
+        let queries = |query: &str| {
+            history
+                .related_searches(query, max_age, 10)
+                .unwrap()
+                .into_iter()
+                .map(|search| search.query)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(queries("Tokio spawn_blocking"), ["tokio spawn blocking"]);
+        assert_eq!(queries("running blocking code"), ["tokio spawn blocking"]);
+        assert_eq!(queries("rust"), ["rust borrow checker"]);
+        assert!(queries("python asyncio").is_empty());
+        assert!(
+            history
+                .related_searches("tokio", Duration::ZERO, 10)
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -0,0 +1,265 @@
+use cloud_llm_client::{WebSearchResponse, WebSearchResult};
+use collections::HashMap;
+use url::Url;
+
+/// The `k` in reciprocal rank fusion, which keeps a single provider's top
+/// result from outweighing results that several providers agree on.
+const RANK_FUSION_OFFSET: f32 = 60.;
+
+/// Shorter titles, like "Introduction", are too generic to identify a page.
+const MIN_TITLE_WORDS_FOR_MATCHING: usize = 3;
+
+/// Query parameters that only identify where a click came from.
+const TRACKING_PARAMETERS: &[&str] = &[
+    "fbclid", "gclid", "dclid", "msclkid", "yclid", "igshid", "mc_cid", "mc_eid", "_hsenc",
+    "_hsmi", "ref_src",
+];
+
+/// Merges responses from several providers into one, ranking results by
+/// reciprocal rank fusion. Duplicate results are combined, keeping the first
+/// response's copy, and so is the first response's answer.
+pub(crate) fn merge_responses(responses: Vec<WebSearchResponse>) -> WebSearchResponse {
+    let mut scored_results = Vec::<(WebSearchResult, f32)>::new();
+    let mut duplicates = DuplicateIndex::default();
+    let mut answer = None;
+    for response in responses {
+        if answer.is_none() {
+            answer = response.answer;
+        }
+        for (rank, result) in response.results.into_iter().enumerate() {
+            let score = 1. / (RANK_FUSION_OFFSET + rank as f32);
+            if let Some(index) = duplicates.find(&result) {
+                scored_results[index].1 += score;
+            } else {
+                duplicates.insert(&result, scored_results.len());
+                scored_results.push((result, score));
+            }
+        }
+    }
+
+    scored_results.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+    WebSearchResponse {
+        results: scored_results
+            .into_iter()
+            .map(|(result, _)| result)
+            .collect(),
+        answer,
+    }
+}
+
+/// Strips tracking parameters from result URLs and drops results that
+/// duplicate an earlier one.
+pub(crate) fn deduplicate_results(results: Vec<WebSearchResult>) -> Vec<WebSearchResult> {
+    let mut deduplicated_results = Vec::with_capacity(results.len());
+    let mut duplicates = DuplicateIndex::default();
+    for mut result in results {
+        result.url = strip_tracking_parameters(&result.url);
+        if duplicates.find(&result).is_none() {
+            duplicates.insert(&result, deduplicated_results.len());
+            deduplicated_results.push(result);
+        }
+    }
+    deduplicated_results
+}
+
+/// Finds results that point at the same page, either by URL or because they
+/// have the same title on the same site.
+#[derive(Default)]
+struct DuplicateIndex {
+    indices_by_key: HashMap<String, usize>,
+}
+
+impl DuplicateIndex {
+    fn find(&self, result: &WebSearchResult) -> Option<usize> {
+        duplicate_keys(result).find_map(|key| self.indices_by_key.get(&key).copied())
+    }
+
+    fn insert(&mut self, result: &WebSearchResult, index: usize) {
+        for key in duplicate_keys(result) {
+            self.indices_by_key.entry(key).or_insert(index);
+        }
+    }
+}
+
+fn duplicate_keys(result: &WebSearchResult) -> impl Iterator<Item = String> {
+    let url_key = canonical_url(&result.url);
+    let title_key = Url::parse(result.url.trim()).ok().and_then(|url| {
+        let title_words = normalized_title_words(&result.title);
+        (title_words.len() >= MIN_TITLE_WORDS_FOR_MATCHING)
+            .then(|| format!("{}\n{}", canonical_host(&url), title_words.join(" ")))
+    });
+    std::iter::once(url_key).chain(title_key)
+}
+
+/// A key under which URLs that point at the same page compare equal: the
+/// scheme, fragment, trailing slash and tracking parameters are ignored, and
+/// query parameters can appear in any order.
+pub(crate) fn canonical_url(url: &str) -> String {
+    let Ok(url) = Url::parse(url.trim()) else {
+        return url.trim().to_string();
+    };
+    let path = url.path().trim_end_matches('/');
+    let mut query_pairs = url
+        .query_pairs()
+        .filter(|(name, _)| !is_tracking_parameter(name))
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect::<Vec<_>>();
+    query_pairs.sort();
+    let host = canonical_host(&url);
+    if query_pairs.is_empty() {
+        format!("{host}{path}")
+    } else {
+        format!("{host}{path}?{}", query_pairs.join("&"))
+    }
+}
+
+fn canonical_host(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    let host = host
+        .strip_prefix("www.")
+        .or_else(|| host.strip_prefix("m."))
+        .unwrap_or(host);
+    match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    }
+}
+
+fn strip_tracking_parameters(url: &str) -> String {
+    let Ok(mut parsed_url) = Url::parse(url.trim()) else {
+        return url.to_string();
+    };
+    if !parsed_url
+        .query_pairs()
+        .any(|(name, _)| is_tracking_parameter(&name))
+    {
+        return url.to_string();
+    }
+
+    let query_pairs = parsed_url
+        .query_pairs()
+        .filter(|(name, _)| !is_tracking_parameter(name))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect::<Vec<_>>();
+    if query_pairs.is_empty() {
+        parsed_url.set_query(None);
+    } else {
+        parsed_url
+            .query_pairs_mut()
+            .clear()
+            .extend_pairs(query_pairs);
+    }
+    parsed_url.to_string()
+}
+
+fn is_tracking_parameter(name: &str) -> bool {
+    name.starts_with("utm_") || TRACKING_PARAMETERS.contains(&name)
+}
+
+/// The title's words in lowercase, without a trailing site name such as the
+/// " | Example Blog" in "Some Article | Example Blog".
+fn normalized_title_words(title: &str) -> Vec<String> {
+    let title = [" | ", " - ", " — ", " · "]
+        .iter()
+        .filter_map(|separator| title.rsplit_once(separator).map(|(title, _)| title))
+        .max_by_key(|title| title.len())
+        .unwrap_or(title);
+    title
+        .split(|character: char| !character.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(title: &str, url: &str) -> WebSearchResult {
+        WebSearchResult {
+            title: title.to_string(),
+            url: url.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn response(urls: &[&str]) -> WebSearchResponse {
+        WebSearchResponse {
+            results: urls.iter().map(|url| result(url, url)).collect(),
+            answer: None,
+        }
+    }
+
+    #[test]
+    fn test_merge_responses() {
+        let merged = merge_responses(vec![
+            response(&["https://a.com", "https://b.com/page", "https://c.com"]),
+            response(&["https://d.com", "http://B.com/page/#intro"]),
+        ]);
+        let titles = merged
+            .results
+            .iter()
+            .map(|result| result.title.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            titles,
+            [
+                "https://b.com/page",
+                "https://a.com",
+                "https://d.com",
+                "https://c.com"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_canonical_url() {
+        assert_eq!(
+            canonical_url("https://www.example.com/post/?b=2&utm_source=feed&a=1#comments"),
+            canonical_url("http://example.com/post?a=1&b=2&fbclid=abc"),
+        );
+        assert_eq!(
+            canonical_url("https://m.example.com/post"),
+            canonical_url("https://example.com/post"),
+        );
+        assert_ne!(
+            canonical_url("https://example.com/post?page=2"),
+            canonical_url("https://example.com/post"),
+        );
+        assert_ne!(
+            canonical_url("https://blog.example.com/post"),
+            canonical_url("https://example.com/post"),
+        );
+    }
+
+    #[test]
+    fn test_deduplicate_results() {
+        let results = deduplicate_results(vec![
+            result(
+                "Async Rust in Practice | Example Blog",
+                "https://example.com/async?utm_source=feed&id=1",
+            ),
+            result(
+                "Async Rust in Practice",
+                "https://www.example.com/posts/async-rust",
+            ),
+            result("async rust in practice", "https://other.com/async-rust"),
+            result("Introduction", "https://example.com/book/intro"),
+            result("Introduction", "https://example.com/guide/intro"),
+            result("Async Rust", "https://example.com/async?id=1&gclid=xyz"),
+        ]);
+        let urls = results
+            .iter()
+            .map(|result| result.url.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/async?id=1",
+                "https://other.com/async-rust",
+                "https://example.com/book/intro",
+                "https://example.com/guide/intro",
+            ]
+        );
+    }
+}
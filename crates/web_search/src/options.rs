@@ -0,0 +1,192 @@
+use chrono::{Days, Months, NaiveDate};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::WebSearchProviderCapabilities;
+
+/// Refinements to a web search. Providers apply the ones their API supports.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct WebSearchOptions {
+    pub kind: SearchKind,
+    pub max_results: Option<usize>,
+    /// Which page of results to return, starting at 1. Unset means the first page.
+    pub page: Option<u32>,
+    /// Only return results from these domains or their subdomains.
+    pub include_domains: Vec<String>,
+    /// Never return results from these domains or their subdomains.
+    pub exclude_domains: Vec<String>,
+    pub published_after: Option<NaiveDate>,
+    pub published_before: Option<NaiveDate>,
+    /// An ISO 639-1 language code, such as "en".
+    pub language: Option<String>,
+    /// An ISO 3166-1 alpha-2 country code, such as "us".
+    pub region: Option<String>,
+    pub safe_search: Option<SafeSearch>,
+    pub search_depth: Option<SearchDepth>,
+}
+
+/// Which index of the provider to search.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchKind {
+    /// General web pages.
+    #[default]
+    Web,
+    /// Recent news articles.
+    News,
+}
+
+/// How recently results must have been published.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Recency {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl Recency {
+    /// The earliest publication date within this period, counting back from
+    /// `today`.
+    pub fn earliest_date(self, today: NaiveDate) -> NaiveDate {
+        let date = match self {
+            Recency::Day => today.checked_sub_days(Days::new(1)),
+            Recency::Week => today.checked_sub_days(Days::new(7)),
+            Recency::Month => today.checked_sub_months(Months::new(1)),
+            Recency::Year => today.checked_sub_months(Months::new(12)),
+        };
+        date.unwrap_or(NaiveDate::MIN)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SafeSearch {
+    Off,
+    Moderate,
+    Strict,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchDepth {
+    Basic,
+    Advanced,
+}
+
+impl WebSearchOptions {
+    /// The provider capabilities needed to apply these options.
+    pub fn required_capabilities(&self) -> WebSearchProviderCapabilities {
+        WebSearchProviderCapabilities {
+            news: self.kind == SearchKind::News,
+            domain_filters: !self.include_domains.is_empty() || !self.exclude_domains.is_empty(),
+            pagination: self.page.is_some_and(|page| page > 1),
+            freshness: self.published_after.is_some() || self.published_before.is_some(),
+            region: self.region.is_some(),
+            safe_search: self.safe_search.is_some(),
+        }
+    }
+
+    /// Whether a result at `url` passes the domain filters, for providers that
+    /// can't filter by domain themselves.
+    pub fn allows_url(&self, url: &str) -> bool {
+        let Some(host) = Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_lowercase))
+        else {
+            return self.include_domains.is_empty();
+        };
+        let matches_domain = |domain: &String| {
+            let domain = domain.trim().trim_start_matches("www.").to_lowercase();
+            host == domain || host.ends_with(&format!(".{domain}"))
+        };
+        (self.include_domains.is_empty() || self.include_domains.iter().any(matches_domain))
+            && !self.exclude_domains.iter().any(matches_domain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows_url() {
+        let options = WebSearchOptions {
+            include_domains: vec!["rust-lang.org".into(), "www.docs.rs".into()],
+            exclude_domains: vec!["blog.rust-lang.org".into()],
+            ..Default::default()
+        };
+        assert!(options.allows_url("https://doc.rust-lang.org/std"));
+        assert!(options.allows_url("https://docs.rs/gpui"));
+        assert!(!options.allows_url("https://blog.rust-lang.org/2024"));
+        assert!(!options.allows_url("https://notrust-lang.org"));
+        assert!(!options.allows_url("not a url"));
+        assert!(WebSearchOptions::default().allows_url("not a url"));
+    }
+
+    #[test]
+    fn test_recency_earliest_date() {
+        let today = NaiveDate::from_ymd_opt(2024, 3, 31).unwrap();
+        assert_eq!(
+            Recency::Day.earliest_date(today),
+            NaiveDate::from_ymd_opt(2024, 3, 30).unwrap()
+        );
+        assert_eq!(
+            Recency::Week.earliest_date(today),
+            NaiveDate::from_ymd_opt(2024, 3, 24).unwrap()
+        );
+        // Months without the day end on their last day instead.
+        assert_eq!(
+            Recency::Month.earliest_date(today),
+            NaiveDate::from_ymd_opt(2024, 2, 29).unwrap()
+        );
+        assert_eq!(
+            Recency::Year.earliest_date(today),
+            NaiveDate::from_ymd_opt(2023, 3, 31).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_required_capabilities() {
+        assert_eq!(
+            WebSearchOptions::default().required_capabilities(),
+            WebSearchProviderCapabilities::default()
+        );
+        assert_eq!(
+            WebSearchOptions {
+                page: Some(1),
+                ..Default::default()
+            }
+            .required_capabilities(),
+            WebSearchProviderCapabilities::default()
+        );
+        assert_eq!(
+            WebSearchOptions {
+                kind: SearchKind::News,
+                page: Some(2),
+                ..Default::default()
+            }
+            .required_capabilities(),
+            WebSearchProviderCapabilities {
+                news: true,
+                pagination: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            WebSearchOptions {
+                region: Some("de".into()),
+                safe_search: Some(SafeSearch::Strict),
+                ..Default::default()
+            }
+            .required_capabilities(),
+            WebSearchProviderCapabilities {
+                region: true,
+                safe_search: true,
+                ..Default::default()
+            }
+        );
+    }
+}
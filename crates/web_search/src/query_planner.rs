@@ -0,0 +1,188 @@
+/// The most queries a single search is expanded into, including the original.
+pub const MAX_PLANNED_QUERIES: usize = 3;
+
+/// Conversational openings that search engines match literally rather than
+/// ignoring.
+const CONVERSATIONAL_PREFIXES: &[&str] = &[
+    "can you tell me",
+    "could you tell me",
+    "i want to know",
+    "i would like to know",
+    "please tell me",
+    "tell me",
+    "can you find",
+    "could you find",
+    "please find",
+    "search the web for",
+    "search for",
+    "look up",
+    "please",
+];
+
+/// Abbreviations that pages tend to spell out.
+const ABBREVIATIONS: &[(&str, &str)] = &[
+    ("js", "javascript"),
+    ("ts", "typescript"),
+    ("py", "python"),
+    ("k8s", "kubernetes"),
+    ("db", "database"),
+    ("docs", "documentation"),
+    ("repo", "repository"),
+    ("env", "environment"),
+    ("config", "configuration"),
+];
+
+/// The queries to search for `query`: the query itself, followed by
+/// `suggested_queries` (such as rewrites from a language model) and then
+/// rule-based rewrites, without duplicates and at most
+/// [`MAX_PLANNED_QUERIES`].
+pub fn plan_queries(
+    query: &str,
+    suggested_queries: impl IntoIterator<Item = String>,
+) -> Vec<String> {
+    let mut queries = Vec::<String>::new();
+    let candidates = std::iter::once(normalize_whitespace(query))
+        .chain(
+            suggested_queries
+                .into_iter()
+                .map(|query| normalize_whitespace(&query)),
+        )
+        .chain(rewrite_query(query));
+    for candidate in candidates {
+        if queries.len() == MAX_PLANNED_QUERIES {
+            break;
+        }
+        if !candidate.is_empty()
+            && !queries
+                .iter()
+                .any(|query| query.eq_ignore_ascii_case(&candidate))
+        {
+            queries.push(candidate);
+        }
+    }
+    queries
+}
+
+/// A prompt asking a language model for better search queries, whose
+/// response can be read with [`parse_suggested_queries`].
+pub fn query_rewrite_prompt(query: &str) -> String {
+    format!(
+        "Rewrite the following web search query into up to {} alternative queries that a search engine would answer well. \
+        Prefer specific keywords over conversational phrasing and keep any names, versions and error messages exactly. \
+        Respond with one query per line and nothing else.\n\nQuery: {query}",
+        MAX_PLANNED_QUERIES - 1
+    )
+}
+
+/// The queries in a response to [`query_rewrite_prompt`], ignoring list
+/// markers and surrounding quotes.
+pub fn parse_suggested_queries(response: &str) -> Vec<String> {
+    response
+        .lines()
+        .map(|line| strip_list_marker(line).trim_matches('"').trim().to_string())
+        .filter(|query| !query.is_empty())
+        .collect()
+}
+
+fn strip_list_marker(line: &str) -> &str {
+    let line = line.trim();
+    if let Some(rest) = line.strip_prefix(['-', '*']) {
+        return rest.trim_start();
+    }
+    let number_len = line.len()
+        - line
+            .trim_start_matches(|character: char| character.is_ascii_digit())
+            .len();
+    match line[number_len..].strip_prefix(['.', ')']) {
+        Some(rest) if number_len > 0 && rest.starts_with(' ') => rest.trim_start(),
+        _ => line,
+    }
+}
+
+/// Rule-based rewrites of `query`: one without conversational phrasing, and
+/// one with common abbreviations spelled out.
+fn rewrite_query(query: &str) -> Vec<String> {
+    let keywords = keyword_query(query);
+    let expanded = expand_abbreviations(&keywords);
+    vec![keywords, expanded]
+}
+
+fn keyword_query(query: &str) -> String {
+    let query = normalize_whitespace(query);
+    let query = CONVERSATIONAL_PREFIXES
+        .iter()
+        .find_map(|prefix| strip_word_prefix(&query, prefix))
+        .unwrap_or(&query);
+    let query = match ["how do i", "how can i"]
+        .iter()
+        .find_map(|prefix| strip_word_prefix(query, prefix))
+    {
+        Some(rest) => format!("how to {rest}"),
+        None => query.to_string(),
+    };
+    query
+        .trim_end_matches(|character: char| matches!(character, '?' | '.' | '!'))
+        .to_string()
+}
+
+/// Strips `prefix` from the start of `query`, ignoring case, when it's
+/// followed by a space.
+fn strip_word_prefix<'a>(query: &'a str, prefix: &str) -> Option<&'a str> {
+    query
+        .get(..prefix.len())
+        .filter(|start| start.eq_ignore_ascii_case(prefix))
+        .and_then(|_| query[prefix.len()..].strip_prefix(' '))
+}
+
+fn expand_abbreviations(query: &str) -> String {
+    query
+        .split(' ')
+        .map(|word| {
+            ABBREVIATIONS
+                .iter()
+                .find(|(abbreviation, _)| word.eq_ignore_ascii_case(abbreviation))
+                .map_or(word, |(_, expansion)| expansion)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn normalize_whitespace(query: &str) -> String {
+    query.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_queries() {
+        assert_eq!(
+            plan_queries("Can you tell me how do I  parse JSON in JS?", []),
+            [
+                "Can you tell me how do I parse JSON in JS?",
+                "how to parse JSON in JS",
+                "how to parse JSON in javascript",
+            ]
+        );
+        assert_eq!(
+            parse_suggested_queries("2024 election results\n2) election results"),
+            ["2024 election results", "election results"]
+        );
+        assert_eq!(
+            plan_queries("rust borrow checker", []),
+            ["rust borrow checker"]
+        );
+        assert_eq!(
+            plan_queries(
+                "tokio docs",
+                parse_suggested_queries("1. \"tokio runtime documentation\"\n- Tokio docs\n\n"),
+            ),
+            [
+                "tokio docs",
+                "tokio runtime documentation",
+                "tokio documentation"
+            ]
+        );
+    }
+}
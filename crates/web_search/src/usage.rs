@@ -0,0 +1,133 @@
+use anyhow::Result;
+use chrono::Utc;
+use collections::HashMap;
+use db::{
+    query,
+    sqlez::{domain::Domain, thread_safe_connection::ThreadSafeConnection},
+    sqlez_macros::sql,
+};
+
+use crate::WebSearchProviderId;
+
+pub struct WebSearchUsage(ThreadSafeConnection);
+
+impl Domain for WebSearchUsage {
+    const NAME: &str = stringify!(WebSearchUsage);
+
+    const MIGRATIONS: &[&str] = &[sql!(
+        CREATE TABLE IF NOT EXISTS web_search_usage(
+            provider TEXT NOT NULL,
+            month TEXT NOT NULL,
+            request_count INTEGER NOT NULL,
+            cost REAL NOT NULL DEFAULT 0,
+            PRIMARY KEY(provider, month)
+        ) STRICT;
+    )];
+}
+
+/// Requests sent to a provider in a calendar month, and what they cost.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WebSearchMonthlyUsage {
+    pub request_count: u32,
+    /// The estimated cost in US dollars, from the provider's `cost_per_request` setting.
+    pub cost: f64,
+}
+
+db::static_connection!(WEB_SEARCH_USAGE, WebSearchUsage, []);
+
+impl WebSearchUsage {
+    /// The usage of every provider in `month`, formatted like `2024-03`.
+    pub async fn usage_in_month(
+        &self,
+        month: String,
+    ) -> Result<HashMap<WebSearchProviderId, WebSearchMonthlyUsage>> {
+        Ok(self
+            .select_month_usage(month)
+            .await?
+            .into_iter()
+            .map(|(provider, request_count, cost)| {
+                (
+                    WebSearchProviderId(provider.into()),
+                    WebSearchMonthlyUsage {
+                        request_count,
+                        cost,
+                    },
+                )
+            })
+            .collect())
+    }
+
+    /// Records a request to `provider` in `month` that cost `cost` US dollars.
+    pub async fn record_request(
+        &self,
+        provider: WebSearchProviderId,
+        month: String,
+        cost: f64,
+    ) -> Result<()> {
+        self.increment_usage(provider.0.to_string(), month, cost)
+            .await
+    }
+
+    query! {
+        async fn select_month_usage(month: String) -> Result<Vec<(String, u32, f64)>> {
+            SELECT provider, request_count, cost FROM web_search_usage
+            WHERE month = (?)
+        }
+    }
+
+    query! {
+        async fn increment_usage(provider: String, month: String, cost: f64) -> Result<()> {
+            INSERT INTO web_search_usage(provider, month, request_count, cost)
+            VALUES (?1, ?2, 1, ?3)
+            ON CONFLICT(provider, month) DO UPDATE SET
+                request_count = request_count + 1,
+                cost = cost + ?3
+        }
+    }
+}
+
+/// The current calendar month (UTC), as stored in the usage table.
+pub(crate) fn current_month() -> String {
+    Utc::now().format("%Y-%m").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[gpui::test]
+    async fn test_usage_in_month() {
+        let usage = WebSearchUsage::open_test_db("test_usage_in_month").await;
+        let provider = WebSearchProviderId("test".into());
+        assert!(
+            usage
+                .usage_in_month("2024-03".into())
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        usage
+            .record_request(provider.clone(), "2024-03".into(), 0.25)
+            .await
+            .unwrap();
+        usage
+            .record_request(provider.clone(), "2024-03".into(), 0.5)
+            .await
+            .unwrap();
+        usage
+            .record_request(provider.clone(), "2024-04".into(), 1.)
+            .await
+            .unwrap();
+        assert_eq!(
+            usage.usage_in_month("2024-03".into()).await.unwrap(),
+            HashMap::from_iter([(
+                provider,
+                WebSearchMonthlyUsage {
+                    request_count: 2,
+                    cost: 0.75,
+                }
+            )])
+        );
+    }
+}
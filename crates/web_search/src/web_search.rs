@@ -1,21 +1,235 @@
-use std::sync::Arc;
+mod cache;
+mod history;
+mod merge;
+mod options;
+mod query_planner;
+mod usage;
 
-use anyhow::Result;
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, anyhow};
 use cloud_llm_client::WebSearchResponse;
 use collections::HashMap;
-use gpui::{App, AppContext as _, Context, Entity, Global, SharedString, Task};
+use futures::{FutureExt as _, StreamExt as _, channel::mpsc, stream::FuturesUnordered};
+use gpui::{
+    App, AppContext as _, AsyncApp, Context, Entity, Global, SharedString, Task, WeakEntity,
+};
+use merge::{deduplicate_results, merge_responses};
+use settings::{RegisterSetting, Settings, SettingsStore};
+use unicode_segmentation::UnicodeSegmentation as _;
+use url::Url;
+use util::ResultExt as _;
+
+pub use cache::*;
+pub use history::*;
+pub use options::*;
+pub use query_planner::*;
+pub use usage::*;
+
+/// The window that `requests_per_minute` limits are counted over.
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+/// How many failures in a row make a provider get skipped.
+const CIRCUIT_BREAKER_THRESHOLD: u32 = 3;
+/// How long a repeatedly failing provider is skipped for.
+const CIRCUIT_BREAKER_COOLDOWN: Duration = Duration::from_secs(60);
 
 pub fn init(cx: &mut App) {
-    let registry = cx.new(|_cx| WebSearchRegistry::default());
+    let registry = cx.new(|cx| {
+        cx.observe_global::<SettingsStore>(|registry, cx| registry.select_default_provider(cx))
+            .detach();
+        let (usage_tx, usage_rx) = mpsc::unbounded();
+        cx.spawn(async move |registry, cx| {
+            record_usage(registry, usage_rx, cx).await;
+        })
+        .detach();
+        WebSearchRegistry {
+            usage_tx: Some(usage_tx),
+            ..Default::default()
+        }
+    });
     cx.set_global(GlobalWebSearchRegistry(registry));
 }
 
+#[derive(Clone, Debug, RegisterSetting)]
+pub struct WebSearchSettings {
+    /// How long search results are reused for identical queries.
+    pub cache_ttl: Duration,
+    /// The provider to search with when it's registered.
+    pub default_provider: Option<WebSearchProviderId>,
+    /// Settings for individual search providers.
+    pub providers: HashMap<WebSearchProviderId, WebSearchProviderSettings>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct WebSearchProviderSettings {
+    /// The most requests to send to this provider in any one minute.
+    pub requests_per_minute: Option<u32>,
+    /// The most requests to send to this provider in a calendar month.
+    pub monthly_quota: Option<u32>,
+    /// What one request costs, in US dollars.
+    pub cost_per_request: Option<f64>,
+    /// How much to spend on this provider in a calendar month, in US dollars.
+    pub monthly_budget: Option<f64>,
+    /// The URL of the provider's API, for self-hosted providers.
+    pub api_url: Option<String>,
+    /// The id of the search engine to query, for providers that host several.
+    pub search_engine_id: Option<String>,
+    pub request_timeout: Option<Duration>,
+    pub max_retries: Option<u32>,
+    /// How long to wait before the first retry, doubling for each one after.
+    pub retry_backoff: Option<Duration>,
+}
+
+impl Settings for WebSearchSettings {
+    fn from_settings(content: &settings::SettingsContent) -> Self {
+        let web_search = content.web_search.clone().unwrap();
+        Self {
+            cache_ttl: Duration::from_secs(web_search.cache_ttl_minutes.unwrap() * 60),
+            default_provider: web_search
+                .default_provider
+                .map(|id| WebSearchProviderId(id.into())),
+            providers: web_search
+                .providers
+                .unwrap()
+                .into_iter()
+                .map(|(id, provider)| {
+                    (
+                        WebSearchProviderId(id.into()),
+                        WebSearchProviderSettings {
+                            requests_per_minute: provider.requests_per_minute,
+                            monthly_quota: provider.monthly_quota,
+                            cost_per_request: provider.cost_per_request,
+                            monthly_budget: provider.monthly_budget,
+                            api_url: provider.api_url,
+                            search_engine_id: provider.search_engine_id,
+                            request_timeout: provider.request_timeout_secs.map(Duration::from_secs),
+                            max_retries: provider.max_retries,
+                            retry_backoff: provider.retry_backoff_ms.map(Duration::from_millis),
+                        },
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+/// How much of its configured request limits and budget a provider has used.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WebSearchQuota {
+    pub requests_last_minute: u32,
+    pub requests_per_minute: Option<u32>,
+    pub requests_this_month: u32,
+    pub monthly_quota: Option<u32>,
+    /// The estimated spending this month, in US dollars.
+    pub cost_this_month: f64,
+    pub monthly_budget: Option<f64>,
+    /// When the provider last reported [`WebSearchQuotaExceeded`], the time
+    /// it can be retried.
+    pub rejected_until: Option<Instant>,
+}
+
+impl WebSearchQuota {
+    pub fn is_exhausted(&self) -> bool {
+        self.requests_per_minute
+            .is_some_and(|limit| self.requests_last_minute >= limit)
+            || self
+                .monthly_quota
+                .is_some_and(|quota| self.requests_this_month >= quota)
+            || self
+                .rejected_until
+                .is_some_and(|rejected_until| Instant::now() < rejected_until)
+    }
+
+    /// Whether this month's estimated spending has reached the budget.
+    /// Providers over budget are only used when no other provider is available.
+    pub fn is_over_budget(&self) -> bool {
+        self.monthly_budget
+            .is_some_and(|budget| self.cost_this_month >= budget)
+    }
+}
+
 #[derive(Clone, Eq, PartialEq, Hash, Debug, Ord, PartialOrd)]
 pub struct WebSearchProviderId(pub SharedString);
 
+/// An error for providers to return when their service rejected a request
+/// because a rate limit or quota was reached. The provider is skipped until
+/// `retry_after` has passed, and the search is retried with another provider.
+#[derive(Debug, thiserror::Error)]
+#[error("web search quota exceeded: {message}")]
+pub struct WebSearchQuotaExceeded {
+    pub message: String,
+    pub retry_after: Option<Duration>,
+}
+
+/// Which [`WebSearchOptions`] a provider honors, so that requests using an
+/// option only go to providers that apply it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WebSearchProviderCapabilities {
+    pub news: bool,
+    pub domain_filters: bool,
+    pub pagination: bool,
+    pub freshness: bool,
+    pub region: bool,
+    pub safe_search: bool,
+}
+
+impl WebSearchProviderCapabilities {
+    /// Whether a provider with these capabilities has all of `required`.
+    pub fn supports(&self, required: &Self) -> bool {
+        (self.news || !required.news)
+            && (self.domain_filters || !required.domain_filters)
+            && (self.pagination || !required.pagination)
+            && (self.freshness || !required.freshness)
+            && (self.region || !required.region)
+            && (self.safe_search || !required.safe_search)
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        [
+            (self.news, "news search"),
+            (self.domain_filters, "domain filters"),
+            (self.pagination, "pagination"),
+            (self.freshness, "date filters"),
+            (self.region, "region targeting"),
+            (self.safe_search, "safe search"),
+        ]
+        .into_iter()
+        .filter_map(|(enabled, name)| enabled.then_some(name))
+        .collect()
+    }
+}
+
 pub trait WebSearchProvider {
     fn id(&self) -> WebSearchProviderId;
-    fn search(&self, query: String, cx: &mut App) -> Task<Result<WebSearchResponse>>;
+    /// The options this provider applies. Requests that use any other option
+    /// are sent to a different provider.
+    fn capabilities(&self) -> WebSearchProviderCapabilities {
+        WebSearchProviderCapabilities::default()
+    }
+    /// Whether this provider is a last resort, to be used only when no other
+    /// provider can search.
+    fn is_fallback(&self) -> bool {
+        false
+    }
+    fn search(
+        &self,
+        query: String,
+        options: WebSearchOptions,
+        cx: &mut App,
+    ) -> Task<Result<WebSearchResponse>>;
+}
+
+/// Fetches the readable content of web pages, such as search results, for
+/// reading beyond their snippets.
+pub trait WebContentProvider {
+    fn id(&self) -> WebSearchProviderId;
+    /// The content of the page at `url`, as Markdown.
+    fn fetch_content(&self, url: Url, cx: &mut App) -> Task<Result<String>>;
 }
 
 struct GlobalWebSearchRegistry(Entity<WebSearchRegistry>);
@@ -26,6 +240,106 @@ impl Global for GlobalWebSearchRegistry {}
 pub struct WebSearchRegistry {
     providers: HashMap<WebSearchProviderId, Arc<dyn WebSearchProvider>>,
     active_provider: Option<Arc<dyn WebSearchProvider>>,
+    recent_requests: HashMap<WebSearchProviderId, VecDeque<Instant>>,
+    failures: HashMap<WebSearchProviderId, ProviderFailures>,
+    rejected_until: HashMap<WebSearchProviderId, Instant>,
+    content_providers: HashMap<WebSearchProviderId, Arc<dyn WebContentProvider>>,
+    monthly_usage: MonthlyUsage,
+    usage_tx: Option<mpsc::UnboundedSender<UsageRecord>>,
+}
+
+/// The requests sent to each provider this month, so that quotas can be
+/// checked without reading the database.
+#[derive(Default)]
+struct MonthlyUsage {
+    month: String,
+    providers: HashMap<WebSearchProviderId, WebSearchMonthlyUsage>,
+}
+
+impl MonthlyUsage {
+    fn get(&self, provider: &WebSearchProviderId, month: &str) -> WebSearchMonthlyUsage {
+        if self.month == month {
+            self.providers.get(provider).copied().unwrap_or_default()
+        } else {
+            WebSearchMonthlyUsage::default()
+        }
+    }
+
+    fn add(&mut self, month: &str, provider: WebSearchProviderId, usage: WebSearchMonthlyUsage) {
+        if self.month.as_str() > month {
+            return;
+        }
+        if self.month != month {
+            self.month = month.to_string();
+            self.providers.clear();
+        }
+        let total = self.providers.entry(provider).or_default();
+        total.request_count += usage.request_count;
+        total.cost += usage.cost;
+    }
+}
+
+struct UsageRecord {
+    provider: WebSearchProviderId,
+    month: String,
+    cost: f64,
+}
+
+/// Loads this month's usage into the registry, then saves the requests it
+/// records. Saving only starts once loading is done, so the loaded usage never
+/// includes requests that the registry has already counted.
+async fn record_usage(
+    registry: WeakEntity<WebSearchRegistry>,
+    mut usage_rx: mpsc::UnboundedReceiver<UsageRecord>,
+    cx: &mut AsyncApp,
+) {
+    let month = current_month();
+    let usage = cx
+        .background_spawn({
+            let month = month.clone();
+            async move { WEB_SEARCH_USAGE.usage_in_month(month).await }
+        })
+        .await
+        .log_err()
+        .unwrap_or_default();
+    if registry
+        .update(cx, |registry, _| {
+            for (provider, usage) in usage {
+                registry.monthly_usage.add(&month, provider, usage);
+            }
+        })
+        .is_err()
+    {
+        return;
+    }
+    cx.background_spawn(async move {
+        while let Some(record) = usage_rx.next().await {
+            WEB_SEARCH_USAGE
+                .record_request(record.provider, record.month, record.cost)
+                .await
+                .log_err();
+        }
+    })
+    .await;
+}
+
+#[derive(Debug, Default)]
+struct ProviderFailures {
+    consecutive_failures: u32,
+    circuit_opened_at: Option<Instant>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WebSearchProviderHealth {
+    Healthy,
+    /// Recent requests failed, but not often enough to stop using the provider.
+    Degraded {
+        consecutive_failures: u32,
+    },
+    /// The provider failed too many times in a row and is skipped until `retry_at`.
+    Unavailable {
+        retry_at: Instant,
+    },
 }
 
 impl WebSearchRegistry {
@@ -45,28 +359,1025 @@ impl WebSearchRegistry {
         self.active_provider.clone()
     }
 
+    /// How much of its request limits and budget `provider` has used.
+    pub fn quota(&self, provider: &WebSearchProviderId, cx: &App) -> WebSearchQuota {
+        let settings = WebSearchSettings::get_global(cx)
+            .providers
+            .get(provider)
+            .cloned()
+            .unwrap_or_default();
+        let requests_last_minute = self.recent_requests.get(provider).map_or(0, |requests| {
+            requests
+                .iter()
+                .filter(|sent_at| sent_at.elapsed() < RATE_LIMIT_WINDOW)
+                .count() as u32
+        });
+        let usage = self.monthly_usage.get(provider, &current_month());
+        WebSearchQuota {
+            requests_last_minute,
+            requests_per_minute: settings.requests_per_minute,
+            requests_this_month: usage.request_count,
+            monthly_quota: settings.monthly_quota,
+            cost_this_month: usage.cost,
+            monthly_budget: settings.monthly_budget,
+            rejected_until: self.rejected_until.get(provider).copied(),
+        }
+    }
+
+    /// The estimated spending on every registered provider this month, in US dollars.
+    pub fn total_cost_this_month(&self, cx: &App) -> f64 {
+        self.providers
+            .keys()
+            .map(|provider| self.quota(provider, cx).cost_this_month)
+            .sum()
+    }
+
+    /// Searches with the active provider, reusing a cached response for the
+    /// same query unless `bypass_cache` is set. When the active provider has
+    /// used up its quota or keeps failing, another provider is used instead,
+    /// including when it rejects the search with [`WebSearchQuotaExceeded`].
+    pub fn search(
+        &mut self,
+        query: String,
+        options: WebSearchOptions,
+        bypass_cache: bool,
+        cx: &mut Context<Self>,
+    ) -> Task<Result<WebSearchResponse>> {
+        let provider = match self.available_provider(&options, cx) {
+            Ok(provider) => provider,
+            Err(error) => return Task::ready(Err(error)),
+        };
+        let mut search =
+            self.search_provider(provider, query.clone(), options.clone(), bypass_cache, cx);
+        cx.spawn(async move |this, cx| {
+            loop {
+                let error = match search.await {
+                    Err(error) if error.is::<WebSearchQuotaExceeded>() => error,
+                    result => return result,
+                };
+                // The provider that rejected the search is now skipped, so
+                // this ends once no other provider is left.
+                let Ok(provider) =
+                    this.update(cx, |this, cx| this.available_provider(&options, cx))?
+                else {
+                    return Err(error);
+                };
+                search = this.update(cx, |this, cx| {
+                    this.search_provider(provider, query.clone(), options.clone(), bypass_cache, cx)
+                })?;
+            }
+        })
+    }
+
+    /// Queries every available provider at once and merges
+    /// their results, leaving out providers that haven't responded within
+    /// `deadline`.
+    pub fn search_all(
+        &mut self,
+        query: String,
+        options: WebSearchOptions,
+        bypass_cache: bool,
+        deadline: Duration,
+        cx: &mut Context<Self>,
+    ) -> Task<Result<WebSearchResponse>> {
+        self.search_all_streaming(query, options, bypass_cache, deadline, cx)
+            .1
+    }
+
+    /// Like [`Self::search_all`], but also sends the results merged so far
+    /// each time another provider responds.
+    pub fn search_all_streaming(
+        &mut self,
+        query: String,
+        options: WebSearchOptions,
+        bypass_cache: bool,
+        deadline: Duration,
+        cx: &mut Context<Self>,
+    ) -> (
+        mpsc::UnboundedReceiver<WebSearchResponse>,
+        Task<Result<WebSearchResponse>>,
+    ) {
+        let (partial_responses_tx, partial_responses_rx) = mpsc::unbounded();
+        let required_capabilities = options.required_capabilities();
+        let mut providers = self
+            .providers
+            .values()
+            .filter(|provider| {
+                provider.capabilities().supports(&required_capabilities)
+                    && self.is_available(&provider.id(), cx)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        if providers.is_empty() {
+            let error = self
+                .unsupported_options_error(&options)
+                .unwrap_or_else(|| anyhow!("Web search is not available."));
+            return (partial_responses_rx, Task::ready(Err(error)));
+        }
+        // Put the active provider's results first so that it wins ties, unless it's over budget.
+        let active_provider_id = self.active_provider.as_ref().map(|provider| provider.id());
+        providers.sort_by_cached_key(|provider| {
+            (
+                self.quota(&provider.id(), cx).is_over_budget(),
+                Some(provider.id()) != active_provider_id,
+                provider.is_fallback(),
+                provider.id(),
+            )
+        });
+
+        let mut pending_searches = providers
+            .into_iter()
+            .map(|provider| {
+                self.search_provider(provider, query.clone(), options.clone(), bypass_cache, cx)
+            })
+            .enumerate()
+            .map(|(provider_index, search)| async move { (provider_index, search.await) })
+            .collect::<FuturesUnordered<_>>();
+        let mut timeout = cx.background_executor().timer(deadline).fuse();
+        let search = cx.background_spawn(async move {
+            let mut responses = (0..pending_searches.len())
+                .map(|_| None)
+                .collect::<Vec<_>>();
+            let mut last_error = None;
+            loop {
+                futures::select_biased! {
+                    search = pending_searches.next() => match search {
+                        Some((provider_index, Ok(response))) => {
+                            responses[provider_index] = Some(response);
+                            partial_responses_tx
+                                .unbounded_send(merge_responses(
+                                    responses.iter().flatten().cloned().collect(),
+                                ))
+                                .ok();
+                        }
+                        Some((_, Err(error))) => last_error = Some(error),
+                        None => break,
+                    },
+                    () = timeout => break,
+                }
+            }
+
+            let responses = responses.into_iter().flatten().collect::<Vec<_>>();
+            if responses.is_empty() {
+                return Err(last_error.unwrap_or_else(|| {
+                    anyhow!("No web search provider responded within {deadline:?}.")
+                }));
+            }
+            Ok(merge_responses(responses))
+        });
+        (partial_responses_rx, search)
+    }
+
+    /// Searches for each of `queries`, such as those from [`plan_queries`],
+    /// and merges the results. When `deadline` is set, each query goes to
+    /// every available provider as in [`Self::search_all`].
+    pub fn search_queries(
+        &mut self,
+        queries: Vec<String>,
+        options: WebSearchOptions,
+        bypass_cache: bool,
+        deadline: Option<Duration>,
+        cx: &mut Context<Self>,
+    ) -> Task<Result<WebSearchResponse>> {
+        let searches = queries
+            .into_iter()
+            .map(|query| match deadline {
+                Some(deadline) => {
+                    self.search_all(query, options.clone(), bypass_cache, deadline, cx)
+                }
+                None => self.search(query, options.clone(), bypass_cache, cx),
+            })
+            .collect::<Vec<_>>();
+        cx.background_spawn(async move {
+            let mut responses = Vec::new();
+            let mut last_error = None;
+            for search in futures::future::join_all(searches).await {
+                match search {
+                    Ok(response) => responses.push(response),
+                    Err(error) => last_error = Some(error),
+                }
+            }
+            if responses.is_empty() {
+                return Err(last_error.unwrap_or_else(|| anyhow!("No queries to search for.")));
+            }
+            let mut response = merge_responses(responses);
+            // Each query returns up to `max_results`, so the merged results can exceed it.
+            if let Some(max_results) = options.max_results {
+                response.results.truncate(max_results);
+            }
+            Ok(response)
+        })
+    }
+
+    fn search_provider(
+        &mut self,
+        provider: Arc<dyn WebSearchProvider>,
+        query: String,
+        options: WebSearchOptions,
+        bypass_cache: bool,
+        cx: &mut Context<Self>,
+    ) -> Task<Result<WebSearchResponse>> {
+        let provider_id = provider.id();
+        let ttl = WebSearchSettings::get_global(cx).cache_ttl;
+        let use_cache = !bypass_cache && !ttl.is_zero();
+
+        cx.spawn(async move |this, cx| {
+            if use_cache {
+                let cached_response = cx
+                    .background_spawn({
+                        let provider_id = provider_id.clone();
+                        let query = query.clone();
+                        let options = options.clone();
+                        async move {
+                            WEB_SEARCH_CACHE.cached_response(&provider_id, &query, &options, ttl)
+                        }
+                    })
+                    .await
+                    .log_err()
+                    .flatten();
+                if let Some(response) = cached_response {
+                    return Ok(response);
+                }
+            }
+
+            let search = this.update(cx, |this, cx| {
+                this.record_request(provider_id.clone(), cx);
+                provider.search(query.clone(), options.clone(), cx)
+            })?;
+            let response = search.await;
+            this.update(cx, |this, _| {
+                this.record_outcome(provider_id.clone(), &response)
+            })?;
+            let mut response = response?;
+            response.results = deduplicate_results(response.results);
+            for result in &mut response.results {
+                if result.source.is_none() {
+                    result.source = Url::parse(&result.url).ok().and_then(|url| {
+                        url.host_str()
+                            .map(|host| host.trim_start_matches("www.").to_string())
+                    });
+                }
+            }
+
+            cx.background_spawn({
+                let response = response.clone();
+                async move {
+                    WEB_SEARCH_HISTORY
+                        .record_search(&query, &provider_id, &response)
+                        .await
+                        .log_err();
+                    if use_cache {
+                        WEB_SEARCH_CACHE
+                            .save_response(&provider_id, &query, &options, &response, ttl)
+                            .await
+                            .log_err();
+                    }
+                }
+            })
+            .detach();
+            Ok(response)
+        })
+    }
+
+    /// Whether `provider` has quota left and hasn't been failing repeatedly.
+    fn is_available(&self, provider: &WebSearchProviderId, cx: &App) -> bool {
+        !self.quota(provider, cx).is_exhausted()
+            && !matches!(
+                self.health(provider),
+                WebSearchProviderHealth::Unavailable { .. }
+            )
+    }
+
+    /// The provider that a search with `options` would be sent to, if any
+    /// can take it.
+    pub fn next_provider(
+        &self,
+        options: &WebSearchOptions,
+        cx: &App,
+    ) -> Option<WebSearchProviderId> {
+        self.available_provider(options, cx)
+            .ok()
+            .map(|provider| provider.id())
+    }
+
+    fn available_provider(
+        &self,
+        options: &WebSearchOptions,
+        cx: &App,
+    ) -> Result<Arc<dyn WebSearchProvider>> {
+        let Some(active_provider) = self.active_provider.clone() else {
+            return Err(anyhow!("Web search is not available."));
+        };
+        let required_capabilities = options.required_capabilities();
+        let active_provider_id = active_provider.id();
+        let mut fallback_providers = self
+            .providers
+            .values()
+            .filter(|provider| provider.id() != active_provider_id)
+            .cloned()
+            .collect::<Vec<_>>();
+        fallback_providers.sort_by_key(|provider| (provider.is_fallback(), provider.id()));
+        let usable_providers = std::iter::once(active_provider)
+            .chain(fallback_providers)
+            .filter(|provider| {
+                provider.capabilities().supports(&required_capabilities)
+                    && self.is_available(&provider.id(), cx)
+            })
+            .collect::<Vec<_>>();
+        if let Some(provider) = usable_providers
+            .iter()
+            .find(|provider| !self.quota(&provider.id(), cx).is_over_budget())
+            .or(usable_providers.first())
+        {
+            return Ok(provider.clone());
+        }
+        if let Some(error) = self.unsupported_options_error(options) {
+            return Err(error);
+        }
+
+        match self.health(&active_provider_id) {
+            WebSearchProviderHealth::Unavailable { retry_at } => Err(anyhow!(
+                "Web search with {} failed repeatedly. Try again in {} seconds.",
+                active_provider_id.0,
+                retry_at.saturating_duration_since(Instant::now()).as_secs() + 1
+            )),
+            _ => Err(anyhow!(
+                "Web search quota for {} is exhausted.",
+                active_provider_id.0
+            )),
+        }
+    }
+
+    /// An error naming the options in `options` that no registered provider
+    /// supports, if there are any.
+    pub fn unsupported_options_error(&self, options: &WebSearchOptions) -> Option<anyhow::Error> {
+        let required_capabilities = options.required_capabilities();
+        if self
+            .providers
+            .values()
+            .any(|provider| provider.capabilities().supports(&required_capabilities))
+        {
+            return None;
+        }
+        Some(anyhow!(
+            "No web search provider supports {}.",
+            required_capabilities.names().join(" with ")
+        ))
+    }
+
+    /// Whether recent requests to `provider` have been failing.
+    pub fn health(&self, provider: &WebSearchProviderId) -> WebSearchProviderHealth {
+        let Some(failures) = self.failures.get(provider) else {
+            return WebSearchProviderHealth::Healthy;
+        };
+        if let Some(circuit_opened_at) = failures.circuit_opened_at {
+            let retry_at = circuit_opened_at + CIRCUIT_BREAKER_COOLDOWN;
+            if Instant::now() < retry_at {
+                return WebSearchProviderHealth::Unavailable { retry_at };
+            }
+        }
+        WebSearchProviderHealth::Degraded {
+            consecutive_failures: failures.consecutive_failures,
+        }
+    }
+
+    fn record_outcome(
+        &mut self,
+        provider: WebSearchProviderId,
+        response: &Result<WebSearchResponse>,
+    ) {
+        let error = match response {
+            Ok(_) => {
+                self.failures.remove(&provider);
+                return;
+            }
+            Err(error) => error,
+        };
+        // A provider that's out of quota is working, so it's skipped without
+        // counting towards the circuit breaker.
+        if let Some(quota_exceeded) = error.downcast_ref::<WebSearchQuotaExceeded>() {
+            let retry_after = quota_exceeded.retry_after.unwrap_or(RATE_LIMIT_WINDOW);
+            self.rejected_until
+                .insert(provider, Instant::now() + retry_after);
+            return;
+        }
+
+        let failures = self.failures.entry(provider).or_default();
+        failures.consecutive_failures += 1;
+        // Once the cooldown is over, a single further failure reopens the circuit.
+        if failures.consecutive_failures >= CIRCUIT_BREAKER_THRESHOLD {
+            failures.circuit_opened_at = Some(Instant::now());
+        }
+    }
+
+    fn record_request(&mut self, provider: WebSearchProviderId, cx: &App) {
+        let cost = WebSearchSettings::get_global(cx)
+            .providers
+            .get(&provider)
+            .and_then(|settings| settings.cost_per_request)
+            .unwrap_or(0.);
+        let now = Instant::now();
+        let requests = self.recent_requests.entry(provider.clone()).or_default();
+        while requests
+            .front()
+            .is_some_and(|sent_at| now.duration_since(*sent_at) >= RATE_LIMIT_WINDOW)
+        {
+            requests.pop_front();
+        }
+        requests.push_back(now);
+
+        let month = current_month();
+        self.monthly_usage.add(
+            &month,
+            provider.clone(),
+            WebSearchMonthlyUsage {
+                request_count: 1,
+                cost,
+            },
+        );
+        if let Some(usage_tx) = &self.usage_tx {
+            usage_tx
+                .unbounded_send(UsageRecord {
+                    provider,
+                    month,
+                    cost,
+                })
+                .log_err();
+        }
+    }
+
     pub fn set_active_provider(&mut self, provider: Arc<dyn WebSearchProvider>) {
         self.active_provider = Some(provider.clone());
         self.providers.insert(provider.id(), provider);
     }
 
+    /// Registers `provider`, replacing any provider with the same id. It
+    /// becomes the active provider if there isn't one yet, if the active
+    /// provider is only a fallback, or if it's the `default_provider` in the
+    /// settings.
     pub fn register_provider<T: WebSearchProvider + 'static>(
         &mut self,
         provider: T,
-        _cx: &mut Context<Self>,
+        cx: &mut Context<Self>,
     ) {
         let id = provider.id();
         let provider = Arc::new(provider);
-        self.providers.insert(id, provider.clone());
-        if self.active_provider.is_none() {
+        self.providers.insert(id.clone(), provider.clone());
+        let default_provider = WebSearchSettings::get_global(cx).default_provider.as_ref();
+        let replaces_active_provider =
+            self.active_provider.as_ref().is_none_or(|active_provider| {
+                active_provider.id() == id
+                    || (active_provider.is_fallback()
+                        && !provider.is_fallback()
+                        && default_provider != Some(&active_provider.id()))
+            });
+        if replaces_active_provider || default_provider == Some(&id) {
             self.active_provider = Some(provider);
         }
     }
 
-    pub fn unregister_provider(&mut self, id: WebSearchProviderId) {
+    /// Unregisters the provider with `id`. If it was the active provider, the
+    /// `default_provider` from the settings or else another registered
+    /// provider takes over.
+    pub fn unregister_provider(&mut self, id: WebSearchProviderId, cx: &mut Context<Self>) {
         self.providers.remove(&id);
         if self.active_provider.as_ref().map(|provider| provider.id()) == Some(id) {
-            self.active_provider = None;
+            self.active_provider = self
+                .providers
+                .values()
+                .min_by_key(|provider| (provider.is_fallback(), provider.id()))
+                .cloned();
+            self.select_default_provider(cx);
+        }
+    }
+
+    /// Makes the `default_provider` from the settings the active provider,
+    /// if it's registered.
+    fn select_default_provider(&mut self, cx: &App) {
+        let Some(default_provider) = WebSearchSettings::get_global(cx).default_provider.as_ref()
+        else {
+            return;
+        };
+        if let Some(provider) = self.providers.get(default_provider) {
+            self.active_provider = Some(provider.clone());
         }
     }
+
+    /// Registers `provider` for fetching page content, replacing any content
+    /// provider with the same id.
+    pub fn register_content_provider<T: WebContentProvider + 'static>(&mut self, provider: T) {
+        self.content_providers
+            .insert(provider.id(), Arc::new(provider));
+    }
+
+    pub fn unregister_content_provider(&mut self, id: &WebSearchProviderId) {
+        self.content_providers.remove(id);
+    }
+
+    pub fn can_fetch_content(&self) -> bool {
+        !self.content_providers.is_empty()
+    }
+
+    /// Fetches the content of the page at `url` as Markdown, with the first
+    /// content provider that has quota left.
+    pub fn fetch_content(&mut self, url: &str, cx: &mut Context<Self>) -> Task<Result<String>> {
+        let url = match Url::parse(url) {
+            Ok(url) => url,
+            Err(error) => return Task::ready(Err(anyhow!("Invalid URL {url:?}: {error}"))),
+        };
+        let mut providers = self.content_providers.values().cloned().collect::<Vec<_>>();
+        providers.sort_by_key(|provider| provider.id());
+        let Some(provider) = providers
+            .into_iter()
+            .find(|provider| !self.quota(&provider.id(), cx).is_exhausted())
+        else {
+            return Task::ready(Err(anyhow!("Fetching page content is not available.")));
+        };
+        self.record_request(provider.id(), cx);
+        provider.fetch_content(url, cx)
+    }
+}
+
+/// Shortens `text` to at most `max_graphemes` user-perceived characters,
+/// ending it with `…` when it's cut. Emoji, combining marks and other
+/// multi-codepoint characters are never split.
+pub fn truncate_text(text: &str, max_graphemes: usize) -> Cow<'_, str> {
+    // Each grapheme is at least one byte, so short text can't need cutting.
+    if text.len() <= max_graphemes {
+        return Cow::Borrowed(text);
+    }
+    if max_graphemes == 0 {
+        return Cow::Borrowed("");
+    }
+    // The last grapheme that fits is replaced by the ellipsis.
+    let mut grapheme_starts = text.grapheme_indices(true).map(|(index, _)| index);
+    let Some(end) = grapheme_starts.nth(max_graphemes - 1) else {
+        return Cow::Borrowed(text);
+    };
+    if grapheme_starts.next().is_none() {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(format!("{}…", text[..end].trim_end()))
+}
+
+#[cfg(test)]
+mod tests {
+    use cloud_llm_client::WebSearchResult;
+    use gpui::{TestAppContext, UpdateGlobal as _};
+    use settings::{SettingsStore, WebSearchProviderSettingsContent};
+
+    use super::*;
+
+    struct FakeProvider(&'static str);
+
+    impl WebSearchProvider for FakeProvider {
+        fn id(&self) -> WebSearchProviderId {
+            WebSearchProviderId(self.0.into())
+        }
+
+        fn search(
+            &self,
+            _query: String,
+            _options: WebSearchOptions,
+            _cx: &mut App,
+        ) -> Task<Result<WebSearchResponse>> {
+            Task::ready(Ok(WebSearchResponse {
+                results: vec![WebSearchResult {
+                    title: self.0.to_string(),
+                    url: format!("https://{}.example.com", self.0),
+                    ..Default::default()
+                }],
+                answer: None,
+            }))
+        }
+    }
+
+    struct NewsProvider;
+
+    impl WebSearchProvider for NewsProvider {
+        fn id(&self) -> WebSearchProviderId {
+            WebSearchProviderId("news".into())
+        }
+
+        fn capabilities(&self) -> WebSearchProviderCapabilities {
+            WebSearchProviderCapabilities {
+                news: true,
+                ..Default::default()
+            }
+        }
+
+        fn search(
+            &self,
+            _query: String,
+            _options: WebSearchOptions,
+            _cx: &mut App,
+        ) -> Task<Result<WebSearchResponse>> {
+            Task::ready(Ok(WebSearchResponse {
+                results: vec![WebSearchResult {
+                    title: "news".to_string(),
+                    url: "https://news.example.com".to_string(),
+                    ..Default::default()
+                }],
+                answer: None,
+            }))
+        }
+    }
+
+    struct FailingProvider;
+
+    impl WebSearchProvider for FailingProvider {
+        fn id(&self) -> WebSearchProviderId {
+            WebSearchProviderId("failing".into())
+        }
+
+        fn search(
+            &self,
+            _query: String,
+            _options: WebSearchOptions,
+            _cx: &mut App,
+        ) -> Task<Result<WebSearchResponse>> {
+            Task::ready(Err(anyhow!("service unavailable")))
+        }
+    }
+
+    struct RateLimitedProvider;
+
+    impl WebSearchProvider for RateLimitedProvider {
+        fn id(&self) -> WebSearchProviderId {
+            WebSearchProviderId("rate_limited".into())
+        }
+
+        fn search(
+            &self,
+            _query: String,
+            _options: WebSearchOptions,
+            _cx: &mut App,
+        ) -> Task<Result<WebSearchResponse>> {
+            Task::ready(Err(WebSearchQuotaExceeded {
+                message: "too many requests".into(),
+                retry_after: Some(Duration::from_secs(30)),
+            }
+            .into()))
+        }
+    }
+
+    struct LastResortProvider;
+
+    impl WebSearchProvider for LastResortProvider {
+        fn id(&self) -> WebSearchProviderId {
+            WebSearchProviderId("last_resort".into())
+        }
+
+        fn is_fallback(&self) -> bool {
+            true
+        }
+
+        fn search(
+            &self,
+            _query: String,
+            _options: WebSearchOptions,
+            _cx: &mut App,
+        ) -> Task<Result<WebSearchResponse>> {
+            Task::ready(Ok(WebSearchResponse {
+                results: vec![WebSearchResult {
+                    title: "last resort".to_string(),
+                    url: "https://last-resort.example.com".to_string(),
+                    ..Default::default()
+                }],
+                answer: None,
+            }))
+        }
+    }
+
+    struct FakeContentProvider;
+
+    impl WebContentProvider for FakeContentProvider {
+        fn id(&self) -> WebSearchProviderId {
+            WebSearchProviderId("reader".into())
+        }
+
+        fn fetch_content(&self, url: Url, _cx: &mut App) -> Task<Result<String>> {
+            Task::ready(Ok(format!("# {}", url.path())))
+        }
+    }
+
+    fn init_test(cx: &mut TestAppContext) -> Entity<WebSearchRegistry> {
+        cx.update(|cx| {
+            let settings_store = SettingsStore::test(cx);
+            cx.set_global(settings_store);
+            init(cx);
+            WebSearchRegistry::global(cx)
+        })
+    }
+
+    #[gpui::test]
+    async fn test_failing_provider_is_skipped(cx: &mut TestAppContext) {
+        let registry = init_test(cx);
+        registry.update(cx, |registry, cx| {
+            registry.register_provider(FailingProvider, cx);
+            registry.register_provider(FakeProvider("healthy"), cx);
+        });
+        let failing_provider = WebSearchProviderId("failing".into());
+
+        for consecutive_failures in 1..CIRCUIT_BREAKER_THRESHOLD {
+            let search = registry.update(cx, |registry, cx| {
+                registry.search("query".into(), WebSearchOptions::default(), true, cx)
+            });
+            assert!(search.await.is_err());
+            registry.read_with(cx, |registry, _| {
+                assert_eq!(
+                    registry.health(&failing_provider),
+                    WebSearchProviderHealth::Degraded {
+                        consecutive_failures
+                    }
+                );
+            });
+        }
+        let search = registry.update(cx, |registry, cx| {
+            registry.search("query".into(), WebSearchOptions::default(), true, cx)
+        });
+        assert!(search.await.is_err());
+        registry.read_with(cx, |registry, _| {
+            assert!(matches!(
+                registry.health(&failing_provider),
+                WebSearchProviderHealth::Unavailable { .. }
+            ));
+        });
+
+        let search = registry.update(cx, |registry, cx| {
+            registry.search("query".into(), WebSearchOptions::default(), true, cx)
+        });
+        assert_eq!(search.await.unwrap().results[0].title, "healthy");
+    }
+
+    #[gpui::test]
+    async fn test_exhausted_provider_is_skipped(cx: &mut TestAppContext) {
+        let registry = init_test(cx);
+        cx.update(|cx| {
+            SettingsStore::update_global(cx, |store, cx| {
+                store.update_user_settings(cx, |settings| {
+                    settings
+                        .web_search
+                        .get_or_insert_default()
+                        .providers
+                        .get_or_insert_default()
+                        .insert(
+                            "first".into(),
+                            WebSearchProviderSettingsContent {
+                                requests_per_minute: Some(1),
+                                ..Default::default()
+                            },
+                        );
+                });
+            });
+        });
+        registry.update(cx, |registry, cx| {
+            registry.register_provider(FakeProvider("first"), cx);
+            registry.register_provider(FakeProvider("second"), cx);
+        });
+
+        let search = |cx: &mut TestAppContext| {
+            registry.update(cx, |registry, cx| {
+                registry.search("query".into(), WebSearchOptions::default(), true, cx)
+            })
+        };
+        let response = search(cx).await.unwrap();
+        assert_eq!(response.results[0].title, "first");
+        let response = search(cx).await.unwrap();
+        assert_eq!(response.results[0].title, "second");
+
+        registry.read_with(cx, |registry, cx| {
+            let quota = registry.quota(&WebSearchProviderId("first".into()), cx);
+            assert_eq!(quota.requests_last_minute, 1);
+            assert!(quota.is_exhausted());
+        });
+    }
+
+    #[gpui::test]
+    async fn test_options_are_routed_to_capable_providers(cx: &mut TestAppContext) {
+        let registry = init_test(cx);
+        registry.update(cx, |registry, cx| {
+            registry.register_provider(FakeProvider("web"), cx);
+            registry.register_provider(NewsProvider, cx);
+        });
+        let news_options = WebSearchOptions {
+            kind: SearchKind::News,
+            ..Default::default()
+        };
+
+        let search = registry.update(cx, |registry, cx| {
+            registry.search("query".into(), WebSearchOptions::default(), true, cx)
+        });
+        assert_eq!(search.await.unwrap().results[0].title, "web");
+        let search = registry.update(cx, |registry, cx| {
+            registry.search("query".into(), news_options.clone(), true, cx)
+        });
+        assert_eq!(search.await.unwrap().results[0].title, "news");
+
+        registry.update(cx, |registry, cx| {
+            registry.unregister_provider(WebSearchProviderId("news".into()), cx)
+        });
+        let search = registry.update(cx, |registry, cx| {
+            registry.search("query".into(), news_options, true, cx)
+        });
+        assert_eq!(
+            search.await.unwrap_err().to_string(),
+            "No web search provider supports news search."
+        );
+    }
+
+    #[gpui::test]
+    async fn test_default_provider_setting(cx: &mut TestAppContext) {
+        let registry = init_test(cx);
+        registry.update(cx, |registry, cx| {
+            registry.register_provider(FakeProvider("first"), cx);
+            registry.register_provider(FakeProvider("second"), cx);
+        });
+        let active_provider = |cx: &mut TestAppContext| {
+            registry.read_with(cx, |registry, _| {
+                registry.active_provider().map(|provider| provider.id().0)
+            })
+        };
+        assert_eq!(active_provider(cx).as_deref(), Some("first"));
+
+        cx.update(|cx| {
+            SettingsStore::update_global(cx, |store, cx| {
+                store.update_user_settings(cx, |settings| {
+                    settings.web_search.get_or_insert_default().default_provider =
+                        Some("second".into());
+                });
+            });
+        });
+        cx.run_until_parked();
+        assert_eq!(active_provider(cx).as_deref(), Some("second"));
+
+        registry.update(cx, |registry, cx| {
+            registry.unregister_provider(WebSearchProviderId("second".into()), cx);
+        });
+        assert_eq!(active_provider(cx).as_deref(), Some("first"));
+        registry.update(cx, |registry, cx| {
+            registry.register_provider(FakeProvider("second"), cx);
+        });
+        assert_eq!(active_provider(cx).as_deref(), Some("second"));
+    }
+
+    #[gpui::test]
+    async fn test_rejected_search_falls_back(cx: &mut TestAppContext) {
+        let registry = init_test(cx);
+        registry.update(cx, |registry, cx| {
+            registry.register_provider(RateLimitedProvider, cx);
+            registry.register_provider(FakeProvider("fallback"), cx);
+        });
+
+        let search = registry.update(cx, |registry, cx| {
+            registry.search("query".into(), WebSearchOptions::default(), true, cx)
+        });
+        assert_eq!(search.await.unwrap().results[0].title, "fallback");
+        registry.read_with(cx, |registry, cx| {
+            let rate_limited_provider = WebSearchProviderId("rate_limited".into());
+            assert!(registry.quota(&rate_limited_provider, cx).is_exhausted());
+            assert_eq!(
+                registry.health(&rate_limited_provider),
+                WebSearchProviderHealth::Healthy
+            );
+        });
+
+        registry.update(cx, |registry, cx| {
+            registry.unregister_provider(WebSearchProviderId("fallback".into()), cx)
+        });
+        let search = registry.update(cx, |registry, cx| {
+            registry.search("query".into(), WebSearchOptions::default(), true, cx)
+        });
+        assert!(search.await.is_err());
+    }
+
+    #[gpui::test]
+    async fn test_fallback_provider_is_used_last(cx: &mut TestAppContext) {
+        let registry = init_test(cx);
+        let search = |cx: &mut TestAppContext| {
+            registry.update(cx, |registry, cx| {
+                registry.search("query".into(), WebSearchOptions::default(), true, cx)
+            })
+        };
+
+        registry.update(cx, |registry, cx| {
+            registry.register_provider(LastResortProvider, cx);
+        });
+        assert_eq!(search(cx).await.unwrap().results[0].title, "last resort");
+
+        registry.update(cx, |registry, cx| {
+            registry.register_provider(FailingProvider, cx);
+            registry.register_provider(FakeProvider("zzz"), cx);
+        });
+        for _ in 0..CIRCUIT_BREAKER_THRESHOLD {
+            search(cx).await.ok();
+        }
+        assert_eq!(search(cx).await.unwrap().results[0].title, "zzz");
+
+        registry.update(cx, |registry, cx| {
+            registry.unregister_provider(WebSearchProviderId("zzz".into()), cx);
+            registry.unregister_provider(WebSearchProviderId("failing".into()), cx);
+        });
+        assert_eq!(search(cx).await.unwrap().results[0].title, "last resort");
+    }
+
+    #[gpui::test]
+    async fn test_fetch_content(cx: &mut TestAppContext) {
+        let registry = init_test(cx);
+        let fetch = |url: &str, cx: &mut TestAppContext| {
+            registry.update(cx, |registry, cx| registry.fetch_content(url, cx))
+        };
+        assert!(fetch("https://example.com/page", cx).await.is_err());
+
+        registry.update(cx, |registry, _| {
+            registry.register_content_provider(FakeContentProvider)
+        });
+        assert_eq!(
+            fetch("https://example.com/page", cx).await.unwrap(),
+            "# /page"
+        );
+        assert!(fetch("not a url", cx).await.is_err());
+        registry.read_with(cx, |registry, cx| {
+            let quota = registry.quota(&WebSearchProviderId("reader".into()), cx);
+            assert_eq!(quota.requests_last_minute, 1);
+        });
+    }
+
+    #[gpui::test]
+    async fn test_monthly_usage(cx: &mut TestAppContext) {
+        let registry = init_test(cx);
+        cx.run_until_parked();
+        cx.update(|cx| {
+            SettingsStore::update_global(cx, |store, cx| {
+                store.update_user_settings(cx, |settings| {
+                    settings
+                        .web_search
+                        .get_or_insert_default()
+                        .providers
+                        .get_or_insert_default()
+                        .insert(
+                            "metered".into(),
+                            WebSearchProviderSettingsContent {
+                                monthly_quota: Some(2),
+                                cost_per_request: Some(0.5),
+                                ..Default::default()
+                            },
+                        );
+                });
+            });
+        });
+        registry.update(cx, |registry, cx| {
+            registry.register_provider(FakeProvider("metered"), cx);
+        });
+        let provider = WebSearchProviderId("metered".into());
+
+        for _ in 0..2 {
+            let search = registry.update(cx, |registry, cx| {
+                registry.search("query".into(), WebSearchOptions::default(), true, cx)
+            });
+            search.await.unwrap();
+        }
+        registry.read_with(cx, |registry, cx| {
+            let quota = registry.quota(&provider, cx);
+            assert_eq!(quota.requests_this_month, 2);
+            assert_eq!(quota.cost_this_month, 1.);
+            assert!(quota.is_exhausted());
+        });
+
+        cx.run_until_parked();
+        let saved_usage = WEB_SEARCH_USAGE
+            .usage_in_month(current_month())
+            .await
+            .unwrap();
+        assert_eq!(
+            saved_usage.get(&provider),
+            Some(&WebSearchMonthlyUsage {
+                request_count: 2,
+                cost: 1.,
+            })
+        );
+    }
+
+    #[test]
+    fn test_truncate_text() {
+        assert_eq!(truncate_text("short", 10), "short");
+        assert_eq!(truncate_text("exactly10!", 10), "exactly10!");
+        assert_eq!(truncate_text("hello world", 8), "hello w…");
+        assert_eq!(truncate_text("hello world", 7), "hello…");
+
+        // Multi-byte characters that fit aren't cut, even when there are more
+        // bytes than the limit.
+        assert_eq!(truncate_text("日本語のテキスト", 8), "日本語のテキスト");
+        assert_eq!(truncate_text("日本語のテキスト", 4), "日本語…");
+
+        // A family emoji is several codepoints joined into one character.
+        let family = "👨‍👩‍👧‍👦";
+        assert_eq!(
+            truncate_text(&format!("{family}{family}{family}"), 2),
+            format!("{family}…")
+        );
+        assert_eq!(truncate_text("e\u{301}e\u{301}e\u{301}", 2), "e\u{301}…");
+        assert_eq!(truncate_text("🦀🦀🦀", 1), "…");
+        assert_eq!(truncate_text("🦀🦀🦀", 0), "");
+    }
 }
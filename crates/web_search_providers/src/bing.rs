@@ -0,0 +1,406 @@
+use std::{
+    sync::{Arc, LazyLock},
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result, anyhow};
+use chrono::{NaiveDate, Utc};
+use cloud_llm_client::{WebSearchResponse, WebSearchResult};
+use futures::AsyncReadExt as _;
+use gpui::{App, AppContext as _, Entity, Task};
+use http_client::{HttpClient, Method, StatusCode};
+use language_model::{ApiKeyState, EnvVar, env_var};
+use serde::Deserialize;
+use url::Url;
+use web_search::{
+    SafeSearch, SearchKind, WebSearchOptions, WebSearchProvider, WebSearchProviderCapabilities,
+    WebSearchProviderId, WebSearchQuotaExceeded,
+};
+
+use crate::{
+    ApiKeyWebSearchProvider, parse_date, query_with_site_filters,
+    request::{RequestPolicy, send_with_retries},
+};
+
+pub const BING_WEB_SEARCH_PROVIDER_ID: &str = "bing";
+pub const BING_API_URL: &str = "https://api.bing.microsoft.com/v7.0";
+
+const API_KEY_ENV_VAR_NAME: &str = "BING_SEARCH_API_KEY";
+pub(crate) static API_KEY_ENV_VAR: LazyLock<EnvVar> = env_var!(API_KEY_ENV_VAR_NAME);
+
+/// The most results Bing returns for one request.
+const MAX_RESULTS_PER_PAGE: usize = 50;
+/// How many results Bing returns when no count is given.
+const DEFAULT_RESULTS_PER_PAGE: usize = 10;
+/// How long to skip Bing after it reports that the subscription's call volume
+/// quota is used up, which doesn't reset for a while.
+const QUOTA_RETRY_AFTER: Duration = Duration::from_secs(60 * 60);
+
+pub struct BingWebSearchProvider {
+    http_client: Arc<dyn HttpClient>,
+    api_key: Entity<ApiKeyState>,
+}
+
+impl BingWebSearchProvider {
+    pub fn new(http_client: Arc<dyn HttpClient>, api_key: Entity<ApiKeyState>) -> Self {
+        Self {
+            http_client,
+            api_key,
+        }
+    }
+}
+
+impl WebSearchProvider for BingWebSearchProvider {
+    fn id(&self) -> WebSearchProviderId {
+        WebSearchProviderId(BING_WEB_SEARCH_PROVIDER_ID.into())
+    }
+
+    fn capabilities(&self) -> WebSearchProviderCapabilities {
+        WebSearchProviderCapabilities {
+            news: true,
+            domain_filters: true,
+            pagination: true,
+            freshness: true,
+            region: true,
+            safe_search: true,
+        }
+    }
+
+    fn search(
+        &self,
+        query: String,
+        options: WebSearchOptions,
+        cx: &mut App,
+    ) -> Task<Result<WebSearchResponse>> {
+        let Some(api_key) = self.api_key.read(cx).key(BING_API_URL) else {
+            return Task::ready(Err(anyhow!(
+                "No Bing Search API key. Set {API_KEY_ENV_VAR_NAME} to use Bing Search."
+            )));
+        };
+        self.search_with_key(api_key, query, options, cx)
+    }
+}
+
+impl ApiKeyWebSearchProvider for BingWebSearchProvider {
+    fn search_with_key(
+        &self,
+        api_key: Arc<str>,
+        query: String,
+        options: WebSearchOptions,
+        cx: &mut App,
+    ) -> Task<Result<WebSearchResponse>> {
+        let http_client = self.http_client.clone();
+        let policy = RequestPolicy::for_provider(&self.id(), cx);
+        let executor = cx.background_executor().clone();
+        cx.background_spawn(async move {
+            let url = search_url(&query, &options, Utc::now().date_naive())?;
+            let mut response = send_with_retries(http_client.as_ref(), policy, &executor, || {
+                Ok(http_client::Request::builder()
+                    .method(Method::GET)
+                    .uri(url.as_str())
+                    .header("Accept", "application/json")
+                    .header("Ocp-Apim-Subscription-Key", api_key.as_ref())
+                    .body(Default::default())?)
+            })
+            .await
+            .context("failed to send Bing search request")?;
+            let retry_after = response
+                .headers()
+                .get("Retry-After")
+                .and_then(|retry_after| retry_after.to_str().ok()?.parse().ok())
+                .map(Duration::from_secs);
+            let mut body = String::new();
+            response.body_mut().read_to_string(&mut body).await?;
+            if !response.status().is_success() {
+                return Err(response_error(response.status(), &body, retry_after));
+            }
+
+            let mut response = parse_response(&body, options.kind)?;
+            // Bing's news search only filters by the last day, week or month,
+            // so date ranges are applied to its results.
+            if options.kind == SearchKind::News {
+                response.results.retain(|result| {
+                    let Some(published_at) = result.published_at.map(|date| date.date_naive())
+                    else {
+                        return true;
+                    };
+                    options
+                        .published_after
+                        .is_none_or(|after| published_at >= after)
+                        && options
+                            .published_before
+                            .is_none_or(|before| published_at <= before)
+                });
+            }
+            Ok(response)
+        })
+    }
+}
+
+fn search_url(query: &str, options: &WebSearchOptions, today: NaiveDate) -> Result<Url> {
+    let endpoint = match options.kind {
+        SearchKind::Web => "search",
+        SearchKind::News => "news/search",
+    };
+    let mut url = Url::parse(&format!("{BING_API_URL}/{endpoint}"))?;
+    {
+        let mut query_pairs = url.query_pairs_mut();
+        query_pairs.append_pair("q", &query_with_site_filters(query, options));
+        let count = options
+            .max_results
+            .map_or(DEFAULT_RESULTS_PER_PAGE, |max_results| {
+                max_results.clamp(1, MAX_RESULTS_PER_PAGE)
+            });
+        if options.max_results.is_some() {
+            query_pairs.append_pair("count", &count.to_string());
+        }
+        if let Some(page) = options.page.filter(|page| *page > 1) {
+            query_pairs.append_pair("offset", &((page as usize - 1) * count).to_string());
+        }
+        if options.kind == SearchKind::Web
+            && (options.published_after.is_some() || options.published_before.is_some())
+        {
+            let after = options.published_after.unwrap_or_default();
+            let before = options.published_before.unwrap_or(today);
+            query_pairs.append_pair("freshness", &format!("{after}..{before}"));
+        }
+        match (&options.language, &options.region) {
+            (Some(language), Some(region)) => {
+                query_pairs.append_pair(
+                    "mkt",
+                    &format!("{}-{}", language.to_lowercase(), region.to_uppercase()),
+                );
+            }
+            (Some(language), None) => {
+                query_pairs.append_pair("setLang", language);
+            }
+            (None, Some(region)) => {
+                query_pairs.append_pair("cc", &region.to_uppercase());
+            }
+            (None, None) => {}
+        }
+        if let Some(safe_search) = options.safe_search {
+            let safe_search = match safe_search {
+                SafeSearch::Off => "Off",
+                SafeSearch::Moderate => "Moderate",
+                SafeSearch::Strict => "Strict",
+            };
+            query_pairs.append_pair("safeSearch", safe_search);
+        }
+    }
+    Ok(url)
+}
+
+#[derive(Deserialize)]
+struct BingErrorResponse {
+    error: BingError,
+}
+
+#[derive(Deserialize)]
+struct BingError {
+    code: String,
+    message: String,
+}
+
+/// Rate limit and quota errors become [`WebSearchQuotaExceeded`], so that
+/// the registry moves on to another provider.
+fn response_error(status: StatusCode, body: &str, retry_after: Option<Duration>) -> anyhow::Error {
+    let error = serde_json::from_str::<BingErrorResponse>(body)
+        .ok()
+        .map(|response| response.error);
+    let is_quota_error = error
+        .as_ref()
+        .is_some_and(|error| error.code.contains("Quota"));
+    if status == StatusCode::TOO_MANY_REQUESTS || is_quota_error {
+        return WebSearchQuotaExceeded {
+            message: error
+                .map(|error| error.message)
+                .unwrap_or_else(|| body.to_string()),
+            retry_after: retry_after.or(is_quota_error.then_some(QUOTA_RETRY_AFTER)),
+        }
+        .into();
+    }
+    match error {
+        Some(error) => anyhow!(
+            "error performing Bing search.\nStatus: {status:?}\n{}: {}",
+            error.code,
+            error.message
+        ),
+        None => anyhow!("error performing Bing search.\nStatus: {status:?}\nBody: {body}"),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BingWebSearchResponse {
+    web_pages: Option<BingWebPages>,
+}
+
+#[derive(Default, Deserialize)]
+struct BingWebPages {
+    #[serde(default)]
+    value: Vec<BingWebPage>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BingWebPage {
+    name: String,
+    url: String,
+    #[serde(default)]
+    snippet: String,
+    date_published: Option<String>,
+    site_name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BingNewsSearchResponse {
+    #[serde(default)]
+    value: Vec<BingNewsArticle>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BingNewsArticle {
+    name: String,
+    url: String,
+    #[serde(default)]
+    description: String,
+    date_published: Option<String>,
+    #[serde(default)]
+    provider: Vec<BingNewsProvider>,
+}
+
+#[derive(Deserialize)]
+struct BingNewsProvider {
+    name: String,
+}
+
+fn parse_response(body: &str, kind: SearchKind) -> Result<WebSearchResponse> {
+    let results = match kind {
+        SearchKind::Web => serde_json::from_str::<BingWebSearchResponse>(body)?
+            .web_pages
+            .unwrap_or_default()
+            .value
+            .into_iter()
+            .map(|page| WebSearchResult {
+                title: page.name,
+                url: page.url,
+                text: page.snippet,
+                published_at: page.date_published.as_deref().and_then(parse_date),
+                source: page.site_name,
+                ..Default::default()
+            })
+            .collect(),
+        SearchKind::News => serde_json::from_str::<BingNewsSearchResponse>(body)?
+            .value
+            .into_iter()
+            .map(|article| WebSearchResult {
+                title: article.name,
+                url: article.url,
+                text: article.description,
+                published_at: article.date_published.as_deref().and_then(parse_date),
+                source: article
+                    .provider
+                    .into_iter()
+                    .next()
+                    .map(|provider| provider.name),
+                ..Default::default()
+            })
+            .collect(),
+    };
+    Ok(WebSearchResponse {
+        results,
+        answer: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_url() {
+        let options = WebSearchOptions {
+            max_results: Some(20),
+            page: Some(3),
+            published_before: NaiveDate::from_ymd_opt(2024, 6, 1),
+            language: Some("en".into()),
+            region: Some("gb".into()),
+            safe_search: Some(SafeSearch::Strict),
+            ..Default::default()
+        };
+        let url = search_url(
+            "async",
+            &options,
+            NaiveDate::from_ymd_opt(2024, 6, 1).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(url.path(), "/v7.0/search");
+        let query_pairs = url.query_pairs().into_owned().collect::<Vec<_>>();
+        assert_eq!(
+            query_pairs,
+            [
+                ("q".to_string(), "async".to_string()),
+                ("count".to_string(), "20".to_string()),
+                ("offset".to_string(), "40".to_string()),
+                (
+                    "freshness".to_string(),
+                    "1970-01-01..2024-06-01".to_string()
+                ),
+                ("mkt".to_string(), "en-GB".to_string()),
+                ("safeSearch".to_string(), "Strict".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let body = r#"{
+            "webPages": {
+                "value": [{
+                    "name": "Async in Rust",
+                    "url": "https://rust-lang.org/async",
+                    "snippet": "An overview.",
+                    "datePublished": "2024-02-03T04:05:06.0000000",
+                    "siteName": "Rust"
+                }]
+            }
+        }"#;
+        let response = parse_response(body, SearchKind::Web).unwrap();
+        let result = &response.results[0];
+        assert_eq!(result.title, "Async in Rust");
+        assert_eq!(result.text, "An overview.");
+        assert_eq!(
+            result.published_at.unwrap().to_rfc3339(),
+            "2024-02-03T04:05:06+00:00"
+        );
+        assert_eq!(result.source.as_deref(), Some("Rust"));
+    }
+
+    #[test]
+    fn test_quota_errors_are_retryable() {
+        let error = response_error(
+            StatusCode::FORBIDDEN,
+            r#"{"error": {"code": "OutOfCallVolumeQuota", "message": "Out of call volume quota."}}"#,
+            None,
+        );
+        let quota_exceeded = error.downcast_ref::<WebSearchQuotaExceeded>().unwrap();
+        assert_eq!(quota_exceeded.retry_after, Some(QUOTA_RETRY_AFTER));
+
+        let error = response_error(
+            StatusCode::TOO_MANY_REQUESTS,
+            "",
+            Some(Duration::from_secs(5)),
+        );
+        let quota_exceeded = error.downcast_ref::<WebSearchQuotaExceeded>().unwrap();
+        assert_eq!(quota_exceeded.retry_after, Some(Duration::from_secs(5)));
+
+        let error = response_error(
+            StatusCode::UNAUTHORIZED,
+            r#"{"error": {"code": "InvalidAuthorization", "message": "Invalid key."}}"#,
+            None,
+        );
+        assert!(!error.is::<WebSearchQuotaExceeded>());
+    }
+}
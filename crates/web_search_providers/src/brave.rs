@@ -0,0 +1,291 @@
+use std::sync::{Arc, LazyLock};
+
+use anyhow::{Context as _, Result, anyhow};
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use cloud_llm_client::{WebSearchResponse, WebSearchResult};
+use futures::AsyncReadExt as _;
+use gpui::{App, AppContext as _, Entity, Task};
+use http_client::{HttpClient, Method};
+use language_model::{ApiKeyState, EnvVar, env_var};
+use serde::Deserialize;
+use url::Url;
+use web_search::{
+    SafeSearch, SearchKind, WebSearchOptions, WebSearchProvider, WebSearchProviderCapabilities,
+    WebSearchProviderId,
+};
+
+use crate::{
+    ApiKeyWebSearchProvider, html_to_text, query_with_site_filters,
+    request::{RequestPolicy, send_with_retries},
+};
+
+pub const BRAVE_WEB_SEARCH_PROVIDER_ID: &str = "brave";
+pub const BRAVE_API_URL: &str = "https://api.search.brave.com/res/v1";
+
+const API_KEY_ENV_VAR_NAME: &str = "BRAVE_SEARCH_API_KEY";
+pub(crate) static API_KEY_ENV_VAR: LazyLock<EnvVar> = env_var!(API_KEY_ENV_VAR_NAME);
+
+/// The most results Brave returns for one request.
+const MAX_RESULTS_PER_PAGE: usize = 20;
+/// Brave only serves the first ten pages of results.
+const MAX_PAGE_OFFSET: u32 = 9;
+
+pub struct BraveWebSearchProvider {
+    http_client: Arc<dyn HttpClient>,
+    api_key: Entity<ApiKeyState>,
+}
+
+impl BraveWebSearchProvider {
+    pub fn new(http_client: Arc<dyn HttpClient>, api_key: Entity<ApiKeyState>) -> Self {
+        Self {
+            http_client,
+            api_key,
+        }
+    }
+}
+
+impl WebSearchProvider for BraveWebSearchProvider {
+    fn id(&self) -> WebSearchProviderId {
+        WebSearchProviderId(BRAVE_WEB_SEARCH_PROVIDER_ID.into())
+    }
+
+    fn capabilities(&self) -> WebSearchProviderCapabilities {
+        WebSearchProviderCapabilities {
+            news: true,
+            domain_filters: true,
+            pagination: true,
+            freshness: true,
+            region: true,
+            safe_search: true,
+        }
+    }
+
+    fn search(
+        &self,
+        query: String,
+        options: WebSearchOptions,
+        cx: &mut App,
+    ) -> Task<Result<WebSearchResponse>> {
+        let Some(api_key) = self.api_key.read(cx).key(BRAVE_API_URL) else {
+            return Task::ready(Err(anyhow!(
+                "No Brave Search API key. Set {API_KEY_ENV_VAR_NAME} to use Brave Search."
+            )));
+        };
+        self.search_with_key(api_key, query, options, cx)
+    }
+}
+
+impl ApiKeyWebSearchProvider for BraveWebSearchProvider {
+    fn search_with_key(
+        &self,
+        api_key: Arc<str>,
+        query: String,
+        options: WebSearchOptions,
+        cx: &mut App,
+    ) -> Task<Result<WebSearchResponse>> {
+        let http_client = self.http_client.clone();
+        let policy = RequestPolicy::for_provider(&self.id(), cx);
+        let executor = cx.background_executor().clone();
+        cx.background_spawn(async move {
+            let url = search_url(&query, &options, Utc::now().date_naive())?;
+            let mut response = send_with_retries(http_client.as_ref(), policy, &executor, || {
+                Ok(http_client::Request::builder()
+                    .method(Method::GET)
+                    .uri(url.as_str())
+                    .header("Accept", "application/json")
+                    .header("X-Subscription-Token", api_key.as_ref())
+                    .body(Default::default())?)
+            })
+            .await
+            .context("failed to send Brave search request")?;
+            let mut body = String::new();
+            response.body_mut().read_to_string(&mut body).await?;
+            anyhow::ensure!(
+                response.status().is_success(),
+                "error performing Brave search.\nStatus: {:?}\nBody: {body}",
+                response.status(),
+            );
+            parse_response(&body, options.kind)
+        })
+    }
+}
+
+fn search_url(query: &str, options: &WebSearchOptions, today: NaiveDate) -> Result<Url> {
+    let endpoint = match options.kind {
+        SearchKind::Web => "web/search",
+        SearchKind::News => "news/search",
+    };
+    let mut url = Url::parse(&format!("{BRAVE_API_URL}/{endpoint}"))?;
+    {
+        let mut query_pairs = url.query_pairs_mut();
+        // Brave has no domain parameters, but understands `site:` operators.
+        query_pairs.append_pair("q", &query_with_site_filters(query, options));
+        if let Some(max_results) = options.max_results {
+            query_pairs.append_pair(
+                "count",
+                &max_results.clamp(1, MAX_RESULTS_PER_PAGE).to_string(),
+            );
+        }
+        if let Some(page) = options.page.filter(|page| *page > 1) {
+            query_pairs.append_pair("offset", &(page - 1).min(MAX_PAGE_OFFSET).to_string());
+        }
+        if options.published_after.is_some() || options.published_before.is_some() {
+            let after = options.published_after.unwrap_or_default();
+            let before = options.published_before.unwrap_or(today);
+            query_pairs.append_pair("freshness", &format!("{after}to{before}"));
+        }
+        if let Some(language) = &options.language {
+            query_pairs.append_pair("search_lang", language);
+        }
+        if let Some(region) = &options.region {
+            query_pairs.append_pair("country", &region.to_uppercase());
+        }
+        if let Some(safe_search) = options.safe_search {
+            let safe_search = match safe_search {
+                SafeSearch::Off => "off",
+                SafeSearch::Moderate => "moderate",
+                SafeSearch::Strict => "strict",
+            };
+            query_pairs.append_pair("safesearch", safe_search);
+        }
+    }
+    Ok(url)
+}
+
+#[derive(Deserialize)]
+struct BraveWebSearchResponse {
+    #[serde(default)]
+    web: Option<BraveResults>,
+}
+
+#[derive(Default, Deserialize)]
+struct BraveResults {
+    #[serde(default)]
+    results: Vec<BraveResult>,
+}
+
+#[derive(Deserialize)]
+struct BraveResult {
+    title: String,
+    url: String,
+    #[serde(default)]
+    description: String,
+    page_age: Option<String>,
+    profile: Option<BraveProfile>,
+    meta_url: Option<BraveMetaUrl>,
+}
+
+#[derive(Deserialize)]
+struct BraveProfile {
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BraveMetaUrl {
+    favicon: Option<String>,
+}
+
+fn parse_response(body: &str, kind: SearchKind) -> Result<WebSearchResponse> {
+    // News results are at the top level, while web results are nested under `web`.
+    let results = match kind {
+        SearchKind::Web => serde_json::from_str::<BraveWebSearchResponse>(body)?
+            .web
+            .unwrap_or_default(),
+        SearchKind::News => serde_json::from_str::<BraveResults>(body)?,
+    };
+    Ok(WebSearchResponse {
+        results: results
+            .results
+            .into_iter()
+            .map(|result| WebSearchResult {
+                title: html_to_text(&result.title),
+                url: result.url,
+                text: html_to_text(&result.description),
+                published_at: result.page_age.and_then(|page_age| {
+                    NaiveDateTime::parse_from_str(&page_age, "%Y-%m-%dT%H:%M:%S")
+                        .ok()
+                        .map(|published_at| published_at.and_utc())
+                }),
+                source: result.profile.and_then(|profile| profile.name),
+                favicon_url: result.meta_url.and_then(|meta_url| meta_url.favicon),
+                ..Default::default()
+            })
+            .collect(),
+        answer: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_url() {
+        let options = WebSearchOptions {
+            kind: SearchKind::News,
+            max_results: Some(50),
+            page: Some(3),
+            include_domains: vec!["rust-lang.org".into(), "docs.rs".into()],
+            exclude_domains: vec!["reddit.com".into()],
+            published_after: NaiveDate::from_ymd_opt(2024, 1, 1),
+            region: Some("us".into()),
+            ..Default::default()
+        };
+        let url = search_url(
+            "async",
+            &options,
+            NaiveDate::from_ymd_opt(2024, 6, 1).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(url.path(), "/res/v1/news/search");
+        let query_pairs = url.query_pairs().into_owned().collect::<Vec<_>>();
+        assert_eq!(
+            query_pairs,
+            [
+                (
+                    "q".to_string(),
+                    "async (site:rust-lang.org OR site:docs.rs) -site:reddit.com".to_string()
+                ),
+                ("count".to_string(), "20".to_string()),
+                ("offset".to_string(), "2".to_string()),
+                (
+                    "freshness".to_string(),
+                    "2024-01-01to2024-06-01".to_string()
+                ),
+                ("country".to_string(), "US".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let body = r#"{
+            "web": {
+                "results": [{
+                    "title": "Async in Rust",
+                    "url": "https://rust-lang.org/async",
+                    "description": "An overview.",
+                    "page_age": "2024-02-03T04:05:06",
+                    "profile": { "name": "Rust" },
+                    "meta_url": { "favicon": "https://rust-lang.org/favicon.ico" }
+                }]
+            }
+        }"#;
+        let response = parse_response(body, SearchKind::Web).unwrap();
+        let result = &response.results[0];
+        assert_eq!(result.title, "Async in Rust");
+        assert_eq!(result.text, "An overview.");
+        assert_eq!(
+            result.published_at.unwrap().to_rfc3339(),
+            "2024-02-03T04:05:06+00:00"
+        );
+        assert_eq!(result.source.as_deref(), Some("Rust"));
+
+        assert!(
+            parse_response(r#"{"results": []}"#, SearchKind::News)
+                .unwrap()
+                .results
+                .is_empty()
+        );
+    }
+}
@@ -4,10 +4,14 @@ use anyhow::{Context as _, Result};
 use client::Client;
 use cloud_llm_client::{EXPIRED_LLM_TOKEN_HEADER_NAME, WebSearchBody, WebSearchResponse};
 use futures::AsyncReadExt as _;
-use gpui::{App, AppContext, Context, Entity, Subscription, Task};
+use gpui::{App, AppContext, BackgroundExecutor, Context, Entity, Subscription, Task};
 use http_client::{HttpClient, Method};
 use language_model::{LlmApiToken, RefreshLlmTokenListener};
-use web_search::{WebSearchProvider, WebSearchProviderId};
+use web_search::{
+    WebSearchOptions, WebSearchProvider, WebSearchProviderCapabilities, WebSearchProviderId,
+};
+
+use crate::request::{RequestPolicy, send_with_retries};
 
 pub struct CloudWebSearchProvider {
     state: Entity<State>,
@@ -57,12 +61,38 @@ impl WebSearchProvider for CloudWebSearchProvider {
         WebSearchProviderId(ZED_WEB_SEARCH_PROVIDER_ID.into())
     }
 
-    fn search(&self, query: String, cx: &mut App) -> Task<Result<WebSearchResponse>> {
+    fn capabilities(&self) -> WebSearchProviderCapabilities {
+        // Domain filters are applied to the results after they arrive.
+        WebSearchProviderCapabilities {
+            domain_filters: true,
+            ..Default::default()
+        }
+    }
+
+    fn search(
+        &self,
+        query: String,
+        options: WebSearchOptions,
+        cx: &mut App,
+    ) -> Task<Result<WebSearchResponse>> {
         let state = self.state.read(cx);
         let client = state.client.clone();
         let llm_api_token = state.llm_api_token.clone();
         let body = WebSearchBody { query };
-        cx.background_spawn(async move { perform_web_search(client, llm_api_token, body).await })
+        let policy = RequestPolicy::for_provider(&self.id(), cx);
+        let executor = cx.background_executor().clone();
+        cx.background_spawn(async move {
+            let mut response =
+                perform_web_search(client, llm_api_token, body, policy, &executor).await?;
+            // The endpoint only takes a query, so apply what options we can to its results.
+            response
+                .results
+                .retain(|result| options.allows_url(&result.url));
+            if let Some(max_results) = options.max_results {
+                response.results.truncate(max_results);
+            }
+            Ok(response)
+        })
     }
 }
 
@@ -70,6 +100,8 @@ async fn perform_web_search(
     client: Arc<Client>,
     llm_api_token: LlmApiToken,
     body: WebSearchBody,
+    policy: RequestPolicy,
+    executor: &BackgroundExecutor,
 ) -> Result<WebSearchResponse> {
     const MAX_RETRIES: usize = 3;
 
@@ -84,16 +116,16 @@ async fn perform_web_search(
             ));
         }
 
-        let request = http_client::Request::builder()
-            .method(Method::POST)
-            .uri(http_client.build_zed_llm_url("/web_search", &[])?.as_ref())
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {token}"))
-            .body(serde_json::to_string(&body)?.into())?;
-        let mut response = http_client
-            .send(request)
-            .await
-            .context("failed to send web search request")?;
+        let mut response = send_with_retries(http_client.as_ref(), policy, executor, || {
+            Ok(http_client::Request::builder()
+                .method(Method::POST)
+                .uri(http_client.build_zed_llm_url("/web_search", &[])?.as_ref())
+                .header("Content-Type", "application/json")
+                .header("Authorization", format!("Bearer {token}"))
+                .body(serde_json::to_string(&body)?.into())?)
+        })
+        .await
+        .context("failed to send web search request")?;
 
         if response.status().is_success() {
             let mut body = String::new();
@@ -107,8 +139,8 @@ async fn perform_web_search(
             token = llm_api_token.refresh(&client).await?;
             retries_remaining -= 1;
         } else {
-            // For now we will only retry if the LLM token is expired,
-            // not if the request failed for any other reason.
+            // Server errors and timeouts were already retried, so only an
+            // expired LLM token gets another attempt here.
             let mut body = String::new();
             response.body_mut().read_to_string(&mut body).await?;
             anyhow::bail!(
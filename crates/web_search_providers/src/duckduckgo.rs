@@ -0,0 +1,272 @@
+use std::sync::Arc;
+
+use anyhow::{Context as _, Result};
+use cloud_llm_client::{WebSearchResponse, WebSearchResult};
+use futures::AsyncReadExt as _;
+use gpui::{App, AppContext as _, Task};
+use html5ever::{driver::ParseOpts, parse_document, tendril::TendrilSink as _};
+use http_client::{HttpClient, Method, StatusCode};
+use markup5ever_rcdom::{Handle, NodeData, RcDom};
+use url::Url;
+use web_search::{
+    SafeSearch, WebSearchOptions, WebSearchProvider, WebSearchProviderCapabilities,
+    WebSearchProviderId, WebSearchQuotaExceeded,
+};
+
+use crate::{
+    query_with_site_filters,
+    request::{RequestPolicy, send_with_retries},
+};
+
+pub const DUCKDUCKGO_WEB_SEARCH_PROVIDER_ID: &str = "duckduckgo";
+const DUCKDUCKGO_HTML_URL: &str = "https://html.duckduckgo.com/html/";
+
+/// DuckDuckGo's HTML results page, which needs no API key. It has no API
+/// guarantees, so it's only used when no other provider can search.
+pub struct DuckDuckGoWebSearchProvider {
+    http_client: Arc<dyn HttpClient>,
+}
+
+impl DuckDuckGoWebSearchProvider {
+    pub fn new(http_client: Arc<dyn HttpClient>) -> Self {
+        Self { http_client }
+    }
+}
+
+impl WebSearchProvider for DuckDuckGoWebSearchProvider {
+    fn id(&self) -> WebSearchProviderId {
+        WebSearchProviderId(DUCKDUCKGO_WEB_SEARCH_PROVIDER_ID.into())
+    }
+
+    fn capabilities(&self) -> WebSearchProviderCapabilities {
+        WebSearchProviderCapabilities {
+            domain_filters: true,
+            region: true,
+            safe_search: true,
+            ..Default::default()
+        }
+    }
+
+    fn is_fallback(&self) -> bool {
+        true
+    }
+
+    fn search(
+        &self,
+        query: String,
+        options: WebSearchOptions,
+        cx: &mut App,
+    ) -> Task<Result<WebSearchResponse>> {
+        let http_client = self.http_client.clone();
+        let policy = RequestPolicy::for_provider(&self.id(), cx);
+        let executor = cx.background_executor().clone();
+        cx.background_spawn(async move {
+            let url = search_url(&query, &options)?;
+            let mut response = send_with_retries(http_client.as_ref(), policy, &executor, || {
+                Ok(http_client::Request::builder()
+                    .method(Method::GET)
+                    .uri(url.as_str())
+                    .header("Accept", "text/html")
+                    .body(Default::default())?)
+            })
+            .await
+            .context("failed to send DuckDuckGo search request")?;
+            let mut body = String::new();
+            response.body_mut().read_to_string(&mut body).await?;
+            // DuckDuckGo answers with a challenge page when it thinks it's
+            // being queried too often.
+            if matches!(
+                response.status(),
+                StatusCode::ACCEPTED | StatusCode::TOO_MANY_REQUESTS
+            ) {
+                return Err(WebSearchQuotaExceeded {
+                    message: "DuckDuckGo is limiting requests.".into(),
+                    retry_after: None,
+                }
+                .into());
+            }
+            anyhow::ensure!(
+                response.status().is_success(),
+                "error performing DuckDuckGo search.\nStatus: {:?}",
+                response.status(),
+            );
+
+            let mut response = parse_results_page(&body)?;
+            if let Some(max_results) = options.max_results {
+                response.results.truncate(max_results);
+            }
+            Ok(response)
+        })
+    }
+}
+
+fn search_url(query: &str, options: &WebSearchOptions) -> Result<Url> {
+    let mut url = Url::parse(DUCKDUCKGO_HTML_URL)?;
+    {
+        let mut query_pairs = url.query_pairs_mut();
+        query_pairs.append_pair("q", &query_with_site_filters(query, options));
+        // Regions combine a country with a language, such as `us-en`.
+        if let Some(region) = &options.region {
+            let language = options.language.as_deref().unwrap_or("en");
+            query_pairs.append_pair(
+                "kl",
+                &format!("{}-{}", region.to_lowercase(), language.to_lowercase()),
+            );
+        }
+        if let Some(safe_search) = options.safe_search {
+            let safe_search = match safe_search {
+                SafeSearch::Off => "-2",
+                SafeSearch::Moderate => "-1",
+                SafeSearch::Strict => "1",
+            };
+            query_pairs.append_pair("kp", safe_search);
+        }
+    }
+    Ok(url)
+}
+
+fn parse_results_page(html: &str) -> Result<WebSearchResponse> {
+    let dom = parse_document(RcDom::default(), ParseOpts::default())
+        .from_utf8()
+        .read_from(&mut html.as_bytes())
+        .context("failed to parse DuckDuckGo results page")?;
+    let mut results = Vec::new();
+    collect_results(&dom.document, &mut results);
+    Ok(WebSearchResponse {
+        results,
+        answer: None,
+    })
+}
+
+/// Collects results from the page's `result__a` title links and the
+/// `result__snippet` elements that follow them.
+fn collect_results(node: &Handle, results: &mut Vec<WebSearchResult>) {
+    if let NodeData::Element { attrs, .. } = &node.data {
+        let attrs = attrs.borrow();
+        let attr = |name: &str| {
+            attrs
+                .iter()
+                .find(|attr| &*attr.name.local == name)
+                .map(|attr| attr.value.to_string())
+        };
+        let classes = attr("class").unwrap_or_default();
+        let has_class = |class: &str| classes.split_whitespace().any(|name| name == class);
+        if has_class("result__a") {
+            // Ads link back to DuckDuckGo rather than to the result.
+            if let Some(url) = attr("href").and_then(|href| result_url(&href)) {
+                results.push(WebSearchResult {
+                    title: node_text(node),
+                    url,
+                    ..Default::default()
+                });
+            }
+            return;
+        }
+        if has_class("result__snippet") {
+            if let Some(result) = results.last_mut()
+                && result.text.is_empty()
+            {
+                result.text = node_text(node);
+            }
+            return;
+        }
+    }
+    for child in node.children.borrow().iter() {
+        collect_results(child, results);
+    }
+}
+
+/// The URL a result link points to, which DuckDuckGo wraps in a redirect
+/// through `/l/?uddg=<url>`.
+fn result_url(href: &str) -> Option<String> {
+    let href = match href.strip_prefix("//") {
+        Some(href) => format!("https://{href}"),
+        None => href.to_string(),
+    };
+    let url = Url::parse(&href).ok()?;
+    let is_duckduckgo = url
+        .host_str()
+        .is_some_and(|host| host == "duckduckgo.com" || host.ends_with(".duckduckgo.com"));
+    if !is_duckduckgo {
+        return Some(url.into());
+    }
+    if url.path() != "/l/" {
+        return None;
+    }
+    url.query_pairs()
+        .find(|(name, _)| name == "uddg")
+        .map(|(_, url)| url.into_owned())
+}
+
+/// The text displayed by `node` and its descendants, with whitespace collapsed.
+fn node_text(node: &Handle) -> String {
+    fn collect_text(node: &Handle, text: &mut String) {
+        match &node.data {
+            NodeData::Text { contents } => text.push_str(&contents.borrow()),
+            NodeData::Element { name, .. } if matches!(&*name.local, "script" | "style") => {
+                return;
+            }
+            _ => {}
+        }
+        for child in node.children.borrow().iter() {
+            collect_text(child, text);
+        }
+    }
+
+    let mut text = String::new();
+    collect_text(node, &mut text);
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_url() {
+        let options = WebSearchOptions {
+            include_domains: vec!["docs.rs".into()],
+            region: Some("DE".into()),
+            language: Some("de".into()),
+            safe_search: Some(SafeSearch::Off),
+            ..Default::default()
+        };
+        let url = search_url("serde", &options).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://html.duckduckgo.com/html/?q=serde+%28site%3Adocs.rs%29&kl=de-de&kp=-2"
+        );
+    }
+
+    #[test]
+    fn test_parse_results_page() {
+        let html = r#"
+            <html><body><div class="results">
+                <div class="result results_links result--ad">
+                    <h2 class="result__title">
+                        <a class="result__a" href="https://duckduckgo.com/y.js?ad_domain=example.com">Ad</a>
+                    </h2>
+                    <a class="result__snippet" href="https://duckduckgo.com/y.js">Buy now</a>
+                </div>
+                <div class="result results_links web-result">
+                    <h2 class="result__title">
+                        <a rel="nofollow" class="result__a" href="//duckduckgo.com/l/?uddg=https%3A%2F%2Fwww.rust-lang.org%2F&amp;rut=abc">Rust Programming Language</a>
+                    </h2>
+                    <a class="result__snippet" href="//duckduckgo.com/l/?uddg=https%3A%2F%2Fwww.rust-lang.org%2F">A language empowering <b>everyone</b>.</a>
+                </div>
+                <div class="result results_links web-result">
+                    <h2 class="result__title">
+                        <a class="result__a" href="https://doc.rust-lang.org/book/">The Rust Book</a>
+                    </h2>
+                </div>
+            </div></body></html>
+        "#;
+        let response = parse_results_page(html).unwrap();
+        assert_eq!(response.results.len(), 2);
+        assert_eq!(response.results[0].title, "Rust Programming Language");
+        assert_eq!(response.results[0].url, "https://www.rust-lang.org/");
+        assert_eq!(response.results[0].text, "A language empowering everyone.");
+        assert_eq!(response.results[1].url, "https://doc.rust-lang.org/book/");
+        assert!(response.results[1].text.is_empty());
+    }
+}
@@ -0,0 +1,366 @@
+use std::sync::{Arc, LazyLock};
+
+use anyhow::{Context as _, Result, anyhow};
+use chrono::{NaiveDate, Utc};
+use cloud_llm_client::{WebSearchResponse, WebSearchResult};
+use collections::HashMap;
+use futures::AsyncReadExt as _;
+use gpui::{App, AppContext as _, Entity, Task};
+use http_client::{HttpClient, Method, StatusCode};
+use language_model::{ApiKeyState, EnvVar, env_var};
+use serde::Deserialize;
+use settings::Settings as _;
+use url::Url;
+use web_search::{
+    SafeSearch, WebSearchOptions, WebSearchProvider, WebSearchProviderCapabilities,
+    WebSearchProviderId, WebSearchQuotaExceeded, WebSearchSettings,
+};
+
+use crate::{
+    ApiKeyWebSearchProvider, parse_date, query_with_site_filters,
+    request::{RequestPolicy, send_with_retries},
+};
+
+pub const GOOGLE_WEB_SEARCH_PROVIDER_ID: &str = "google";
+pub const GOOGLE_API_URL: &str = "https://www.googleapis.com/customsearch/v1";
+
+const API_KEY_ENV_VAR_NAME: &str = "GOOGLE_SEARCH_API_KEY";
+pub(crate) static API_KEY_ENV_VAR: LazyLock<EnvVar> = env_var!(API_KEY_ENV_VAR_NAME);
+
+/// The most results Google returns for one request.
+const MAX_RESULTS_PER_PAGE: usize = 10;
+/// Google only serves the first hundred results.
+const MAX_START_INDEX: usize = 91;
+
+/// A Google Programmable Search Engine, which needs both an API key and the
+/// id of the search engine, set in `web_search.providers.google.search_engine_id`.
+pub struct GoogleWebSearchProvider {
+    http_client: Arc<dyn HttpClient>,
+    api_key: Entity<ApiKeyState>,
+}
+
+impl GoogleWebSearchProvider {
+    pub fn new(http_client: Arc<dyn HttpClient>, api_key: Entity<ApiKeyState>) -> Self {
+        Self {
+            http_client,
+            api_key,
+        }
+    }
+}
+
+impl WebSearchProvider for GoogleWebSearchProvider {
+    fn id(&self) -> WebSearchProviderId {
+        WebSearchProviderId(GOOGLE_WEB_SEARCH_PROVIDER_ID.into())
+    }
+
+    fn capabilities(&self) -> WebSearchProviderCapabilities {
+        WebSearchProviderCapabilities {
+            news: false,
+            domain_filters: true,
+            pagination: true,
+            freshness: true,
+            region: true,
+            safe_search: true,
+        }
+    }
+
+    fn search(
+        &self,
+        query: String,
+        options: WebSearchOptions,
+        cx: &mut App,
+    ) -> Task<Result<WebSearchResponse>> {
+        let Some(api_key) = self.api_key.read(cx).key(GOOGLE_API_URL) else {
+            return Task::ready(Err(anyhow!(
+                "No Google Search API key. Set {API_KEY_ENV_VAR_NAME} to use Google Search."
+            )));
+        };
+        self.search_with_key(api_key, query, options, cx)
+    }
+}
+
+impl ApiKeyWebSearchProvider for GoogleWebSearchProvider {
+    fn search_with_key(
+        &self,
+        api_key: Arc<str>,
+        query: String,
+        options: WebSearchOptions,
+        cx: &mut App,
+    ) -> Task<Result<WebSearchResponse>> {
+        let Some(search_engine_id) = WebSearchSettings::get_global(cx)
+            .providers
+            .get(&self.id())
+            .and_then(|settings| settings.search_engine_id.clone())
+        else {
+            return Task::ready(Err(anyhow!(
+                "No Google search engine id. Set web_search.providers.google.search_engine_id to use Google Search."
+            )));
+        };
+        let http_client = self.http_client.clone();
+        let policy = RequestPolicy::for_provider(&self.id(), cx);
+        let executor = cx.background_executor().clone();
+        cx.background_spawn(async move {
+            let url = search_url(&query, &search_engine_id, &options, Utc::now().date_naive())?;
+            // The key is sent in a header rather than the URL, so that it isn't logged.
+            let mut response = send_with_retries(http_client.as_ref(), policy, &executor, || {
+                Ok(http_client::Request::builder()
+                    .method(Method::GET)
+                    .uri(url.as_str())
+                    .header("Accept", "application/json")
+                    .header("X-Goog-Api-Key", api_key.as_ref())
+                    .body(Default::default())?)
+            })
+            .await
+            .context("failed to send Google search request")?;
+            let mut body = String::new();
+            response.body_mut().read_to_string(&mut body).await?;
+            if !response.status().is_success() {
+                return Err(response_error(response.status(), &body));
+            }
+
+            let mut response = parse_response(&body)?;
+            // Google can only restrict results to the last N days.
+            if let Some(before) = options.published_before {
+                response.results.retain(|result| {
+                    result
+                        .published_at
+                        .is_none_or(|published_at| published_at.date_naive() <= before)
+                });
+            }
+            Ok(response)
+        })
+    }
+}
+
+fn search_url(
+    query: &str,
+    search_engine_id: &str,
+    options: &WebSearchOptions,
+    today: NaiveDate,
+) -> Result<Url> {
+    let mut url = Url::parse(GOOGLE_API_URL)?;
+    {
+        let mut query_pairs = url.query_pairs_mut();
+        query_pairs.append_pair("cx", search_engine_id);
+        // Google's `siteSearch` parameter takes a single domain, so any other
+        // combination of domain filters becomes `site:` operators.
+        match (
+            options.include_domains.as_slice(),
+            options.exclude_domains.as_slice(),
+        ) {
+            ([domain], []) => {
+                query_pairs.append_pair("q", query);
+                query_pairs.append_pair("siteSearch", domain);
+                query_pairs.append_pair("siteSearchFilter", "i");
+            }
+            ([], [domain]) => {
+                query_pairs.append_pair("q", query);
+                query_pairs.append_pair("siteSearch", domain);
+                query_pairs.append_pair("siteSearchFilter", "e");
+            }
+            _ => {
+                query_pairs.append_pair("q", &query_with_site_filters(query, options));
+            }
+        }
+        let count = options
+            .max_results
+            .map_or(MAX_RESULTS_PER_PAGE, |max_results| {
+                max_results.clamp(1, MAX_RESULTS_PER_PAGE)
+            });
+        if options.max_results.is_some() {
+            query_pairs.append_pair("num", &count.to_string());
+        }
+        if let Some(page) = options.page.filter(|page| *page > 1) {
+            let start = ((page as usize - 1) * count + 1).min(MAX_START_INDEX);
+            query_pairs.append_pair("start", &start.to_string());
+        }
+        if let Some(after) = options.published_after {
+            let days = today.signed_duration_since(after).num_days().max(1);
+            query_pairs.append_pair("dateRestrict", &format!("d{days}"));
+        }
+        if let Some(language) = &options.language {
+            query_pairs.append_pair("lr", &format!("lang_{}", language.to_lowercase()));
+        }
+        if let Some(region) = &options.region {
+            query_pairs.append_pair("gl", &region.to_lowercase());
+        }
+        if let Some(safe_search) = options.safe_search {
+            let safe_search = match safe_search {
+                SafeSearch::Off => "off",
+                SafeSearch::Moderate | SafeSearch::Strict => "active",
+            };
+            query_pairs.append_pair("safe", safe_search);
+        }
+    }
+    Ok(url)
+}
+
+#[derive(Deserialize)]
+struct GoogleErrorResponse {
+    error: GoogleError,
+}
+
+#[derive(Deserialize)]
+struct GoogleError {
+    message: String,
+}
+
+/// Exceeding the daily query limit or the rate limit becomes
+/// [`WebSearchQuotaExceeded`], so that the registry moves on to another provider.
+fn response_error(status: StatusCode, body: &str) -> anyhow::Error {
+    let message = serde_json::from_str::<GoogleErrorResponse>(body)
+        .map(|response| response.error.message)
+        .unwrap_or_else(|_| body.to_string());
+    if status == StatusCode::TOO_MANY_REQUESTS {
+        return WebSearchQuotaExceeded {
+            message,
+            retry_after: None,
+        }
+        .into();
+    }
+    anyhow!("error performing Google search.\nStatus: {status:?}\n{message}")
+}
+
+#[derive(Deserialize)]
+struct GoogleSearchResponse {
+    #[serde(default)]
+    items: Vec<GoogleItem>,
+}
+
+#[derive(Deserialize)]
+struct GoogleItem {
+    title: String,
+    link: String,
+    #[serde(default)]
+    snippet: String,
+    pagemap: Option<GooglePageMap>,
+}
+
+#[derive(Deserialize)]
+struct GooglePageMap {
+    #[serde(default)]
+    metatags: Vec<HashMap<String, String>>,
+}
+
+fn parse_response(body: &str) -> Result<WebSearchResponse> {
+    let response = serde_json::from_str::<GoogleSearchResponse>(body)?;
+    Ok(WebSearchResponse {
+        results: response
+            .items
+            .into_iter()
+            .map(|item| {
+                let metatags = item
+                    .pagemap
+                    .and_then(|pagemap| pagemap.metatags.into_iter().next())
+                    .unwrap_or_default();
+                WebSearchResult {
+                    title: item.title,
+                    url: item.link,
+                    text: item.snippet,
+                    published_at: metatags
+                        .get("article:published_time")
+                        .and_then(|date| parse_date(date)),
+                    source: metatags.get("og:site_name").cloned(),
+                    ..Default::default()
+                }
+            })
+            .collect(),
+        answer: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_url() {
+        let options = WebSearchOptions {
+            max_results: Some(5),
+            page: Some(3),
+            include_domains: vec!["docs.rs".into()],
+            published_after: NaiveDate::from_ymd_opt(2024, 5, 1),
+            language: Some("EN".into()),
+            safe_search: Some(SafeSearch::Strict),
+            ..Default::default()
+        };
+        let url = search_url(
+            "serde",
+            "engine-id",
+            &options,
+            NaiveDate::from_ymd_opt(2024, 6, 1).unwrap(),
+        )
+        .unwrap();
+        let query_pairs = url.query_pairs().into_owned().collect::<Vec<_>>();
+        assert_eq!(
+            query_pairs,
+            [
+                ("cx".to_string(), "engine-id".to_string()),
+                ("q".to_string(), "serde".to_string()),
+                ("siteSearch".to_string(), "docs.rs".to_string()),
+                ("siteSearchFilter".to_string(), "i".to_string()),
+                ("num".to_string(), "5".to_string()),
+                ("start".to_string(), "11".to_string()),
+                ("dateRestrict".to_string(), "d31".to_string()),
+                ("lr".to_string(), "lang_en".to_string()),
+                ("safe".to_string(), "active".to_string()),
+            ]
+        );
+
+        let options = WebSearchOptions {
+            include_domains: vec!["docs.rs".into()],
+            exclude_domains: vec!["reddit.com".into()],
+            ..Default::default()
+        };
+        let url = search_url(
+            "serde",
+            "engine-id",
+            &options,
+            NaiveDate::from_ymd_opt(2024, 6, 1).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            url.query_pairs().into_owned().collect::<Vec<_>>(),
+            [
+                ("cx".to_string(), "engine-id".to_string()),
+                (
+                    "q".to_string(),
+                    "serde (site:docs.rs) -site:reddit.com".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let body = r#"{
+            "kind": "customsearch#search",
+            "items": [
+                {
+                    "title": "serde - Rust",
+                    "link": "https://docs.rs/serde",
+                    "snippet": "A generic serialization framework.",
+                    "pagemap": {
+                        "metatags": [{
+                            "og:site_name": "Docs.rs",
+                            "article:published_time": "2024-02-03T04:05:06+00:00"
+                        }]
+                    }
+                },
+                { "title": "Serde", "link": "https://serde.rs" }
+            ]
+        }"#;
+        let response = parse_response(body).unwrap();
+        assert_eq!(response.results.len(), 2);
+        let result = &response.results[0];
+        assert_eq!(result.text, "A generic serialization framework.");
+        assert_eq!(result.source.as_deref(), Some("Docs.rs"));
+        assert_eq!(
+            result.published_at.unwrap().to_rfc3339(),
+            "2024-02-03T04:05:06+00:00"
+        );
+        assert!(response.results[1].published_at.is_none());
+
+        assert!(parse_response("{}").unwrap().results.is_empty());
+    }
+}
@@ -0,0 +1,152 @@
+use std::sync::{Arc, LazyLock};
+
+use anyhow::{Context as _, Result};
+use futures::AsyncReadExt as _;
+use gpui::{App, AppContext as _, Entity, Task};
+use http_client::{HttpClient, Method, StatusCode};
+use language_model::{ApiKeyState, EnvVar, env_var};
+use serde::Deserialize;
+use url::Url;
+use web_search::{WebContentProvider, WebSearchProviderId, WebSearchQuotaExceeded};
+
+use crate::request::{RequestPolicy, send_with_retries};
+
+pub const JINA_CONTENT_PROVIDER_ID: &str = "jina";
+pub const JINA_READER_URL: &str = "https://r.jina.ai";
+
+const API_KEY_ENV_VAR_NAME: &str = "JINA_API_KEY";
+pub(crate) static API_KEY_ENV_VAR: LazyLock<EnvVar> = env_var!(API_KEY_ENV_VAR_NAME);
+
+/// Jina's Reader, which turns web pages into Markdown. It works without an
+/// API key, at a lower rate limit.
+pub struct JinaContentProvider {
+    http_client: Arc<dyn HttpClient>,
+    api_key: Entity<ApiKeyState>,
+}
+
+impl JinaContentProvider {
+    pub fn new(http_client: Arc<dyn HttpClient>, api_key: Entity<ApiKeyState>) -> Self {
+        Self {
+            http_client,
+            api_key,
+        }
+    }
+
+    /// Fetches `url` with the given key rather than the stored one, so that
+    /// keys can be checked before they're saved.
+    pub(crate) fn fetch_content_with_key(
+        &self,
+        api_key: Option<Arc<str>>,
+        url: Url,
+        cx: &mut App,
+    ) -> Task<Result<String>> {
+        let http_client = self.http_client.clone();
+        let policy = RequestPolicy::for_provider(&self.id(), cx);
+        let executor = cx.background_executor().clone();
+        cx.background_spawn(async move {
+            let mut response = send_with_retries(http_client.as_ref(), policy, &executor, || {
+                let mut request = http_client::Request::builder()
+                    .method(Method::GET)
+                    .uri(reader_url(&url))
+                    .header("Accept", "application/json")
+                    .header("X-Return-Format", "markdown");
+                if let Some(api_key) = &api_key {
+                    request = request.header("Authorization", format!("Bearer {api_key}"));
+                }
+                Ok(request.body(Default::default())?)
+            })
+            .await
+            .with_context(|| format!("failed to fetch {url} with Jina Reader"))?;
+            let mut body = String::new();
+            response.body_mut().read_to_string(&mut body).await?;
+            if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                return Err(WebSearchQuotaExceeded {
+                    message: body,
+                    retry_after: None,
+                }
+                .into());
+            }
+            anyhow::ensure!(
+                response.status().is_success(),
+                "error fetching {url} with Jina Reader.\nStatus: {:?}\nBody: {body}",
+                response.status(),
+            );
+            parse_response(&body)
+        })
+    }
+}
+
+impl WebContentProvider for JinaContentProvider {
+    fn id(&self) -> WebSearchProviderId {
+        WebSearchProviderId(JINA_CONTENT_PROVIDER_ID.into())
+    }
+
+    fn fetch_content(&self, url: Url, cx: &mut App) -> Task<Result<String>> {
+        let api_key = self.api_key.read(cx).key(JINA_READER_URL);
+        self.fetch_content_with_key(api_key, url, cx)
+    }
+}
+
+/// The Reader takes the page's URL as its path. Fragments are left out, since
+/// they'd be dropped from the request.
+fn reader_url(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    format!("{JINA_READER_URL}/{url}")
+}
+
+#[derive(Deserialize)]
+struct JinaResponse {
+    data: JinaPage,
+}
+
+#[derive(Deserialize)]
+struct JinaPage {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    content: String,
+}
+
+fn parse_response(body: &str) -> Result<String> {
+    let page = serde_json::from_str::<JinaResponse>(body)?.data;
+    let content = page.content.trim();
+    if page.title.is_empty() || content.starts_with('#') {
+        Ok(content.to_string())
+    } else {
+        Ok(format!("# {}\n\n{content}", page.title))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reader_url() {
+        let url = Url::parse("https://example.com/docs?page=2#intro").unwrap();
+        assert_eq!(
+            reader_url(&url),
+            "https://r.jina.ai/https://example.com/docs?page=2"
+        );
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let body = r#"{
+            "code": 200,
+            "data": {
+                "title": "Serde",
+                "url": "https://serde.rs",
+                "content": "Serde is a framework for *ser*ializing and *de*serializing.\n"
+            }
+        }"#;
+        assert_eq!(
+            parse_response(body).unwrap(),
+            "# Serde\n\nSerde is a framework for *ser*ializing and *de*serializing."
+        );
+
+        let body = r##"{"data": {"title": "Serde", "content": "# Serde\n\nOverview"}}"##;
+        assert_eq!(parse_response(body).unwrap(), "# Serde\n\nOverview");
+    }
+}
@@ -0,0 +1,316 @@
+use std::sync::{Arc, LazyLock};
+
+use anyhow::{Context as _, Result, anyhow};
+use chrono::NaiveDate;
+use cloud_llm_client::{WebSearchResponse, WebSearchResult};
+use futures::AsyncReadExt as _;
+use gpui::{App, AppContext as _, Entity, Task};
+use http_client::{AsyncBody, HttpClient, Method, StatusCode};
+use language_model::{ApiKeyState, EnvVar, env_var};
+use serde::{Deserialize, Serialize};
+use web_search::{
+    WebSearchOptions, WebSearchProvider, WebSearchProviderCapabilities, WebSearchProviderId,
+    WebSearchQuotaExceeded,
+};
+
+use crate::{
+    ApiKeyWebSearchProvider, parse_date,
+    request::{RequestPolicy, send_with_retries},
+};
+
+pub const PERPLEXITY_WEB_SEARCH_PROVIDER_ID: &str = "perplexity";
+pub const PERPLEXITY_API_URL: &str = "https://api.perplexity.ai";
+
+const API_KEY_ENV_VAR_NAME: &str = "PERPLEXITY_API_KEY";
+pub(crate) static API_KEY_ENV_VAR: LazyLock<EnvVar> = env_var!(API_KEY_ENV_VAR_NAME);
+
+const SONAR_MODEL: &str = "sonar";
+
+/// Perplexity's Sonar models, which answer the query from the pages they find
+/// and cite those pages as results.
+pub struct PerplexityWebSearchProvider {
+    http_client: Arc<dyn HttpClient>,
+    api_key: Entity<ApiKeyState>,
+}
+
+impl PerplexityWebSearchProvider {
+    pub fn new(http_client: Arc<dyn HttpClient>, api_key: Entity<ApiKeyState>) -> Self {
+        Self {
+            http_client,
+            api_key,
+        }
+    }
+}
+
+impl WebSearchProvider for PerplexityWebSearchProvider {
+    fn id(&self) -> WebSearchProviderId {
+        WebSearchProviderId(PERPLEXITY_WEB_SEARCH_PROVIDER_ID.into())
+    }
+
+    fn capabilities(&self) -> WebSearchProviderCapabilities {
+        WebSearchProviderCapabilities {
+            news: false,
+            domain_filters: true,
+            pagination: false,
+            freshness: true,
+            region: false,
+            safe_search: false,
+        }
+    }
+
+    fn search(
+        &self,
+        query: String,
+        options: WebSearchOptions,
+        cx: &mut App,
+    ) -> Task<Result<WebSearchResponse>> {
+        let Some(api_key) = self.api_key.read(cx).key(PERPLEXITY_API_URL) else {
+            return Task::ready(Err(anyhow!(
+                "No Perplexity API key. Set {API_KEY_ENV_VAR_NAME} to use Perplexity."
+            )));
+        };
+        self.search_with_key(api_key, query, options, cx)
+    }
+}
+
+impl ApiKeyWebSearchProvider for PerplexityWebSearchProvider {
+    fn search_with_key(
+        &self,
+        api_key: Arc<str>,
+        query: String,
+        options: WebSearchOptions,
+        cx: &mut App,
+    ) -> Task<Result<WebSearchResponse>> {
+        let http_client = self.http_client.clone();
+        let policy = RequestPolicy::for_provider(&self.id(), cx);
+        let executor = cx.background_executor().clone();
+        cx.background_spawn(async move {
+            let body = serde_json::to_string(&request_body(query, &options))?;
+            let mut response = send_with_retries(http_client.as_ref(), policy, &executor, || {
+                Ok(http_client::Request::builder()
+                    .method(Method::POST)
+                    .uri(format!("{PERPLEXITY_API_URL}/chat/completions"))
+                    .header("Content-Type", "application/json")
+                    .header("Authorization", format!("Bearer {api_key}"))
+                    .body(AsyncBody::from(body.clone()))?)
+            })
+            .await
+            .context("failed to send Perplexity search request")?;
+            let mut body = String::new();
+            response.body_mut().read_to_string(&mut body).await?;
+            if response.status() == StatusCode::TOO_MANY_REQUESTS {
+                return Err(WebSearchQuotaExceeded {
+                    message: body,
+                    retry_after: None,
+                }
+                .into());
+            }
+            anyhow::ensure!(
+                response.status().is_success(),
+                "error performing Perplexity search.\nStatus: {:?}\nBody: {body}",
+                response.status(),
+            );
+
+            let mut response = parse_response(&body)?;
+            if let Some(max_results) = options.max_results {
+                response.results.truncate(max_results);
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct PerplexityRequest {
+    model: &'static str,
+    messages: Vec<PerplexityMessage>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    search_domain_filter: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    search_after_date_filter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    search_before_date_filter: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PerplexityMessage {
+    role: String,
+    content: String,
+}
+
+fn request_body(query: String, options: &WebSearchOptions) -> PerplexityRequest {
+    // Perplexity takes dates as month/day/year.
+    let format_date = |date: NaiveDate| date.format("%-m/%-d/%Y").to_string();
+    PerplexityRequest {
+        model: SONAR_MODEL,
+        messages: vec![PerplexityMessage {
+            role: "user".into(),
+            content: query,
+        }],
+        // Excluded domains are prefixed with `-`.
+        search_domain_filter: options
+            .include_domains
+            .iter()
+            .cloned()
+            .chain(
+                options
+                    .exclude_domains
+                    .iter()
+                    .map(|domain| format!("-{domain}")),
+            )
+            .collect(),
+        search_after_date_filter: options.published_after.map(format_date),
+        search_before_date_filter: options.published_before.map(format_date),
+    }
+}
+
+#[derive(Deserialize)]
+struct PerplexityResponse {
+    #[serde(default)]
+    choices: Vec<PerplexityChoice>,
+    #[serde(default)]
+    citations: Vec<String>,
+    #[serde(default)]
+    search_results: Vec<PerplexitySearchResult>,
+}
+
+#[derive(Deserialize)]
+struct PerplexityChoice {
+    message: PerplexityMessage,
+}
+
+#[derive(Deserialize)]
+struct PerplexitySearchResult {
+    title: String,
+    url: String,
+    date: Option<String>,
+    #[serde(default)]
+    snippet: String,
+}
+
+fn parse_response(body: &str) -> Result<WebSearchResponse> {
+    let response = serde_json::from_str::<PerplexityResponse>(body)?;
+    let answer = response
+        .choices
+        .into_iter()
+        .next()
+        .map(|choice| link_citations(&choice.message.content, &response.citations));
+    // Older responses only list the cited URLs.
+    let results = if response.search_results.is_empty() {
+        response
+            .citations
+            .into_iter()
+            .map(|url| WebSearchResult {
+                title: url.clone(),
+                url,
+                ..Default::default()
+            })
+            .collect()
+    } else {
+        response
+            .search_results
+            .into_iter()
+            .map(|result| WebSearchResult {
+                title: result.title,
+                url: result.url,
+                text: result.snippet,
+                published_at: result.date.as_deref().and_then(|date| {
+                    parse_date(date).or_else(|| {
+                        NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                            .ok()
+                            .and_then(|date| date.and_hms_opt(0, 0, 0))
+                            .map(|date| date.and_utc())
+                    })
+                }),
+                ..Default::default()
+            })
+            .collect()
+    };
+    Ok(WebSearchResponse { results, answer })
+}
+
+/// Turns citation markers like `[1]` into links to the cited URLs, so that the
+/// answer still makes sense once results are reordered or merged.
+fn link_citations(answer: &str, citations: &[String]) -> String {
+    let mut linked = String::with_capacity(answer.len());
+    let mut rest = answer;
+    while let Some(start) = rest.find('[') {
+        linked.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let citation = rest[1..].find(']').and_then(|end| {
+            let number = rest[1..end + 1].parse::<usize>().ok()?;
+            let url = citations.get(number.checked_sub(1)?)?;
+            Some((end + 2, format!("[{number}]({url})")))
+        });
+        match citation {
+            Some((len, link)) => {
+                linked.push_str(&link);
+                rest = &rest[len..];
+            }
+            None => {
+                linked.push('[');
+                rest = &rest[1..];
+            }
+        }
+    }
+    linked.push_str(rest);
+    linked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_body() {
+        let options = WebSearchOptions {
+            include_domains: vec!["docs.rs".into()],
+            exclude_domains: vec!["reddit.com".into()],
+            published_after: NaiveDate::from_ymd_opt(2024, 3, 5),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_value(request_body("serde".into(), &options)).unwrap(),
+            serde_json::json!({
+                "model": "sonar",
+                "messages": [{ "role": "user", "content": "serde" }],
+                "search_domain_filter": ["docs.rs", "-reddit.com"],
+                "search_after_date_filter": "3/5/2024"
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let body = r#"{
+            "id": "1",
+            "model": "sonar",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": "Serde is a serialization framework [1][2]. See [3] and [docs]."
+                }
+            }],
+            "citations": ["https://serde.rs", "https://docs.rs/serde"],
+            "search_results": [
+                { "title": "Serde", "url": "https://serde.rs", "date": "2024-02-03" },
+                { "title": "serde - Rust", "url": "https://docs.rs/serde", "date": null }
+            ]
+        }"#;
+        let response = parse_response(body).unwrap();
+        assert_eq!(
+            response.answer.as_deref(),
+            Some(
+                "Serde is a serialization framework [1](https://serde.rs)[2](https://docs.rs/serde). See [3] and [docs]."
+            )
+        );
+        assert_eq!(response.results.len(), 2);
+        assert_eq!(response.results[0].title, "Serde");
+        assert_eq!(
+            response.results[0].published_at.unwrap().to_rfc3339(),
+            "2024-02-03T00:00:00+00:00"
+        );
+        assert!(response.results[1].published_at.is_none());
+    }
+}
@@ -0,0 +1,151 @@
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use futures::FutureExt as _;
+use gpui::{App, BackgroundExecutor};
+use http_client::{AsyncBody, HttpClient, Request, Response, StatusCode};
+use settings::Settings as _;
+use web_search::{WebSearchProviderId, WebSearchSettings};
+
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_MAX_RETRIES: u32 = 2;
+const DEFAULT_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// How long a provider's requests may take and how they're retried, from
+/// `web_search.providers` in the settings.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct RequestPolicy {
+    pub timeout: Duration,
+    pub max_retries: u32,
+    pub retry_backoff: Duration,
+}
+
+impl Default for RequestPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_backoff: DEFAULT_RETRY_BACKOFF,
+        }
+    }
+}
+
+impl RequestPolicy {
+    pub fn for_provider(provider: &WebSearchProviderId, cx: &App) -> Self {
+        let Some(settings) = WebSearchSettings::get_global(cx).providers.get(provider) else {
+            return Self::default();
+        };
+        Self {
+            timeout: settings.request_timeout.unwrap_or(DEFAULT_REQUEST_TIMEOUT),
+            max_retries: settings.max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+            retry_backoff: settings.retry_backoff.unwrap_or(DEFAULT_RETRY_BACKOFF),
+        }
+    }
+
+    /// How long to wait before the given retry, counting from 1.
+    fn backoff(&self, retry: u32) -> Duration {
+        self.retry_backoff
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+    }
+}
+
+/// Sends the request made by `build_request`, giving up on each attempt after
+/// the policy's timeout. Attempts that time out, fail to connect or get a
+/// server error are retried with exponential backoff, but other error
+/// responses, such as rate limits, are returned for the provider to handle.
+pub(crate) async fn send_with_retries(
+    http_client: &dyn HttpClient,
+    policy: RequestPolicy,
+    executor: &BackgroundExecutor,
+    build_request: impl Fn() -> Result<Request<AsyncBody>>,
+) -> Result<Response<AsyncBody>> {
+    let mut retry = 0;
+    loop {
+        let request = build_request()?;
+        let outcome = futures::select_biased! {
+            response = http_client.send(request).fuse() => response,
+            () = executor.timer(policy.timeout).fuse() => {
+                Err(anyhow!("no response within {:?}", policy.timeout))
+            }
+        };
+        let should_retry = match &outcome {
+            Ok(response) => {
+                response.status().is_server_error()
+                    || response.status() == StatusCode::REQUEST_TIMEOUT
+            }
+            Err(_) => true,
+        };
+        if !should_retry || retry >= policy.max_retries {
+            return outcome;
+        }
+        retry += 1;
+        executor.timer(policy.backoff(retry)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    };
+
+    use gpui::TestAppContext;
+    use http_client::FakeHttpClient;
+
+    use super::*;
+
+    #[gpui::test]
+    async fn test_send_with_retries(cx: &mut TestAppContext) {
+        let attempts = Arc::new(AtomicU32::new(0));
+        let http_client = FakeHttpClient::create({
+            let attempts = attempts.clone();
+            move |_| {
+                let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    let status = if attempt < 2 {
+                        StatusCode::BAD_GATEWAY
+                    } else {
+                        StatusCode::OK
+                    };
+                    Ok(Response::builder()
+                        .status(status)
+                        .body(AsyncBody::default())?)
+                }
+            }
+        });
+        let executor = cx.executor();
+        let policy = RequestPolicy {
+            timeout: Duration::from_secs(1),
+            max_retries: 2,
+            retry_backoff: Duration::ZERO,
+        };
+        let build_request = || -> Result<Request<AsyncBody>> {
+            Ok(Request::get("https://example.com").body(AsyncBody::default())?)
+        };
+
+        let response = send_with_retries(http_client.as_ref(), policy, &executor, build_request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        attempts.store(0, Ordering::SeqCst);
+        let policy = RequestPolicy {
+            max_retries: 1,
+            ..policy
+        };
+        let response = send_with_retries(http_client.as_ref(), policy, &executor, build_request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RequestPolicy::default();
+        assert_eq!(policy.backoff(1), Duration::from_millis(500));
+        assert_eq!(policy.backoff(3), Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,250 @@
+use std::sync::Arc;
+
+use anyhow::{Context as _, Result};
+use chrono::{NaiveDate, Utc};
+use cloud_llm_client::{WebSearchResponse, WebSearchResult};
+use futures::AsyncReadExt as _;
+use gpui::{App, AppContext as _, Task};
+use http_client::{HttpClient, Method};
+use serde::Deserialize;
+use url::Url;
+use web_search::{
+    SafeSearch, SearchKind, WebSearchOptions, WebSearchProvider, WebSearchProviderCapabilities,
+    WebSearchProviderId,
+};
+
+use crate::{
+    html_to_text, parse_date,
+    request::{RequestPolicy, send_with_retries},
+};
+
+pub const SEARXNG_WEB_SEARCH_PROVIDER_ID: &str = "searxng";
+
+/// A self-hosted SearXNG instance. Its JSON output format must be enabled in
+/// the instance's `search.formats` setting.
+pub struct SearxngWebSearchProvider {
+    http_client: Arc<dyn HttpClient>,
+    api_url: String,
+}
+
+impl SearxngWebSearchProvider {
+    pub fn new(http_client: Arc<dyn HttpClient>, api_url: String) -> Self {
+        Self {
+            http_client,
+            api_url,
+        }
+    }
+}
+
+impl WebSearchProvider for SearxngWebSearchProvider {
+    fn id(&self) -> WebSearchProviderId {
+        WebSearchProviderId(SEARXNG_WEB_SEARCH_PROVIDER_ID.into())
+    }
+
+    fn capabilities(&self) -> WebSearchProviderCapabilities {
+        WebSearchProviderCapabilities {
+            news: true,
+            domain_filters: true,
+            pagination: true,
+            freshness: true,
+            region: false,
+            safe_search: true,
+        }
+    }
+
+    fn search(
+        &self,
+        query: String,
+        options: WebSearchOptions,
+        cx: &mut App,
+    ) -> Task<Result<WebSearchResponse>> {
+        let http_client = self.http_client.clone();
+        let policy = RequestPolicy::for_provider(&self.id(), cx);
+        let executor = cx.background_executor().clone();
+        let api_url = self.api_url.clone();
+        cx.background_spawn(async move {
+            let url = search_url(&api_url, &query, &options, Utc::now().date_naive())?;
+            let mut response = send_with_retries(http_client.as_ref(), policy, &executor, || {
+                Ok(http_client::Request::builder()
+                    .method(Method::GET)
+                    .uri(url.as_str())
+                    .header("Accept", "application/json")
+                    .body(Default::default())?)
+            })
+            .await
+                .with_context(|| format!("failed to send search request to SearXNG at {api_url}"))?;
+            let mut body = String::new();
+            response.body_mut().read_to_string(&mut body).await?;
+            anyhow::ensure!(
+                response.status().is_success(),
+                "error performing SearXNG search. Make sure the instance allows the json format.\nStatus: {:?}\nBody: {body}",
+                response.status(),
+            );
+
+            let mut response = parse_response(&body)?;
+            // SearXNG has no domain filters and only coarse time ranges, so
+            // they're applied to its results.
+            response.results.retain(|result| {
+                let published_on = result.published_at.map(|date| date.date_naive());
+                options.allows_url(&result.url)
+                    && published_on.is_none_or(|published_on| {
+                        options
+                            .published_after
+                            .is_none_or(|after| published_on >= after)
+                            && options
+                                .published_before
+                                .is_none_or(|before| published_on <= before)
+                    })
+            });
+            if let Some(max_results) = options.max_results {
+                response.results.truncate(max_results);
+            }
+            Ok(response)
+        })
+    }
+}
+
+fn search_url(
+    api_url: &str,
+    query: &str,
+    options: &WebSearchOptions,
+    today: NaiveDate,
+) -> Result<Url> {
+    // Without a trailing slash, joining would replace the last path segment.
+    let mut url = Url::parse(&format!("{}/", api_url.trim_end_matches('/')))
+        .with_context(|| format!("invalid SearXNG URL {api_url:?}"))?
+        .join("search")?;
+    {
+        let mut query_pairs = url.query_pairs_mut();
+        query_pairs.append_pair("q", query);
+        query_pairs.append_pair("format", "json");
+        if options.kind == SearchKind::News {
+            query_pairs.append_pair("categories", "news");
+        }
+        if let Some(page) = options.page.filter(|page| *page > 1) {
+            query_pairs.append_pair("pageno", &page.to_string());
+        }
+        if let Some(time_range) = options
+            .published_after
+            .and_then(|after| time_range(today.signed_duration_since(after).num_days()))
+        {
+            query_pairs.append_pair("time_range", time_range);
+        }
+        if let Some(language) = &options.language {
+            query_pairs.append_pair("language", language);
+        }
+        if let Some(safe_search) = options.safe_search {
+            let safe_search = match safe_search {
+                SafeSearch::Off => "0",
+                SafeSearch::Moderate => "1",
+                SafeSearch::Strict => "2",
+            };
+            query_pairs.append_pair("safesearch", safe_search);
+        }
+    }
+    Ok(url)
+}
+
+/// The narrowest of SearXNG's time ranges that covers the last `days` days.
+fn time_range(days: i64) -> Option<&'static str> {
+    match days {
+        ..=1 => Some("day"),
+        2..=7 => Some("week"),
+        8..=31 => Some("month"),
+        32..=366 => Some("year"),
+        _ => None,
+    }
+}
+
+#[derive(Deserialize)]
+struct SearxngResponse {
+    #[serde(default)]
+    results: Vec<SearxngResult>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SearxngResult {
+    title: String,
+    url: String,
+    #[serde(default)]
+    content: String,
+    published_date: Option<String>,
+    score: Option<f32>,
+}
+
+fn parse_response(body: &str) -> Result<WebSearchResponse> {
+    let response = serde_json::from_str::<SearxngResponse>(body)?;
+    Ok(WebSearchResponse {
+        results: response
+            .results
+            .into_iter()
+            .map(|result| WebSearchResult {
+                title: html_to_text(&result.title),
+                url: result.url,
+                text: html_to_text(&result.content),
+                published_at: result.published_date.as_deref().and_then(parse_date),
+                score: result.score,
+                ..Default::default()
+            })
+            .collect(),
+        answer: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_url() {
+        let options = WebSearchOptions {
+            kind: SearchKind::News,
+            page: Some(2),
+            published_after: NaiveDate::from_ymd_opt(2024, 5, 28),
+            safe_search: Some(SafeSearch::Moderate),
+            ..Default::default()
+        };
+        let url = search_url(
+            "http://localhost:8888",
+            "rust release",
+            &options,
+            NaiveDate::from_ymd_opt(2024, 6, 1).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            url.as_str(),
+            "http://localhost:8888/search?q=rust+release&format=json&categories=news&pageno=2&time_range=week&safesearch=1"
+        );
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let body = r#"{
+            "query": "rust",
+            "results": [
+                {
+                    "url": "https://rust-lang.org",
+                    "title": "Rust",
+                    "content": "A language empowering everyone.",
+                    "publishedDate": "2024-02-03T04:05:06",
+                    "score": 1.5
+                },
+                {
+                    "url": "https://doc.rust-lang.org",
+                    "title": "Docs",
+                    "publishedDate": null
+                }
+            ]
+        }"#;
+        let response = parse_response(body).unwrap();
+        assert_eq!(response.results.len(), 2);
+        assert_eq!(response.results[0].text, "A language empowering everyone.");
+        assert_eq!(response.results[0].score, Some(1.5));
+        assert_eq!(
+            response.results[0].published_at.unwrap().to_rfc3339(),
+            "2024-02-03T04:05:06+00:00"
+        );
+        assert!(response.results[1].published_at.is_none());
+    }
+}
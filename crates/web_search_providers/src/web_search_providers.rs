@@ -1,10 +1,36 @@
+mod bing;
+mod brave;
 mod cloud;
+mod duckduckgo;
+mod google;
+mod jina;
+mod perplexity;
+mod request;
+mod searxng;
 
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, NaiveDateTime, Utc};
 use client::Client;
-use gpui::{App, Context, Entity};
-use language_model::LanguageModelRegistry;
-use std::sync::Arc;
-use web_search::{WebSearchProviderId, WebSearchRegistry};
+use cloud_llm_client::WebSearchResponse;
+use collections::HashMap;
+use gpui::{App, AppContext as _, Context, Entity, Global, SharedString, Task};
+use html_to_markdown::{
+    HandleTag, HtmlElement, MarkdownWriter, StartTagOutcome, TagHandler, convert_html_to_markdown,
+    markdown::WebpageChromeRemover,
+};
+use http_client::HttpClient;
+use language_model::{ApiKeyState, EnvVar, LanguageModelRegistry};
+use settings::{Settings as _, SettingsStore};
+use std::{cell::RefCell, rc::Rc, sync::Arc};
+use url::Url;
+use web_search::{
+    WebSearchOptions, WebSearchProvider, WebSearchProviderId, WebSearchRegistry, WebSearchSettings,
+};
+
+/// The query searched for to check that an API key works.
+const API_KEY_VALIDATION_QUERY: &str = "zed";
+/// The page fetched to check that a Jina Reader API key works.
+const API_KEY_VALIDATION_URL: &str = "https://example.com";
 
 pub fn init(client: Arc<Client>, cx: &mut App) {
     let registry = WebSearchRegistry::global(cx);
@@ -25,6 +51,57 @@ fn register_web_search_providers(
         cx,
     );
 
+    let http_client: Arc<dyn HttpClient> = client.http_client();
+    register_api_key_provider(
+        WebSearchProviderId(bing::BING_WEB_SEARCH_PROVIDER_ID.into()),
+        bing::BING_API_URL.into(),
+        bing::API_KEY_ENV_VAR.clone(),
+        {
+            let http_client = http_client.clone();
+            move |api_key| bing::BingWebSearchProvider::new(http_client.clone(), api_key)
+        },
+        cx,
+    );
+    register_api_key_provider(
+        WebSearchProviderId(brave::BRAVE_WEB_SEARCH_PROVIDER_ID.into()),
+        brave::BRAVE_API_URL.into(),
+        brave::API_KEY_ENV_VAR.clone(),
+        {
+            let http_client = http_client.clone();
+            move |api_key| brave::BraveWebSearchProvider::new(http_client.clone(), api_key)
+        },
+        cx,
+    );
+    register_api_key_provider(
+        WebSearchProviderId(google::GOOGLE_WEB_SEARCH_PROVIDER_ID.into()),
+        google::GOOGLE_API_URL.into(),
+        google::API_KEY_ENV_VAR.clone(),
+        {
+            let http_client = http_client.clone();
+            move |api_key| google::GoogleWebSearchProvider::new(http_client.clone(), api_key)
+        },
+        cx,
+    );
+    register_api_key_provider(
+        WebSearchProviderId(perplexity::PERPLEXITY_WEB_SEARCH_PROVIDER_ID.into()),
+        perplexity::PERPLEXITY_API_URL.into(),
+        perplexity::API_KEY_ENV_VAR.clone(),
+        {
+            let http_client = http_client.clone();
+            move |api_key| {
+                perplexity::PerplexityWebSearchProvider::new(http_client.clone(), api_key)
+            }
+        },
+        cx,
+    );
+
+    register_searxng_provider(registry, http_client.clone(), cx);
+    register_jina_content_provider(registry, http_client.clone(), cx);
+    registry.register_provider(
+        duckduckgo::DuckDuckGoWebSearchProvider::new(http_client),
+        cx,
+    );
+
     cx.subscribe(
         &LanguageModelRegistry::global(cx),
         move |this, registry, event, cx| {
@@ -49,8 +126,454 @@ fn register_zed_web_search_provider(
     if using_zed_provider {
         registry.register_provider(cloud::CloudWebSearchProvider::new(client, cx), cx)
     } else {
-        registry.unregister_provider(WebSearchProviderId(
-            cloud::ZED_WEB_SEARCH_PROVIDER_ID.into(),
-        ));
+        registry.unregister_provider(
+            WebSearchProviderId(cloud::ZED_WEB_SEARCH_PROVIDER_ID.into()),
+            cx,
+        );
+    }
+}
+
+/// Registers SearXNG whenever an instance's URL is set in the settings.
+fn register_searxng_provider(
+    registry: &mut WebSearchRegistry,
+    http_client: Arc<dyn HttpClient>,
+    cx: &mut Context<WebSearchRegistry>,
+) {
+    let id = WebSearchProviderId(searxng::SEARXNG_WEB_SEARCH_PROVIDER_ID.into());
+    let mut registered_api_url = None;
+    let mut update_registration =
+        move |registry: &mut WebSearchRegistry, cx: &mut Context<WebSearchRegistry>| {
+            let api_url = WebSearchSettings::get_global(cx)
+                .providers
+                .get(&id)
+                .and_then(|settings| settings.api_url.clone());
+            if api_url == registered_api_url {
+                return;
+            }
+            registered_api_url = api_url.clone();
+            match api_url {
+                Some(api_url) => registry.register_provider(
+                    searxng::SearxngWebSearchProvider::new(http_client.clone(), api_url),
+                    cx,
+                ),
+                None => registry.unregister_provider(id.clone(), cx),
+            }
+        };
+    update_registration(registry, cx);
+    cx.observe_global::<SettingsStore>(update_registration)
+        .detach();
+}
+
+/// Registers Jina's Reader for fetching page content, using an API key for
+/// higher rate limits when there is one.
+fn register_jina_content_provider(
+    registry: &mut WebSearchRegistry,
+    http_client: Arc<dyn HttpClient>,
+    cx: &mut Context<WebSearchRegistry>,
+) {
+    let api_url = SharedString::from(jina::JINA_READER_URL);
+    let api_key = cx.new(|_| ApiKeyState::new(api_url.clone(), jina::API_KEY_ENV_VAR.clone()));
+    api_key
+        .update(cx, |api_key, cx| {
+            api_key.load_if_needed(api_url.clone(), |api_key| api_key, cx)
+        })
+        .detach();
+    registry.register_content_provider(jina::JinaContentProvider::new(
+        http_client.clone(),
+        api_key.clone(),
+    ));
+    let provider = jina::JinaContentProvider::new(http_client, api_key.clone());
+    cx.default_global::<WebSearchApiKeys>().0.insert(
+        WebSearchProviderId(jina::JINA_CONTENT_PROVIDER_ID.into()),
+        ProviderApiKey {
+            api_url,
+            state: api_key,
+            validate: Rc::new(move |api_key: Arc<str>, cx: &mut App| {
+                let Ok(url) = Url::parse(API_KEY_VALIDATION_URL) else {
+                    return Task::ready(Err(anyhow!("invalid validation URL")));
+                };
+                let fetch = provider.fetch_content_with_key(Some(api_key), url, cx);
+                cx.background_spawn(async move { fetch.await.map(drop) })
+            }),
+        },
+    );
+}
+
+/// Registers the provider that `build_provider` creates whenever there's an API
+/// key for it, from the environment or the system keychain, and unregisters
+/// it when the key is removed.
+fn register_api_key_provider<P: ApiKeyWebSearchProvider + 'static>(
+    id: WebSearchProviderId,
+    api_url: SharedString,
+    env_var: EnvVar,
+    build_provider: impl Fn(Entity<ApiKeyState>) -> P + 'static,
+    cx: &mut Context<WebSearchRegistry>,
+) {
+    let build_provider = Rc::new(build_provider);
+    let api_key = cx.new(|_| ApiKeyState::new(api_url.clone(), env_var));
+    cx.observe(&api_key, {
+        let id = id.clone();
+        let build_provider = build_provider.clone();
+        move |registry, api_key, cx| {
+            if api_key.read(cx).has_key() {
+                registry.register_provider(build_provider(api_key), cx);
+            } else {
+                registry.unregister_provider(id.clone(), cx);
+            }
+        }
+    })
+    .detach();
+    api_key
+        .update(cx, |api_key, cx| {
+            api_key.load_if_needed(api_url.clone(), |api_key| api_key, cx)
+        })
+        .detach();
+
+    let validate = Rc::new({
+        let api_key = api_key.clone();
+        move |key: Arc<str>, cx: &mut App| {
+            let options = WebSearchOptions {
+                max_results: Some(1),
+                ..Default::default()
+            };
+            let search = build_provider(api_key.clone()).search_with_key(
+                key,
+                API_KEY_VALIDATION_QUERY.into(),
+                options,
+                cx,
+            );
+            cx.background_spawn(async move { search.await.map(drop) })
+        }
+    });
+    cx.default_global::<WebSearchApiKeys>().0.insert(
+        id,
+        ProviderApiKey {
+            api_url,
+            state: api_key,
+            validate,
+        },
+    );
+}
+
+/// A web search provider that authenticates with an API key.
+trait ApiKeyWebSearchProvider: WebSearchProvider {
+    /// Searches with the given key rather than the stored one, so that keys
+    /// can be checked before they're saved.
+    fn search_with_key(
+        &self,
+        api_key: Arc<str>,
+        query: String,
+        options: WebSearchOptions,
+        cx: &mut App,
+    ) -> Task<Result<WebSearchResponse>>;
+}
+
+/// The API keys of the providers that use one, so that they can be managed
+/// from the settings UI.
+#[derive(Default)]
+struct WebSearchApiKeys(HashMap<WebSearchProviderId, ProviderApiKey>);
+
+impl Global for WebSearchApiKeys {}
+
+struct ProviderApiKey {
+    api_url: SharedString,
+    state: Entity<ApiKeyState>,
+    validate: Rc<dyn Fn(Arc<str>, &mut App) -> Task<Result<()>>>,
+}
+
+/// How a provider's API key is configured.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ApiKeyStatus {
+    Missing,
+    Stored,
+    /// The key is set by this environment variable, so it can't be changed
+    /// or deleted from Zed.
+    FromEnvVar(SharedString),
+}
+
+/// The providers that use an API key, sorted by id.
+pub fn api_key_providers(cx: &App) -> Vec<WebSearchProviderId> {
+    let mut providers = cx
+        .try_global::<WebSearchApiKeys>()
+        .map(|api_keys| api_keys.0.keys().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+    providers.sort();
+    providers
+}
+
+/// How the API key for `provider` is configured, or `None` if the provider
+/// doesn't use one.
+pub fn api_key_status(provider: &WebSearchProviderId, cx: &App) -> Option<ApiKeyStatus> {
+    let state = cx
+        .try_global::<WebSearchApiKeys>()?
+        .0
+        .get(provider)?
+        .state
+        .read(cx);
+    Some(if !state.has_key() {
+        ApiKeyStatus::Missing
+    } else if state.is_from_env_var() {
+        ApiKeyStatus::FromEnvVar(state.env_var_name().clone())
+    } else {
+        ApiKeyStatus::Stored
+    })
+}
+
+/// Checks that `api_key` works for `provider` by making a cheap request with
+/// it, without storing it.
+pub fn validate_api_key(
+    provider: &WebSearchProviderId,
+    api_key: &str,
+    cx: &mut App,
+) -> Task<Result<()>> {
+    let api_key = api_key.trim();
+    if api_key.is_empty() {
+        return Task::ready(Err(anyhow!("The API key is empty.")));
+    }
+    let validate = match provider_api_key(provider, cx) {
+        Ok(provider_api_key) => provider_api_key.validate.clone(),
+        Err(error) => return Task::ready(Err(error)),
+    };
+    validate(api_key.into(), cx)
+}
+
+/// Saves `api_key` for `provider` in the system keychain.
+pub fn store_api_key(
+    provider: &WebSearchProviderId,
+    api_key: String,
+    cx: &mut App,
+) -> Task<Result<()>> {
+    let api_key = api_key.trim().to_string();
+    if api_key.is_empty() {
+        return Task::ready(Err(anyhow!("The API key is empty.")));
+    }
+    update_stored_api_key(provider, Some(api_key), cx)
+}
+
+/// Removes the API key for `provider` from the system keychain.
+pub fn delete_api_key(provider: &WebSearchProviderId, cx: &mut App) -> Task<Result<()>> {
+    update_stored_api_key(provider, None, cx)
+}
+
+fn update_stored_api_key(
+    provider: &WebSearchProviderId,
+    api_key: Option<String>,
+    cx: &mut App,
+) -> Task<Result<()>> {
+    let (api_url, state) = match provider_api_key(provider, cx) {
+        Ok(provider_api_key) => (
+            provider_api_key.api_url.clone(),
+            provider_api_key.state.clone(),
+        ),
+        Err(error) => return Task::ready(Err(error)),
+    };
+    if state.read(cx).is_from_env_var() {
+        return Task::ready(Err(anyhow!(
+            "The {} API key is set by {}. Unset it to change the key in Zed.",
+            provider.0,
+            state.read(cx).env_var_name(),
+        )));
+    }
+    state.update(cx, |state, cx| {
+        state.store(api_url, api_key, |state| state, cx)
+    })
+}
+
+fn provider_api_key<'a>(provider: &WebSearchProviderId, cx: &'a App) -> Result<&'a ProviderApiKey> {
+    cx.try_global::<WebSearchApiKeys>()
+        .and_then(|api_keys| api_keys.0.get(provider))
+        .ok_or_else(|| anyhow!("{} doesn't use an API key.", provider.0))
+}
+
+/// Adds `site:` operators for the domain filters in `options` to `query`, for
+/// search APIs that have no parameters for them.
+fn query_with_site_filters(query: &str, options: &WebSearchOptions) -> String {
+    let mut query = query.to_string();
+    if !options.include_domains.is_empty() {
+        let sites = options
+            .include_domains
+            .iter()
+            .map(|domain| format!("site:{domain}"))
+            .collect::<Vec<_>>()
+            .join(" OR ");
+        query.push_str(&format!(" ({sites})"));
+    }
+    for domain in &options.exclude_domains {
+        query.push_str(&format!(" -site:{domain}"));
+    }
+    query
+}
+
+/// The text that `html`, such as a highlighted search snippet, displays: tags
+/// are removed along with scripts and styles, entities are decoded and
+/// whitespace is collapsed.
+fn html_to_text(html: &str) -> String {
+    let text = if html.contains(['<', '&']) {
+        let mut handlers: Vec<TagHandler> = vec![
+            Rc::new(RefCell::new(WebpageChromeRemover)),
+            Rc::new(RefCell::new(WordSeparatorHandler)),
+        ];
+        convert_html_to_markdown(html.as_bytes(), &mut handlers)
+            .unwrap_or_else(|_| html.to_string())
+    } else {
+        html.to_string()
+    };
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Keeps the words on either side of line breaks and block boundaries apart.
+struct WordSeparatorHandler;
+
+impl HandleTag for WordSeparatorHandler {
+    fn should_handle(&self, tag: &str) -> bool {
+        matches!(tag, "br" | "p" | "div" | "li" | "tr" | "td" | "th")
+    }
+
+    fn handle_tag_start(
+        &mut self,
+        _tag: &HtmlElement,
+        writer: &mut MarkdownWriter,
+    ) -> StartTagOutcome {
+        writer.push_str(" ");
+        StartTagOutcome::Continue
+    }
+}
+
+/// Parses a date from a search API, treating dates without a time zone as UTC.
+fn parse_date(date: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(date)
+        .map(|date| date.to_utc())
+        .or_else(|_| {
+            NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S%.f").map(|date| date.and_utc())
+        })
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use gpui::TestAppContext;
+    use http_client::{FakeHttpClient, Response};
+
+    use super::*;
+
+    const VALID_API_KEY: &str = "valid-key";
+
+    #[test]
+    fn test_html_to_text() {
+        assert_eq!(
+            html_to_text("Use <strong>serde</strong> &amp; <em>serde_json</em>"),
+            "Use serde & serde_json"
+        );
+        assert_eq!(
+            html_to_text("It&#x27;s &lt;Vec&lt;u8&gt;&gt; &quot;bytes&quot;&nbsp;here"),
+            "It's <Vec<u8>> \"bytes\" here"
+        );
+        assert_eq!(
+            html_to_text("<script>alert('hi')</script><style>p { color: red }</style>Visible"),
+            "Visible"
+        );
+        assert_eq!(
+            html_to_text(
+                "<div><p>First<br>line</p><ul><li>a <b>bold <i>nested</i></b></li><li>b</li></ul></div>"
+            ),
+            "First line a bold nested b"
+        );
+        assert_eq!(html_to_text("if a < b && c"), "if a < b && c");
+        assert_eq!(html_to_text("  plain\n text "), "plain text");
+    }
+
+    fn init_test(cx: &mut TestAppContext) -> Entity<WebSearchRegistry> {
+        cx.update(|cx| {
+            let settings_store = SettingsStore::test(cx);
+            cx.set_global(settings_store);
+            web_search::init(cx);
+            let http_client: Arc<dyn HttpClient> = FakeHttpClient::create(|request| async move {
+                let authorized = request
+                    .headers()
+                    .get("X-Subscription-Token")
+                    .is_some_and(|api_key| api_key.as_bytes() == VALID_API_KEY.as_bytes());
+                if authorized {
+                    Ok(Response::new(r#"{"web":{"results":[]}}"#.into()))
+                } else {
+                    Ok(Response::builder()
+                        .status(401)
+                        .body("invalid subscription token".into())?)
+                }
+            });
+            let registry = WebSearchRegistry::global(cx);
+            registry.update(cx, |_, cx| {
+                register_api_key_provider(
+                    WebSearchProviderId(brave::BRAVE_WEB_SEARCH_PROVIDER_ID.into()),
+                    brave::BRAVE_API_URL.into(),
+                    brave::API_KEY_ENV_VAR.clone(),
+                    move |api_key| brave::BraveWebSearchProvider::new(http_client.clone(), api_key),
+                    cx,
+                );
+            });
+            registry
+        })
+    }
+
+    fn is_registered(
+        registry: &Entity<WebSearchRegistry>,
+        provider: &WebSearchProviderId,
+        cx: &mut TestAppContext,
+    ) -> bool {
+        registry.read_with(cx, |registry, _| {
+            registry
+                .providers()
+                .any(|registered| registered.id() == *provider)
+        })
+    }
+
+    #[gpui::test]
+    async fn test_api_key_management(cx: &mut TestAppContext) {
+        let registry = init_test(cx);
+        cx.run_until_parked();
+        let brave = WebSearchProviderId(brave::BRAVE_WEB_SEARCH_PROVIDER_ID.into());
+        assert_eq!(cx.update(|cx| api_key_providers(cx)), [brave.clone()]);
+        assert_eq!(
+            cx.update(|cx| api_key_status(&brave, cx)),
+            Some(ApiKeyStatus::Missing)
+        );
+        assert!(!is_registered(&registry, &brave, cx));
+
+        cx.update(|cx| validate_api_key(&brave, VALID_API_KEY, cx))
+            .await
+            .unwrap();
+        let error = cx
+            .update(|cx| validate_api_key(&brave, "invalid-key", cx))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("401"), "{error}");
+        // Validating a key doesn't store it.
+        assert_eq!(
+            cx.update(|cx| api_key_status(&brave, cx)),
+            Some(ApiKeyStatus::Missing)
+        );
+
+        cx.update(|cx| store_api_key(&brave, format!(" {VALID_API_KEY} "), cx))
+            .await
+            .unwrap();
+        assert_eq!(
+            cx.update(|cx| api_key_status(&brave, cx)),
+            Some(ApiKeyStatus::Stored)
+        );
+        assert!(is_registered(&registry, &brave, cx));
+
+        cx.update(|cx| delete_api_key(&brave, cx)).await.unwrap();
+        assert_eq!(
+            cx.update(|cx| api_key_status(&brave, cx)),
+            Some(ApiKeyStatus::Missing)
+        );
+        assert!(!is_registered(&registry, &brave, cx));
+
+        let duckduckgo = WebSearchProviderId(duckduckgo::DUCKDUCKGO_WEB_SEARCH_PROVIDER_ID.into());
+        assert_eq!(cx.update(|cx| api_key_status(&duckduckgo, cx)), None);
+        assert!(
+            cx.update(|cx| validate_api_key(&duckduckgo, VALID_API_KEY, cx))
+                .await
+                .is_err()
+        );
     }
 }